@@ -0,0 +1,196 @@
+//! Shapes of the camera's aperture, which out of focus highlights (bokeh) take
+//! on.
+
+use std::{f32::consts::PI, sync::Arc};
+
+use crate::{film::Film, math::vec3::Vec3, utils};
+
+#[derive(Debug, Clone, Default)]
+pub enum ApertureShape {
+    #[default]
+    Circle,
+    /// Regular polygon formed by this many blades, with its corners on the
+    /// circle.
+    Polygon(u32),
+    /// Any shape, with the lens letting light through in proportion to the
+    /// luminance of the image.
+    Image(Arc<ApertureImage>),
+}
+
+/// The shape of the lens, scaled to the unit disk.
+#[derive(Debug, Clone, Default)]
+pub struct Aperture {
+    pub shape: ApertureShape,
+    /// Counterclockwise rotation of the shape.
+    pub rotation_deg: f32,
+}
+
+impl Aperture {
+    /// Maps a uniform sample to a point on the aperture in the unit disk.
+    pub fn sample(&self, u: [f32; 2]) -> Vec3 {
+        let p = match &self.shape {
+            ApertureShape::Circle => return utils::sample_unit_disk(u),
+            ApertureShape::Polygon(blades) => sample_polygon(*blades, u),
+            ApertureShape::Image(image) => image.sample(u),
+        };
+        let (sin, cos) = self.rotation_deg.to_radians().sin_cos();
+        Vec3::new(p.x * cos - p.y * sin, p.x * sin + p.y * cos, 0.0)
+    }
+}
+
+/// Picks one of the triangles between the centre and the edges with the first
+/// number, then a point in it.
+fn sample_polygon(blades: u32, u: [f32; 2]) -> Vec3 {
+    let n = blades.max(3);
+    let scaled = u[0] * n as f32;
+    let edge = (scaled as u32).min(n - 1);
+    let corner = |i: u32| {
+        let angle = PI / 2.0 + 2.0 * PI * i as f32 / n as f32;
+        Vec3::new(angle.cos(), angle.sin(), 0.0)
+    };
+    let along = corner(edge) * (1.0 - u[1]) + corner(edge + 1) * u[1];
+    along * (scaled - edge as f32).sqrt()
+}
+
+/// An image as a distribution over the unit square, for drawing points with a
+/// density proportional to its luminance.
+#[derive(Debug)]
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    /// Cumulative luminance along each row, normalized to end at 1.
+    row_cdfs: Vec<f32>,
+    /// Cumulative luminance of the rows, normalized to end at 1.
+    column_cdf: Vec<f32>,
+}
+
+impl ApertureImage {
+    /// `None` if the image is black.
+    pub fn new(image: &Film) -> Option<ApertureImage> {
+        let width = image.width as usize;
+        let height = image.height as usize;
+        let mut row_cdfs = Vec::with_capacity((width + 1) * height);
+        let mut column_cdf = Vec::with_capacity(height + 1);
+        column_cdf.push(0.0);
+        for y in 0..height {
+            let start = row_cdfs.len();
+            let mut sum = 0.0;
+            row_cdfs.push(0.0);
+            for x in 0..width {
+                let c = image.pixels[y * width + x];
+                sum += (0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z).max(0.0);
+                row_cdfs.push(sum);
+            }
+            if sum > 0.0 {
+                row_cdfs[start..].iter_mut().for_each(|c| *c /= sum);
+            }
+            column_cdf.push(column_cdf[y] + sum);
+        }
+        let total = column_cdf[height];
+        if total <= 0.0 {
+            return None;
+        }
+        column_cdf.iter_mut().for_each(|c| *c /= total);
+        Some(ApertureImage {
+            width,
+            height,
+            row_cdfs,
+            column_cdf,
+        })
+    }
+
+    /// Point in the square around the unit disk, with the image's longer side
+    /// across it and y up.
+    fn sample(&self, u: [f32; 2]) -> Vec3 {
+        let y = sample_cdf(&self.column_cdf, u[1]);
+        let row = (y as usize).min(self.height - 1);
+        let x = sample_cdf(
+            &self.row_cdfs[row * (self.width + 1)..(row + 1) * (self.width + 1)],
+            u[0],
+        );
+        let scale = 2.0 / self.width.max(self.height) as f32;
+        Vec3::new(
+            (x - self.width as f32 / 2.0) * scale,
+            (self.height as f32 / 2.0 - y) * scale,
+            0.0,
+        )
+    }
+}
+
+/// Inverts a piecewise linear cumulative distribution over `cdf.len() - 1`
+/// cells, returning a position in `[0, cdf.len() - 1]`.
+fn sample_cdf(cdf: &[f32], u: f32) -> f32 {
+    // Last cell starting at or below u that has any weight
+    let cell = cdf.partition_point(|&c| c <= u).clamp(1, cdf.len() - 1) - 1;
+    let width = cdf[cell + 1] - cdf[cell];
+    let t = if width > 0.0 {
+        ((u - cdf[cell]) / width).clamp(0.0, 1.0)
+    } else {
+        0.5
+    };
+    cell as f32 + t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> impl Iterator<Item = [f32; 2]> {
+        (0..64)
+            .flat_map(|i| (0..64).map(move |j| [(i as f32 + 0.5) / 64.0, (j as f32 + 0.5) / 64.0]))
+    }
+
+    #[test]
+    fn polygons_stay_inside_their_edges() {
+        for blades in [3, 5, 6, 9] {
+            let aperture = Aperture {
+                shape: ApertureShape::Polygon(blades),
+                rotation_deg: 10.0,
+            };
+            // Distance of the polygon's edges from its centre
+            let apothem = (PI / blades as f32).cos();
+            let mut furthest: f32 = 0.0;
+            for u in grid() {
+                let p = aperture.sample(u);
+                for edge in 0..blades {
+                    let angle = (10.0f32 + 90.0).to_radians()
+                        + (2.0 * edge as f32 + 1.0) * PI / blades as f32;
+                    let distance = p.x * angle.cos() + p.y * angle.sin();
+                    assert!(distance <= apothem + 1e-5, "{} blades", blades);
+                }
+                furthest = furthest.max(p.length());
+            }
+            assert!(furthest > 0.95, "{} blades", blades);
+        }
+    }
+
+    #[test]
+    fn images_are_sampled_by_luminance() {
+        // A wide image, black on the left, twice as bright at the bottom right
+        // as at the top right
+        let mut image = Film::new(4, 2);
+        image.write_pixel(2, 0, Vec3::ONE);
+        image.write_pixel(3, 0, Vec3::ONE);
+        image.write_pixel(2, 1, Vec3::ONE * 2.0);
+        image.write_pixel(3, 1, Vec3::ONE * 2.0);
+        let aperture = Aperture {
+            shape: ApertureShape::Image(Arc::new(ApertureImage::new(&image).unwrap())),
+            rotation_deg: 0.0,
+        };
+
+        let (mut top, mut bottom) = (0, 0);
+        for u in grid() {
+            let p = aperture.sample(u);
+            assert!((0.0..=1.0).contains(&p.x), "{:?}", p);
+            assert!((-0.5..=0.5).contains(&p.y), "{:?}", p);
+            if p.y > 0.0 {
+                top += 1;
+            } else {
+                bottom += 1;
+            }
+        }
+        assert!((bottom as f32 / top as f32 - 2.0).abs() < 0.1);
+
+        assert!(ApertureImage::new(&Film::new(3, 3)).is_none());
+    }
+}
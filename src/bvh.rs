@@ -0,0 +1,507 @@
+//! Bounding volume hierarchies over the objects of a scene or the faces of a mesh.
+
+use std::sync::Arc;
+
+use crate::{
+    hittables::{HitInfo, Hittable, HittableList},
+    math::{aabb::AABB, interval::Interval, ray::Ray, vec3::Vec3},
+};
+
+/// How a node's objects are divided between its two children.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitMethod {
+    /// Halves the objects sorted along the longest axis. Quick to build, but
+    /// makes poor trees when object sizes vary a lot.
+    Median,
+    /// Binned surface area heuristic.
+    Sah(SahSettings),
+}
+
+impl SplitMethod {
+    pub const NAMES: [&'static str; 2] = ["median", "sah"];
+
+    pub fn from_name(name: &str) -> Option<SplitMethod> {
+        match name {
+            "median" => Some(SplitMethod::Median),
+            "sah" => Some(SplitMethod::Sah(SahSettings::default())),
+            _ => None,
+        }
+    }
+}
+
+impl Default for SplitMethod {
+    fn default() -> Self {
+        SplitMethod::Sah(SahSettings::default())
+    }
+}
+
+/// Parameters of the surface area heuristic. The expected cost of a split is
+/// `traversal_cost + intersection_cost * (A_l * N_l + A_r * N_r) / A`, where the
+/// `A` are surface areas and the `N` object counts of the children.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SahSettings {
+    /// Candidate split planes per axis are placed between this many bins.
+    pub bins: usize,
+    /// Nodes with more objects than this are always split.
+    pub max_leaf_size: usize,
+    /// Cost of visiting a node, relative to `intersection_cost`.
+    pub traversal_cost: f32,
+    /// Cost of intersecting a single object.
+    pub intersection_cost: f32,
+}
+
+impl Default for SahSettings {
+    fn default() -> Self {
+        SahSettings {
+            bins: 16,
+            max_leaf_size: 4,
+            traversal_cost: 1.0,
+            intersection_cost: 1.0,
+        }
+    }
+}
+
+/// Shape of a built hierarchy.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BvhStats {
+    pub interior_nodes: usize,
+    pub leaves: usize,
+    pub max_depth: usize,
+    /// Expected cost of tracing a ray that hits the root box, summed over the
+    /// nodes with the default `SahSettings` costs. Lower is better.
+    pub sah_cost: f32,
+}
+
+/// What a [`BVH`] can be built over.
+pub trait Primitive {
+    fn bounding_box(&self) -> &AABB;
+}
+
+impl Primitive for Arc<dyn Hittable> {
+    fn bounding_box(&self) -> &AABB {
+        (**self).bounding_box()
+    }
+}
+
+/// Deepest tree the traversal stack can hold. The builder makes leaves at
+/// this depth however many objects are left.
+const MAX_DEPTH: usize = 64;
+
+/// A node in the depth-first node array. An interior node's first child
+/// directly follows it.
+struct LinearNode {
+    bounding_box: AABB,
+    /// First object of a leaf, or index of an interior node's second child.
+    offset: u32,
+    /// Number of objects in a leaf, zero for interior nodes.
+    count: u32,
+    /// Axis that interior nodes were split along.
+    axis: u8,
+}
+
+/// Bounding volume hierarchy flattened into an array of nodes over the
+/// objects, which are reordered so that every leaf owns a contiguous range.
+#[allow(clippy::upper_case_acronyms)]
+pub struct BVH<T = Arc<dyn Hittable>> {
+    nodes: Vec<LinearNode>,
+    objects: Vec<T>,
+}
+
+impl<T: Primitive + Clone> BVH<T> {
+    pub fn new(objects: &[T]) -> BVH<T> {
+        BVH::build(objects, &SplitMethod::default()).0
+    }
+
+    pub fn build(objects: &[T], method: &SplitMethod) -> (BVH<T>, BvhStats) {
+        let mut objects = objects.to_vec();
+        let mut builder = Builder {
+            method,
+            root_area: bounds(&objects).surface_area(),
+            nodes: Vec::with_capacity(2 * objects.len()),
+            stats: BvhStats::default(),
+        };
+        if !objects.is_empty() {
+            builder.node(&mut objects, 0, 0);
+        }
+
+        let bvh = BVH {
+            nodes: builder.nodes,
+            objects,
+        };
+        (bvh, builder.stats)
+    }
+
+    /// Box around all of the objects.
+    pub fn bounds(&self) -> &AABB {
+        match self.nodes.first() {
+            Some(root) => &root.bounding_box,
+            None => &AABB::EMPTY,
+        }
+    }
+
+    /// The closest of the hits that `hit_object` finds along `ray`, given an
+    /// object and the range a hit must be in to be closer than those found so
+    /// far. Hits are returned with their `t`.
+    pub fn closest_hit<H>(
+        &self,
+        ray: &Ray,
+        t_range: &Interval,
+        mut hit_object: impl FnMut(&T, &Interval) -> Option<(f32, H)>,
+    ) -> Option<H> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse_direction = Vec3::new(
+            1.0 / ray.direction.x,
+            1.0 / ray.direction.y,
+            1.0 / ray.direction.z,
+        );
+        let direction_is_negative = [
+            inverse_direction.x < 0.0,
+            inverse_direction.y < 0.0,
+            inverse_direction.z < 0.0,
+        ];
+
+        let mut closest = None;
+        let mut closest_so_far = t_range.end;
+        let mut stack = [0u32; MAX_DEPTH];
+        let mut stack_size = 0;
+        let mut node_index = 0;
+        loop {
+            let node = &self.nodes[node_index];
+            let t_range = Interval::new(t_range.start, closest_so_far);
+            if node
+                .bounding_box
+                .hit_inverse(&ray.origin, &inverse_direction, t_range)
+            {
+                if node.count > 0 {
+                    let start = node.offset as usize;
+                    for object in &self.objects[start..start + node.count as usize] {
+                        let t_range = Interval::new(t_range.start, closest_so_far);
+                        if let Some((t, hit)) = hit_object(object, &t_range) {
+                            closest_so_far = t;
+                            closest = Some(hit);
+                        }
+                    }
+                } else {
+                    // Visit the nearer child first so the farther one can be
+                    // culled by a closer hit
+                    let (near, far) = if direction_is_negative[node.axis as usize] {
+                        (node.offset as usize, node_index + 1)
+                    } else {
+                        (node_index + 1, node.offset as usize)
+                    };
+                    stack[stack_size] = far as u32;
+                    stack_size += 1;
+                    node_index = near;
+                    continue;
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            node_index = stack[stack_size] as usize;
+        }
+        closest
+    }
+}
+
+impl BVH {
+    pub fn from_hittable_list(hittable_list: &HittableList) -> BVH {
+        BVH::new(hittable_list.objects())
+    }
+}
+
+impl Hittable for BVH {
+    fn hit(&self, ray: &Ray, t_range: &Interval) -> Option<HitInfo> {
+        self.closest_hit(ray, t_range, |object, t_range| {
+            object.hit(ray, t_range).map(|info| (info.t, info))
+        })
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.bounds()
+    }
+}
+
+fn bounds<T: Primitive>(objects: &[T]) -> AABB {
+    objects.iter().fold(AABB::EMPTY, |bounds, object| {
+        AABB::combine(&bounds, object.bounding_box())
+    })
+}
+
+struct Builder<'a> {
+    method: &'a SplitMethod,
+    root_area: f32,
+    nodes: Vec<LinearNode>,
+    stats: BvhStats,
+}
+
+impl Builder<'_> {
+    /// Area of `bounding_box` relative to the root, the chance that a ray
+    /// through the root also passes through it.
+    fn relative_area(&self, bounding_box: &AABB) -> f32 {
+        if self.root_area > 0.0 {
+            bounding_box.surface_area() / self.root_area
+        } else {
+            1.0
+        }
+    }
+
+    /// Appends the subtree over `objects`, which start at `offset` in the
+    /// final object array, and returns the index of its root.
+    fn node<T: Primitive>(&mut self, objects: &mut [T], offset: usize, depth: usize) -> usize {
+        let costs = SahSettings::default();
+        let bounding_box = bounds(objects);
+        let index = self.nodes.len();
+        let relative_area = self.relative_area(&bounding_box);
+
+        let split = if depth + 1 < MAX_DEPTH {
+            self.split(objects, &bounding_box)
+        } else {
+            None
+        };
+        match split {
+            Some((mid, axis)) => {
+                self.stats.interior_nodes += 1;
+                self.stats.sah_cost += costs.traversal_cost * relative_area;
+                self.nodes.push(LinearNode {
+                    bounding_box,
+                    offset: 0,
+                    count: 0,
+                    axis: axis as u8,
+                });
+
+                let (left, right) = objects.split_at_mut(mid);
+                self.node(left, offset, depth + 1);
+                let second = self.node(right, offset + mid, depth + 1);
+                self.nodes[index].offset = second as u32;
+            }
+            None => {
+                self.stats.leaves += 1;
+                self.stats.max_depth = self.stats.max_depth.max(depth);
+                self.stats.sah_cost +=
+                    costs.intersection_cost * objects.len() as f32 * relative_area;
+                self.nodes.push(LinearNode {
+                    bounding_box,
+                    offset: offset as u32,
+                    count: objects.len() as u32,
+                    axis: 0,
+                });
+            }
+        }
+        index
+    }
+
+    /// Reorders `objects` so that the first `mid` go to the first child and
+    /// returns `mid` with the split axis, or `None` to make a leaf.
+    fn split<T: Primitive>(
+        &self,
+        objects: &mut [T],
+        bounding_box: &AABB,
+    ) -> Option<(usize, usize)> {
+        if objects.len() <= 1 {
+            return None;
+        }
+        match self.method {
+            SplitMethod::Median => {
+                let axis = bounding_box.longest_axis() as usize;
+                objects.sort_by(|a, b| {
+                    let a_start = a.bounding_box()[axis].start;
+                    let b_start = b.bounding_box()[axis].start;
+                    a_start.total_cmp(&b_start)
+                });
+                Some((objects.len() / 2, axis))
+            }
+            SplitMethod::Sah(settings) => sah_split(objects, bounding_box, settings),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bounds: AABB,
+    count: usize,
+}
+
+fn sah_split<T: Primitive>(
+    objects: &mut [T],
+    bounding_box: &AABB,
+    settings: &SahSettings,
+) -> Option<(usize, usize)> {
+    let count = objects.len();
+    let mut min = Vec3::uniform(f32::INFINITY);
+    let mut max = Vec3::uniform(f32::NEG_INFINITY);
+    for object in objects.iter() {
+        let c = object.bounding_box().centroid();
+        min = Vec3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z));
+        max = Vec3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z));
+    }
+    let extent = max - min;
+
+    let bin_count = settings.bins.max(2);
+    let bin_index = |object: &T, axis: usize| {
+        let offset = (object.bounding_box().centroid()[axis] - min[axis]) / extent[axis];
+        ((offset * bin_count as f32) as usize).min(bin_count - 1)
+    };
+
+    // Cheapest split plane over all axes, as (cost, axis, bins on the left)
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        if extent[axis] <= 0.0 {
+            continue;
+        }
+
+        let empty = Bin {
+            bounds: AABB::EMPTY,
+            count: 0,
+        };
+        let mut bins = vec![empty; bin_count];
+        for object in objects.iter() {
+            let bin = &mut bins[bin_index(object, axis)];
+            bin.bounds = AABB::combine(&bin.bounds, object.bounding_box());
+            bin.count += 1;
+        }
+
+        // Sweep from the right to get the cost of everything past each plane
+        let mut right_costs = vec![0.0; bin_count];
+        let mut right = empty;
+        for split in (1..bin_count).rev() {
+            right.bounds = AABB::combine(&right.bounds, &bins[split].bounds);
+            right.count += bins[split].count;
+            right_costs[split] = right.bounds.surface_area() * right.count as f32;
+        }
+
+        let mut left = empty;
+        for split in 1..bin_count {
+            left.bounds = AABB::combine(&left.bounds, &bins[split - 1].bounds);
+            left.count += bins[split - 1].count;
+            if left.count == 0 || left.count == count {
+                continue;
+            }
+            let cost = settings.traversal_cost
+                + settings.intersection_cost
+                    * (left.bounds.surface_area() * left.count as f32 + right_costs[split])
+                    / bounding_box.surface_area();
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    let leaf_cost = settings.intersection_cost * count as f32;
+    let Some((cost, axis, split)) = best else {
+        // All centroids coincide, any split is as good as another
+        return (count > settings.max_leaf_size).then_some((count / 2, 0));
+    };
+    if count <= settings.max_leaf_size && leaf_cost <= cost {
+        return None;
+    }
+
+    let mut mid = 0;
+    for i in 0..count {
+        if bin_index(&objects[i], axis) < split {
+            objects.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some((mid, axis))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        hittables::Sphere,
+        materials::{DiffuseLight, Material},
+    };
+
+    /// A huge ground sphere under many small ones, like the final scene.
+    fn spheres() -> Vec<Arc<dyn Hittable>> {
+        let material: Arc<dyn Material> = Arc::new(DiffuseLight { color: Vec3::ONE });
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut objects: Vec<Arc<dyn Hittable>> = vec![Arc::new(Sphere::new(
+            Vec3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            material.clone(),
+        ))];
+        for _ in 0..500 {
+            let center = Vec3::new(rng.gen_range(-11.0..11.0), 0.2, rng.gen_range(-11.0..11.0));
+            objects.push(Arc::new(Sphere::new(center, 0.2, material.clone())));
+        }
+        objects
+    }
+
+    fn closest_hit(objects: &[Arc<dyn Hittable>], ray: &Ray) -> Option<f32> {
+        objects
+            .iter()
+            .filter_map(|object| object.hit(ray, &Interval::new(0.001, f32::INFINITY)))
+            .map(|hit| hit.t)
+            .min_by(f32::total_cmp)
+    }
+
+    #[test]
+    fn split_methods_find_the_closest_hit() {
+        let reference = spheres();
+        let mut rng = StdRng::seed_from_u64(2);
+        let methods = [
+            SplitMethod::Median,
+            SplitMethod::default(),
+            SplitMethod::Sah(SahSettings {
+                bins: 4,
+                max_leaf_size: 1,
+                ..Default::default()
+            }),
+        ];
+        for method in methods {
+            let (bvh, stats) = BVH::build(&spheres(), &method);
+            assert!(stats.leaves > 1 && stats.interior_nodes > 0);
+
+            for i in 0..1000 {
+                let origin = Vec3::new(rng.gen_range(-15.0..15.0), 3.0, 15.0);
+                let target = Vec3::new(rng.gen_range(-11.0..11.0), 0.0, rng.gen_range(-11.0..11.0));
+                // Also axis aligned rays, which have infinite inverse directions
+                let ray = if i % 2 == 0 {
+                    Ray::new(origin, target - origin)
+                } else {
+                    Ray::new(target + Vec3::UP * 3.0, Vec3::DOWN)
+                };
+                let t = bvh
+                    .hit(&ray, &Interval::new(0.001, f32::INFINITY))
+                    .map(|hit| hit.t);
+                assert_eq!(t, closest_hit(&reference, &ray), "{:?}", method);
+            }
+        }
+    }
+
+    #[test]
+    fn sah_beats_median_split_with_mixed_sizes() {
+        let (_, median) = BVH::build(&spheres(), &SplitMethod::Median);
+        let (_, sah) = BVH::build(&spheres(), &SplitMethod::default());
+        assert!(
+            sah.sah_cost < median.sah_cost * 0.8,
+            "{:?} {:?}",
+            sah,
+            median
+        );
+    }
+
+    #[test]
+    fn small_and_empty_scenes() {
+        let (bvh, stats) = BVH::build(&[], &SplitMethod::default());
+        let ray = Ray::new(Vec3::ZERO, Vec3::FORWARD);
+        assert!(bvh.hit(&ray, &Interval::UNIVERSE).is_none());
+        assert_eq!(stats.interior_nodes, 0);
+
+        let objects = spheres();
+        let (bvh, stats) = BVH::build(&objects[..1], &SplitMethod::default());
+        assert_eq!((stats.interior_nodes, stats.leaves), (0, 1));
+        assert!(bvh
+            .hit(&Ray::new(Vec3::ZERO, Vec3::DOWN), &Interval::UNIVERSE)
+            .is_some());
+    }
+}
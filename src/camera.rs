@@ -1,25 +1,257 @@
+use std::f32::consts::PI;
+
 use crate::{
+    aperture::Aperture,
     math::{ray::Ray, vec3::Vec3},
-    utils,
+    sampler::Sampler,
 };
 
+/// The sampled numbers that pick a camera ray.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraSample {
+    /// Position on the film relative to the pixel centre, in pixels.
+    pub film_offset: [f32; 2],
+    /// Uniform position on the lens.
+    pub lens: [f32; 2],
+    /// Uniform time within the shutter interval.
+    pub time: f32,
+}
+
+impl CameraSample {
+    pub fn new(sampler: &mut dyn Sampler) -> CameraSample {
+        let [u, v] = sampler.get_2d();
+        CameraSample {
+            film_offset: [u - 0.5, v - 0.5],
+            lens: sampler.get_2d(),
+            time: sampler.get_1d(),
+        }
+    }
+}
+
+/// How directions in the scene map to positions on the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Pinhole or thin lens camera with a vertical field of view.
+    Perspective { vfov_deg: f32 },
+    /// Parallel rays along the view direction, covering `width` scene units
+    /// across the image. Used for elevations and plans.
+    Orthographic { width: f32 },
+    /// A circular fisheye image filling the shorter side of the image, with a
+    /// field of view of up to 360 degrees across the circle. Pixels outside the
+    /// circle are black.
+    Fisheye {
+        mapping: FisheyeMapping,
+        fov_deg: f32,
+    },
+    /// Longitude across and latitude down the image, covering every direction
+    /// around the camera. A 2:1 image has square pixels.
+    Equirectangular,
+}
+
+impl Projection {
+    pub const NAMES: [&'static str; 4] =
+        ["perspective", "orthographic", "fisheye", "equirectangular"];
+
+    /// The projection called `name` with default parameters.
+    pub fn from_name(name: &str) -> Option<Projection> {
+        match name {
+            "perspective" => Some(Projection::Perspective { vfov_deg: 90.0 }),
+            "orthographic" => Some(Projection::Orthographic { width: 2.0 }),
+            "fisheye" => Some(Projection::Fisheye {
+                mapping: FisheyeMapping::default(),
+                fov_deg: 180.0,
+            }),
+            "equirectangular" => Some(Projection::Equirectangular),
+            _ => None,
+        }
+    }
+}
+
+/// Relation between the angle of a direction from the view direction and its
+/// distance from the centre of a fisheye image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FisheyeMapping {
+    /// Distance proportional to the angle.
+    #[default]
+    Equidistant,
+    /// Equal solid angles cover equal areas of the image, so the edge of the
+    /// circle is less stretched.
+    Equisolid,
+}
+
+impl FisheyeMapping {
+    pub const NAMES: [&'static str; 2] = ["equidistant", "equisolid"];
+
+    pub fn from_name(name: &str) -> Option<FisheyeMapping> {
+        match name {
+            "equidistant" => Some(FisheyeMapping::Equidistant),
+            "equisolid" => Some(FisheyeMapping::Equisolid),
+            _ => None,
+        }
+    }
+}
+
+/// A camera described the way a photographer would, from which the field of
+/// view, the size of the aperture and the exposure follow.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicalCamera {
+    /// Focal length of the lens in millimetres.
+    pub focal_length: f32,
+    /// Width of the sensor in millimetres, its height follows from the image's
+    /// aspect ratio. 36 is a full frame sensor.
+    pub sensor_width: f32,
+    pub f_number: f32,
+    /// Exposure time in seconds.
+    pub shutter_speed: f32,
+    pub iso: f32,
+    /// Scene units in a metre, for the size of the aperture.
+    pub units_per_meter: f32,
+}
+
+impl PhysicalCamera {
+    /// A full frame camera at a shutter speed of 1 second and ISO 100.
+    pub fn new(focal_length: f32, f_number: f32) -> PhysicalCamera {
+        PhysicalCamera {
+            focal_length,
+            sensor_width: 36.0,
+            f_number,
+            shutter_speed: 1.0,
+            iso: 100.0,
+            units_per_meter: 1.0,
+        }
+    }
+
+    pub fn vfov_deg(&self, aspect_ratio: f32) -> f32 {
+        let sensor_height = self.sensor_width / aspect_ratio;
+        2.0 * (sensor_height / (2.0 * self.focal_length))
+            .atan()
+            .to_degrees()
+    }
+
+    /// Radius of the aperture in scene units.
+    pub fn aperture_radius(&self) -> f32 {
+        self.focal_length / (2.0 * self.f_number) / 1000.0 * self.units_per_meter
+    }
+
+    /// Angle the aperture covers seen from the plane of focus, the
+    /// `defocus_angle` of a [`Camera`].
+    pub fn defocus_angle(&self, focus_dist: f32) -> f32 {
+        2.0 * (self.aperture_radius() / focus_dist).atan().to_degrees()
+    }
+
+    /// Exposure value at ISO 100, 0 for f/1 and 1 second.
+    pub fn exposure_value(&self) -> f32 {
+        (self.f_number * self.f_number / self.shutter_speed).log2() - (self.iso / 100.0).log2()
+    }
+
+    /// Factor applied to the radiance reaching the camera. A radiance of 1
+    /// comes out as 1 at an exposure value of 0 and halves with every step up.
+    pub fn exposure(&self) -> f32 {
+        2f32.powf(-self.exposure_value())
+    }
+}
+
+/// Resolution independent camera parameters, as read from a scene file.
+#[derive(Debug, Clone)]
+pub struct CameraSettings {
+    pub position: Vec3,
+    pub projection: Projection,
+    pub lookat: Vec3,
+    pub up: Vec3,
+    pub defocus_angle: f32,
+    pub focus_dist: f32,
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+    /// Overrides the field of view of a perspective projection, the defocus
+    /// angle and the exposure.
+    pub physical: Option<PhysicalCamera>,
+    pub aperture: Aperture,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            position: Vec3::ZERO,
+            projection: Projection::Perspective { vfov_deg: 90.0 },
+            lookat: Vec3::FORWARD,
+            up: Vec3::UP,
+            defocus_angle: 0.0,
+            focus_dist: 1.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            physical: None,
+            aperture: Aperture::default(),
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn build(&self, width: u32, height: u32) -> Camera {
+        let mut projection = self.projection;
+        let mut defocus_angle = self.defocus_angle;
+        let mut exposure = 1.0;
+        if let Some(physical) = &self.physical {
+            if let Projection::Perspective { vfov_deg } = &mut projection {
+                *vfov_deg = physical.vfov_deg(width as f32 / height as f32);
+            }
+            defocus_angle = physical.defocus_angle(self.focus_dist);
+            exposure = physical.exposure();
+        }
+        Camera::new(
+            width,
+            height,
+            self.position,
+            projection,
+            self.lookat,
+            self.up,
+            defocus_angle,
+            self.focus_dist,
+        )
+        .with_shutter(self.shutter_open, self.shutter_close)
+        .with_aperture(self.aperture.clone())
+        .with_exposure(exposure)
+    }
+}
+
 pub struct Camera {
+    projection: Projection,
     position: Vec3,
+    width: f32,
+    height: f32,
+
+    /// Right, up and backwards, the camera looks along `-w`.
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+
+    /// Pixel grid on the plane of focus, for the perspective and orthographic
+    /// projections.
     pixel00_loc: Vec3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
+    focus_dist: f32,
 
+    /// Lens offsets for defocus blur, which only the perspective and
+    /// orthographic projections have.
     defocus_angle: f32,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
+    aperture: Aperture,
+    exposure: f32,
+
+    /// Rays are sent at random times in this interval. Moving objects are
+    /// defined from time 0 to 1.
+    shutter_open: f32,
+    shutter_close: f32,
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         width: u32,
         height: u32,
         position: Vec3,
-        vfov_deg: f32,
+        projection: Projection,
         lookat: Vec3,
         up: Vec3,
         defocus_angle: f32,
@@ -27,14 +259,19 @@ impl Camera {
     ) -> Camera {
         let aspect_ratio = width as f32 / height as f32;
 
-        let h = (vfov_deg.to_radians() / 2.0).tan();
-        let viewport_height = 2.0 * h * focus_dist;
-        let viewport_width = viewport_height * aspect_ratio;
-
         let w = (position - lookat).normalized();
         let u = Vec3::cross(&up, &w).normalized();
         let v = Vec3::cross(&w, &u);
 
+        let viewport_height = match projection {
+            Projection::Perspective { vfov_deg } => {
+                2.0 * (vfov_deg.to_radians() / 2.0).tan() * focus_dist
+            }
+            Projection::Orthographic { width } => width / aspect_ratio,
+            Projection::Fisheye { .. } | Projection::Equirectangular => 0.0,
+        };
+        let viewport_width = viewport_height * aspect_ratio;
+
         let viewport_u = viewport_width * u;
         let viewport_v = -viewport_height * v;
         let viewport_upper_left = position - focus_dist * w - viewport_u / 2.0 - viewport_v / 2.0;
@@ -48,30 +285,233 @@ impl Camera {
         let defocus_disk_v = v * defocus_radius;
 
         Camera {
+            projection,
             position,
+            width: width as f32,
+            height: height as f32,
+            u,
+            v,
+            w,
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
+            focus_dist,
             defocus_angle,
             defocus_disk_u,
             defocus_disk_v,
+            aperture: Aperture::default(),
+            exposure: 1.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
         }
     }
 
-    pub fn get_ray(&self, x: u32, y: u32) -> Ray {
-        let offset = utils::sample_unit_square();
-        let pixel_sample = self.pixel00_loc
-            + self.pixel_delta_u * (x as f32 + offset.x)
-            + self.pixel_delta_v * (y as f32 + offset.y);
+    pub fn with_shutter(mut self, open: f32, close: f32) -> Camera {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
 
-        let ray_origin = if self.defocus_angle <= 0.0 {
-            self.position
-        } else {
-            let p = utils::random_in_unit_disk();
-            self.position + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
+    /// Shape of the lens for defocus blur, a circle by default.
+    pub fn with_aperture(mut self, aperture: Aperture) -> Camera {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn with_exposure(mut self, exposure: f32) -> Camera {
+        self.exposure = exposure;
+        self
+    }
+
+    /// Factor to scale the radiance along the camera's rays by.
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// The ray through pixel `(x, y)` picked by `sample`, or `None` where the
+    /// image has no view of the scene, outside a fisheye's image circle.
+    pub fn get_ray(&self, x: u32, y: u32, sample: &CameraSample) -> Option<Ray> {
+        let [dx, dy] = sample.film_offset;
+        let time = self.shutter_open + (self.shutter_close - self.shutter_open) * sample.time;
+
+        // Position on the image with the origin at its centre, x right and y up
+        let film_x = x as f32 + 0.5 + dx - self.width / 2.0;
+        let film_y = self.height / 2.0 - (y as f32 + 0.5 + dy);
+
+        let (origin, direction) = match self.projection {
+            Projection::Perspective { .. } | Projection::Orthographic { .. } => {
+                let focus_point = self.pixel00_loc
+                    + self.pixel_delta_u * (x as f32 + dx)
+                    + self.pixel_delta_v * (y as f32 + dy);
+                let pinhole = match self.projection {
+                    Projection::Orthographic { .. } => focus_point + self.focus_dist * self.w,
+                    _ => self.position,
+                };
+                let ray_origin = if self.defocus_angle <= 0.0 {
+                    pinhole
+                } else {
+                    let p = self.aperture.sample(sample.lens);
+                    pinhole + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
+                };
+                (ray_origin, focus_point - ray_origin)
+            }
+            Projection::Fisheye { mapping, fov_deg } => {
+                // Distance from the centre relative to the radius of the circle
+                let r = (film_x * film_x + film_y * film_y).sqrt()
+                    / (self.width.min(self.height) / 2.0);
+                if r > 1.0 {
+                    return None;
+                }
+                let max_theta = fov_deg.to_radians() / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * max_theta,
+                    FisheyeMapping::Equisolid => {
+                        2.0 * (r * (max_theta / 2.0).sin()).clamp(-1.0, 1.0).asin()
+                    }
+                };
+                let phi = film_y.atan2(film_x);
+                let (sin_theta, cos_theta) = theta.sin_cos();
+                let direction =
+                    sin_theta * (phi.cos() * self.u + phi.sin() * self.v) - cos_theta * self.w;
+                (self.position, direction)
+            }
+            Projection::Equirectangular => {
+                let longitude = 2.0 * PI * film_x / self.width;
+                let latitude = PI * film_y / self.height;
+                let (sin_lon, cos_lon) = longitude.sin_cos();
+                let direction = latitude.cos() * (sin_lon * self.u - cos_lon * self.w)
+                    + latitude.sin() * self.v;
+                (self.position, direction)
+            }
         };
+        Some(Ray::with_time(origin, direction, time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+
+    fn ray(camera: &Camera, x: u32, y: u32, film_offset: [f32; 2]) -> Option<Ray> {
+        let sample = CameraSample {
+            film_offset,
+            lens: [0.5, 0.5],
+            time: 0.0,
+        };
+        camera.get_ray(x, y, &sample)
+    }
+
+    fn angle_from_forward(ray: &Ray) -> f32 {
+        Vec3::dot(&ray.direction.normalized(), &Vec3::FORWARD)
+            .clamp(-1.0, 1.0)
+            .acos()
+            .to_degrees()
+    }
+
+    fn camera(width: u32, height: u32, projection: Projection) -> Camera {
+        let lookat = Vec3::FORWARD;
+        Camera::new(
+            width,
+            height,
+            Vec3::ZERO,
+            projection,
+            lookat,
+            Vec3::UP,
+            0.0,
+            1.0,
+        )
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let camera = camera(200, 100, Projection::Orthographic { width: 8.0 });
+        let left = ray(&camera, 0, 50, [-0.5, -0.5]).unwrap();
+        let right = ray(&camera, 199, 50, [0.5, -0.5]).unwrap();
+        let top = ray(&camera, 100, 0, [-0.5, -0.5]).unwrap();
+        for ray in [&left, &right, &top] {
+            assert_approx_eq!(angle_from_forward(ray), 0.0, 1e-2);
+        }
+        assert_approx_eq!(right.origin.x - left.origin.x, 8.0, 1e-4);
+        assert_approx_eq!(top.origin.y, 2.0, 1e-4);
+    }
+
+    #[test]
+    fn fisheye_covers_its_field_of_view() {
+        for mapping in FisheyeMapping::NAMES.map(|name| FisheyeMapping::from_name(name).unwrap()) {
+            for fov_deg in [120.0, 180.0, 360.0] {
+                let camera = camera(300, 200, Projection::Fisheye { mapping, fov_deg });
+                let centre = ray(&camera, 150, 100, [-0.5, -0.5]).unwrap();
+                assert_approx_eq!(angle_from_forward(&centre), 0.0, 1e-2);
+                // The circle's top edge and the corners
+                let edge = ray(&camera, 150, 0, [-0.5, -0.5]).unwrap();
+                assert_approx_eq!(angle_from_forward(&edge), fov_deg / 2.0, 0.5);
+                assert!(fov_deg == 360.0 || edge.direction.y > 0.0);
+                assert!(ray(&camera, 0, 0, [0.0, 0.0]).is_none());
+            }
+        }
+
+        // Halfway out, equisolid shows less of the edge than equidistant
+        let halfway = |mapping| {
+            let camera = camera(
+                200,
+                200,
+                Projection::Fisheye {
+                    mapping,
+                    fov_deg: 180.0,
+                },
+            );
+            angle_from_forward(&ray(&camera, 150, 100, [-0.5, -0.5]).unwrap())
+        };
+        assert_approx_eq!(halfway(FisheyeMapping::Equidistant), 45.0, 1e-2);
+        assert_approx_eq!(halfway(FisheyeMapping::Equisolid), 41.41, 1e-2);
+    }
+
+    #[test]
+    fn physical_camera() {
+        let mut physical = PhysicalCamera::new(50.0, 2.0);
+        assert_approx_eq!(physical.vfov_deg(1.0), 39.6, 0.05);
+        assert_approx_eq!(physical.vfov_deg(1.5), 27.0, 0.05);
+        assert_approx_eq!(physical.aperture_radius(), 0.0125);
+        assert_approx_eq!(physical.defocus_angle(0.0125), 90.0, 1e-3);
+        assert_approx_eq!(physical.exposure_value(), 2.0);
+        assert_approx_eq!(physical.exposure(), 0.25);
+
+        // Sunny 16
+        physical.f_number = 16.0;
+        physical.shutter_speed = 1.0 / 125.0;
+        assert_approx_eq!(physical.exposure_value(), 15.0, 0.05);
+        physical.iso = 400.0;
+        assert_approx_eq!(physical.exposure_value(), 13.0, 0.05);
+
+        let settings = CameraSettings {
+            physical: Some(PhysicalCamera::new(50.0, 2.0)),
+            focus_dist: 3.0,
+            ..CameraSettings::default()
+        };
+        let camera = settings.build(300, 200);
+        assert_approx_eq!(camera.exposure(), 0.25);
+        assert_approx_eq!(
+            camera.defocus_disk_u.length(),
+            PhysicalCamera::new(50.0, 2.0).aperture_radius()
+        );
+        let top = ray(&camera, 150, 0, [-0.5, -0.5]).unwrap();
+        assert_approx_eq!(angle_from_forward(&top), 13.5, 0.05);
+    }
+
+    #[test]
+    fn equirectangular_covers_every_direction() {
+        let camera = camera(400, 200, Projection::Equirectangular);
+        let direction = |x, y, offset| ray(&camera, x, y, offset).unwrap().direction.normalized();
 
-        let ray_dir = pixel_sample - ray_origin;
-        Ray::new(ray_origin, ray_dir)
+        let centre = direction(200, 100, [-0.5, -0.5]);
+        assert_approx_eq!(Vec3::dot(&centre, &Vec3::FORWARD), 1.0, 1e-5);
+        let right = direction(300, 100, [-0.5, -0.5]);
+        assert_approx_eq!(right.x, 1.0, 1e-5);
+        let behind = direction(0, 100, [-0.5, -0.5]);
+        assert_approx_eq!(Vec3::dot(&behind, &Vec3::FORWARD), -1.0, 1e-5);
+        let up = direction(200, 0, [0.0, -0.5]);
+        assert_approx_eq!(up.y, 1.0, 1e-5);
     }
 }
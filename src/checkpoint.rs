@@ -0,0 +1,218 @@
+//! Checkpoint files that let an interrupted or finished render continue with
+//! more samples.
+//!
+//! A checkpoint is the `RTCKPT03` magic followed by the width and height as
+//! little endian `u32`s, the scene seed and the scene hash as `u64`s, the
+//! filter as a `u8` index into `FilterKind::NAMES` and an `f32` radius, and the
+//! sampler as a `u8` index into `SamplerKind::NAMES`. Then for every pixel in
+//! row order come the filtered radiance sum as three `f32`s, the sum of the
+//! filter weights as an `f32` and the sample count as a `u32`.
+
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use crate::{
+    film::Accumulator,
+    filter::{Filter, FilterKind},
+    math::vec3::Vec3,
+    sampler::SamplerKind,
+    utils::splitmix64,
+};
+
+const MAGIC: &[u8; 8] = b"RTCKPT03";
+const HEADER_SIZE: usize = MAGIC.len() + 4 + 4 + 8 + 8 + 1 + 4 + 1;
+const PIXEL_SIZE: usize = 5 * 4;
+
+/// A saved render: the samples taken so far and the seed the scene was built
+/// with, so built-in scenes with random placement come out the same.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub accumulator: Accumulator,
+    pub seed: u64,
+    /// [`scene_hash`] of the scene that was rendered.
+    pub scene_hash: u64,
+    /// The filter that weighted the sums and the sampler that took the
+    /// samples, which the samples added on resuming have to share.
+    pub filter: Filter,
+    pub sampler: SamplerKind,
+}
+
+/// Hash of the source of a scene, the text of a scene file or the name of a
+/// built-in scene, to tell whether a checkpoint was rendered from it.
+pub fn scene_hash(source: &[u8]) -> u64 {
+    source
+        .chunks(8)
+        .fold(splitmix64(source.len() as u64), |hash, chunk| {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            splitmix64(hash ^ u64::from_le_bytes(word))
+        })
+}
+
+/// Position of `name` in `names`, how kinds of filters and samplers are stored.
+fn name_index(names: &[&str], name: &str) -> u8 {
+    names.iter().position(|&n| n == name).unwrap() as u8
+}
+
+impl Checkpoint {
+    pub fn encode(&self) -> Vec<u8> {
+        let accumulator = &self.accumulator;
+        let mut data = Vec::with_capacity(HEADER_SIZE + accumulator.sums.len() * PIXEL_SIZE);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&accumulator.width.to_le_bytes());
+        data.extend_from_slice(&accumulator.height.to_le_bytes());
+        data.extend_from_slice(&self.seed.to_le_bytes());
+        data.extend_from_slice(&self.scene_hash.to_le_bytes());
+        data.push(name_index(&FilterKind::NAMES, self.filter.kind.name()));
+        data.extend_from_slice(&self.filter.radius.to_le_bytes());
+        data.push(name_index(&SamplerKind::NAMES, self.sampler.name()));
+        let pixels = accumulator.sums.iter().zip(&accumulator.weights);
+        for ((sum, weight), count) in pixels.zip(&accumulator.counts) {
+            for value in [sum.x, sum.y, sum.z, *weight] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(&count.to_le_bytes());
+        }
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Checkpoint, String> {
+        if data.len() < HEADER_SIZE || &data[..MAGIC.len() - 2] != b"RTCKPT" {
+            return Err(String::from("not a render checkpoint"));
+        }
+        if &data[..MAGIC.len()] != MAGIC {
+            return Err(String::from(
+                "checkpoint from an older version of the renderer",
+            ));
+        }
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let f32_at =
+            |offset: usize| f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+        let width = u32_at(8);
+        let height = u32_at(12);
+        let seed = u64_at(16);
+        let scene_hash = u64_at(24);
+        let filter_kind = FilterKind::NAMES
+            .get(data[32] as usize)
+            .and_then(|name| FilterKind::from_name(name));
+        let sampler = SamplerKind::NAMES
+            .get(data[37] as usize)
+            .and_then(|name| SamplerKind::from_name(name));
+        let (Some(filter_kind), Some(sampler)) = (filter_kind, sampler) else {
+            return Err(String::from("checkpoint has an unknown filter or sampler"));
+        };
+        let filter = Filter {
+            kind: filter_kind,
+            radius: f32_at(33),
+        };
+        let pixel_count = width as usize * height as usize;
+        if data.len() != HEADER_SIZE + pixel_count * PIXEL_SIZE {
+            return Err(format!(
+                "checkpoint is truncated or corrupt, expected {}x{} pixels",
+                width, height
+            ));
+        }
+
+        let mut accumulator = Accumulator::new(width, height);
+        for i in 0..pixel_count {
+            let offset = HEADER_SIZE + i * PIXEL_SIZE;
+            accumulator.sums[i] = Vec3::new(f32_at(offset), f32_at(offset + 4), f32_at(offset + 8));
+            accumulator.weights[i] = f32_at(offset + 12);
+            accumulator.counts[i] = u32_at(offset + 16);
+        }
+        Ok(Checkpoint {
+            accumulator,
+            seed,
+            scene_hash,
+            filter,
+            sampler,
+        })
+    }
+}
+
+/// Writes the checkpoint next to `path` and renames it into place, so a render
+/// killed while saving leaves the previous checkpoint intact.
+pub fn save(checkpoint: &Checkpoint, path: &Path) -> io::Result<()> {
+    let parent_dir = path.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(parent_dir)?;
+
+    let mut temp_name = path.as_os_str().to_owned();
+    temp_name.push(".tmp");
+    fs::write(&temp_name, checkpoint.encode())?;
+    fs::rename(&temp_name, path)
+}
+
+pub fn load(path: &Path) -> io::Result<Checkpoint> {
+    Checkpoint::decode(&fs::read(path)?)
+        .map_err(|message| io::Error::new(ErrorKind::InvalidData, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut accumulator = Accumulator::new(3, 2);
+        for i in 0..6 {
+            accumulator.sums[i] = Vec3::new(i as f32, 0.5, -1.0e-3);
+            accumulator.weights[i] = 0.25 * i as f32;
+            accumulator.counts[i] = 10 + i as u32;
+        }
+        let checkpoint = Checkpoint {
+            accumulator,
+            seed: u64::MAX - 7,
+            scene_hash: scene_hash(b"scenes/final.scene"),
+            filter: Filter {
+                kind: FilterKind::Mitchell,
+                radius: 1.75,
+            },
+            sampler: SamplerKind::BlueNoise,
+        };
+
+        assert_ne!(scene_hash(b"cornell"), scene_hash(b"cornell\0"));
+
+        let data = checkpoint.encode();
+        assert_eq!(data.len(), HEADER_SIZE + 6 * PIXEL_SIZE);
+        assert_eq!(Checkpoint::decode(&data), Ok(checkpoint.clone()));
+
+        let path =
+            std::env::temp_dir().join(format!("checkpoint-test-{}.ckpt", std::process::id()));
+        save(&checkpoint, &path).unwrap();
+        assert_eq!(load(&path).unwrap(), checkpoint);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        let data = Checkpoint {
+            accumulator: Accumulator::new(2, 2),
+            seed: 1,
+            scene_hash: 2,
+            filter: Filter::default(),
+            sampler: SamplerKind::default(),
+        }
+        .encode();
+        assert!(Checkpoint::decode(&data[..data.len() - 1]).is_err());
+        assert!(Checkpoint::decode(b"P3 2 2 255").is_err());
+        let mut old = data.clone();
+        old[7] = b'2';
+        assert_eq!(
+            Checkpoint::decode(&old),
+            Err(String::from(
+                "checkpoint from an older version of the renderer"
+            ))
+        );
+        assert!(Checkpoint::decode(&[]).is_err());
+        let mut unknown_filter = data.clone();
+        unknown_filter[32] = FilterKind::NAMES.len() as u8;
+        assert!(Checkpoint::decode(&unknown_filter).is_err());
+    }
+}
@@ -0,0 +1,447 @@
+use std::{fmt, path::PathBuf};
+
+use crate::{
+    bvh::SplitMethod,
+    filter::{Filter, FilterKind},
+    image::{ExrCompression, ImageFormat},
+    sampler::SamplerKind,
+    scenes,
+    tiles::TileOrder,
+    tonemap::ToneMapper,
+};
+
+pub const USAGE: &str = "\
+Usage: rust-raytracer [OPTIONS]
+
+Options:
+  --scene <NAME|FILE>   Built-in scene name or path to a scene file [default: final]
+  --width <PIXELS>      Image width
+  --height <PIXELS>     Image height
+  -s, --samples <N>     Samples per pixel
+  --max-depth <N>       Maximum number of bounces per path
+  -t, --threads <N>     Render threads [default: available cores]
+  -o, --output <PATH>   Output image path [default: ./out/test.png]
+  --format <FORMAT>     Output format, inferred from the extension by default
+                        [png, ppm, ppm-ascii, pfm, hdr, exr]
+  --exr-compression <C> Compression for OpenEXR output [default: zip] [none, zip]
+  --tonemap <OPERATOR>  Tone mapping for 8-bit output [default: clamp]
+                        [clamp, reinhard, reinhard-extended, aces, hable, agx]
+  --white-point <L>     White point for reinhard-extended, the only tone mapper
+                        that takes one
+  --exposure <EV>       Exposure adjustment in stops
+  --dither              Dither 8-bit output to hide banding
+  --seed <N>            Seed for the samples and for scenes with random placement
+                        [default: random]
+  --bvh <METHOD>        How the scene BVH is split [default: sah] [median, sah]
+  --sampler <SAMPLER>   Sample generator [default: sobol]
+                        [independent, stratified, halton, sobol, blue-noise]
+  --filter <FILTER>     Pixel reconstruction filter [default: box]
+                        [box, tent, gaussian, mitchell, lanczos]
+  --filter-radius <R>   Filter radius in pixels, at least 0.5
+                        [default: depends on the filter]
+  --pass-samples <N>    Samples per pixel in each progressive pass [default: 16]
+  --tile-size <PIXELS>  Size of the tiles the threads work on [default: 32]
+  --tile-order <ORDER>  Order the tiles are rendered in [default: spiral]
+                        [scanline, spiral, hilbert]
+  --checkpoint <PATH>   Where to save the checkpoint after each pass
+                        [default: the output path with a .ckpt extension]
+  --resume <PATH>       Continue the render saved in a checkpoint up to --samples
+  -h, --help            Print this help
+
+Settings given on the command line override the ones from the scene file. The
+image and the checkpoint are written after every pass, and the image also every
+few seconds as tiles finish. A checkpoint can only be resumed with the scene,
+seed, size, filter and sampler it was rendered with.
+";
+
+/// Every option that takes a value.
+const VALUE_OPTIONS: &[&str] = &[
+    "--scene",
+    "--width",
+    "--height",
+    "-s",
+    "--samples",
+    "--max-depth",
+    "-t",
+    "--threads",
+    "-o",
+    "--output",
+    "--format",
+    "--exr-compression",
+    "--tonemap",
+    "--white-point",
+    "--exposure",
+    "--seed",
+    "--bvh",
+    "--sampler",
+    "--filter",
+    "--filter-radius",
+    "--pass-samples",
+    "--tile-size",
+    "--tile-order",
+    "--checkpoint",
+    "--resume",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneSource {
+    Builtin(String),
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Args {
+    pub scene: SceneSource,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub threads: Option<u32>,
+    pub output: PathBuf,
+    pub format: ImageFormat,
+    pub tone_mapper: Option<ToneMapper>,
+    /// Only for the reinhard-extended tone mapper, from here or the scene.
+    pub white_point: Option<f32>,
+    pub exposure: Option<f32>,
+    pub dither: bool,
+    pub seed: Option<u64>,
+    pub bvh: Option<SplitMethod>,
+    pub sampler: Option<SamplerKind>,
+    pub filter: Option<FilterKind>,
+    pub filter_radius: Option<f32>,
+    pub pass_samples: Option<u32>,
+    pub tile_size: Option<u32>,
+    pub tile_order: Option<TileOrder>,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: Option<PathBuf>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            scene: SceneSource::Builtin(String::from("final")),
+            width: None,
+            height: None,
+            samples: None,
+            max_depth: None,
+            threads: None,
+            output: PathBuf::from("./out/test.png"),
+            format: ImageFormat::Png,
+            tone_mapper: None,
+            white_point: None,
+            exposure: None,
+            dither: false,
+            seed: None,
+            bvh: None,
+            sampler: None,
+            filter: None,
+            filter_radius: None,
+            pass_samples: None,
+            tile_size: None,
+            tile_order: None,
+            checkpoint: None,
+            resume: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Render(Box<Args>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CliError(String);
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, CliError> {
+    value
+        .parse()
+        .map_err(|_| CliError(format!("invalid value '{}' for {}", value, option)))
+}
+
+fn parse_positive(option: &str, value: &str) -> Result<u32, CliError> {
+    match parse_number(option, value)? {
+        0 => Err(CliError(format!("{} must be greater than zero", option))),
+        n => Ok(n),
+    }
+}
+
+/// Parses the arguments after the program name.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, CliError> {
+    let mut parsed = Args::default();
+    let mut format = None;
+    let mut exr_compression = ExrCompression::Zip;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // Accept both `--option value` and `--option=value`
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => {
+                (option.to_string(), Some(value.to_string()))
+            }
+            _ => (arg, None),
+        };
+
+        match option.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--dither" => {
+                parsed.dither = true;
+                continue;
+            }
+            _ => (),
+        }
+
+        if !VALUE_OPTIONS.contains(&option.as_str()) {
+            return Err(if option.starts_with('-') {
+                CliError(format!("unknown option '{}'", option))
+            } else {
+                CliError(format!("unexpected argument '{}'", option))
+            });
+        }
+        let value = inline_value
+            .or_else(|| args.next())
+            .ok_or_else(|| CliError(format!("{} expects a value", option)))?;
+
+        match option.as_str() {
+            "--scene" => {
+                parsed.scene = if scenes::builtin(&value).is_some() {
+                    SceneSource::Builtin(value)
+                } else {
+                    SceneSource::File(PathBuf::from(value))
+                };
+            }
+            "--width" => parsed.width = Some(parse_positive(&option, &value)?),
+            "--height" => parsed.height = Some(parse_positive(&option, &value)?),
+            "-s" | "--samples" => parsed.samples = Some(parse_positive(&option, &value)?),
+            "--max-depth" => parsed.max_depth = Some(parse_positive(&option, &value)?),
+            "-t" | "--threads" => parsed.threads = Some(parse_positive(&option, &value)?),
+            "-o" | "--output" => parsed.output = PathBuf::from(value),
+            "--format" => {
+                format = Some(
+                    ImageFormat::from_name(&value)
+                        .ok_or_else(|| CliError(format!("unknown output format '{}'", value)))?,
+                )
+            }
+            "--exr-compression" => {
+                exr_compression = match value.as_str() {
+                    "none" => ExrCompression::None,
+                    "zip" => ExrCompression::Zip,
+                    _ => return Err(CliError(format!("unknown EXR compression '{}'", value))),
+                }
+            }
+            "--tonemap" => {
+                parsed.tone_mapper = Some(
+                    ToneMapper::from_name(&value)
+                        .ok_or_else(|| CliError(format!("unknown tone mapper '{}'", value)))?,
+                )
+            }
+            "--white-point" => parsed.white_point = Some(parse_number(&option, &value)?),
+            "--exposure" => parsed.exposure = Some(parse_number(&option, &value)?),
+            "--seed" => parsed.seed = Some(parse_number(&option, &value)?),
+            "--bvh" => {
+                parsed.bvh = Some(
+                    SplitMethod::from_name(&value)
+                        .ok_or_else(|| CliError(format!("unknown BVH split method '{}'", value)))?,
+                )
+            }
+            "--sampler" => {
+                parsed.sampler = Some(
+                    SamplerKind::from_name(&value)
+                        .ok_or_else(|| CliError(format!("unknown sampler '{}'", value)))?,
+                )
+            }
+            "--filter" => {
+                parsed.filter = Some(
+                    FilterKind::from_name(&value)
+                        .ok_or_else(|| CliError(format!("unknown filter '{}'", value)))?,
+                )
+            }
+            "--filter-radius" => {
+                let radius: f32 = parse_number(&option, &value)?;
+                if !(radius.is_finite() && radius >= Filter::MIN_RADIUS) {
+                    return Err(CliError(format!(
+                        "{} must be at least {}",
+                        option,
+                        Filter::MIN_RADIUS
+                    )));
+                }
+                parsed.filter_radius = Some(radius);
+            }
+            "--pass-samples" => parsed.pass_samples = Some(parse_positive(&option, &value)?),
+            "--tile-size" => parsed.tile_size = Some(parse_positive(&option, &value)?),
+            "--tile-order" => {
+                parsed.tile_order = Some(
+                    TileOrder::from_name(&value)
+                        .ok_or_else(|| CliError(format!("unknown tile order '{}'", value)))?,
+                )
+            }
+            "--checkpoint" => parsed.checkpoint = Some(PathBuf::from(value)),
+            "--resume" => parsed.resume = Some(PathBuf::from(value)),
+            _ => unreachable!("option missing from VALUE_OPTIONS"),
+        }
+    }
+
+    if let (Some(tone_mapper), Some(white_point)) = (parsed.tone_mapper, parsed.white_point) {
+        if tone_mapper.with_white_point(white_point).is_none() {
+            return Err(CliError(String::from(
+                "--white-point only applies to --tonemap reinhard-extended",
+            )));
+        }
+    }
+
+    parsed.format = match format {
+        Some(format) => format,
+        None => ImageFormat::from_path(&parsed.output).ok_or_else(|| {
+            CliError(format!(
+                "can't infer the output format of '{}', use --format",
+                parsed.output.display()
+            ))
+        })?,
+    };
+
+    if let ImageFormat::Exr(compression) = &mut parsed.format {
+        *compression = exr_compression;
+    }
+
+    Ok(Command::Render(Box::new(parsed)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, CliError> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(parse(&[]), Ok(Command::Render(Box::default())));
+    }
+
+    #[test]
+    fn options_and_inline_values() {
+        let Ok(Command::Render(args)) = parse(&[
+            "--scene",
+            "cornell",
+            "--width=320",
+            "-s",
+            "16",
+            "--seed",
+            "42",
+            "-o",
+            "out/cornell.ppm",
+        ]) else {
+            panic!("expected render command");
+        };
+        assert_eq!(args.scene, SceneSource::Builtin(String::from("cornell")));
+        assert_eq!(args.width, Some(320));
+        assert_eq!(args.samples, Some(16));
+        assert_eq!(args.seed, Some(42));
+        assert_eq!(args.height, None);
+
+        let Ok(Command::Render(args)) =
+            parse(&["--dither", "--tonemap", "aces", "--exposure=-1.5"])
+        else {
+            panic!("expected render command");
+        };
+        assert!(args.dither);
+        assert_eq!(args.tone_mapper, Some(ToneMapper::Aces));
+        assert_eq!(args.exposure, Some(-1.5));
+
+        // The white point is kept apart from the tone mapper, in either order
+        for options in [
+            ["--tonemap", "reinhard-extended", "--white-point", "8"],
+            ["--white-point", "8", "--tonemap", "reinhard-extended"],
+        ] {
+            let Ok(Command::Render(args)) = parse(&options) else {
+                panic!("expected render command");
+            };
+            assert_eq!(
+                args.tone_mapper,
+                Some(ToneMapper::ExtendedReinhard { white_point: 4.0 })
+            );
+            assert_eq!(args.white_point, Some(8.0));
+        }
+        assert!(parse(&["--tonemap", "aces", "--white-point", "8"]).is_err());
+        assert!(parse(&["--white-point", "8", "--tonemap", "aces"]).is_err());
+
+        let Ok(Command::Render(args)) = parse(&["--bvh", "median"]) else {
+            panic!("expected render command");
+        };
+        assert_eq!(args.bvh, Some(SplitMethod::Median));
+
+        let Ok(Command::Render(args)) = parse(&["--tile-size", "16", "--tile-order=hilbert"])
+        else {
+            panic!("expected render command");
+        };
+        assert_eq!(args.tile_size, Some(16));
+        assert_eq!(args.tile_order, Some(TileOrder::Hilbert));
+
+        let Ok(Command::Render(args)) = parse(&["--sampler", "blue-noise"]) else {
+            panic!("expected render command");
+        };
+        assert_eq!(args.sampler, Some(SamplerKind::BlueNoise));
+
+        let Ok(Command::Render(args)) = parse(&["--filter", "mitchell", "--filter-radius=1.5"])
+        else {
+            panic!("expected render command");
+        };
+        assert_eq!(args.filter, Some(FilterKind::Mitchell));
+        assert_eq!(args.filter_radius, Some(1.5));
+
+        let Ok(Command::Render(args)) =
+            parse(&["--resume", "out/test.ckpt", "-s", "400", "--pass-samples=8"])
+        else {
+            panic!("expected render command");
+        };
+        assert_eq!(args.resume, Some(PathBuf::from("out/test.ckpt")));
+        assert_eq!(args.samples, Some(400));
+        assert_eq!(args.pass_samples, Some(8));
+        assert_eq!(args.checkpoint, None);
+    }
+
+    #[test]
+    fn scene_file_and_help() {
+        let Ok(Command::Render(args)) = parse(&["--scene", "scenes/cornell.scene"]) else {
+            panic!("expected render command");
+        };
+        assert_eq!(
+            args.scene,
+            SceneSource::File(PathBuf::from("scenes/cornell.scene"))
+        );
+        assert_eq!(parse(&["--samples", "4", "--help"]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert!(parse(&["--samples", "0"]).is_err());
+        assert!(parse(&["--width", "abc"]).is_err());
+        assert!(parse(&["--threads"]).is_err());
+        assert_eq!(
+            parse(&["--bogus", "1"]),
+            Err(CliError(String::from("unknown option '--bogus'")))
+        );
+        assert!(parse(&["-o", "image.xyz"]).is_err());
+        assert!(parse(&["--bvh", "octree"]).is_err());
+        assert!(parse(&["--pass-samples", "0"]).is_err());
+        assert!(parse(&["--tile-order", "random"]).is_err());
+        assert!(parse(&["--sampler", "sobel"]).is_err());
+        assert!(parse(&["--filter", "sinc"]).is_err());
+        assert!(parse(&["--filter-radius", "-1"]).is_err());
+        assert!(parse(&["--filter-radius", "0.3"]).is_err());
+        assert!(parse(&["--filter-radius", "NaN"]).is_err());
+        assert!(parse(&["--filter-radius", "inf"]).is_err());
+        assert!(parse(&["--filter-radius", "0.5"]).is_ok());
+        assert!(parse(&["--resume"]).is_err());
+    }
+}
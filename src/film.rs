@@ -0,0 +1,257 @@
+use crate::{
+    filter::Filter, math::vec3::Vec3, screen::Screen, tiles::Tile, tonemap::DisplayTransform,
+};
+
+/// Linear radiance for every pixel of the image.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Film {
+        Film {
+            width,
+            height,
+            pixels: vec![Vec3::ZERO; (width * height) as usize],
+        }
+    }
+
+    pub fn write_pixel(&mut self, x: u32, y: u32, color: Vec3) {
+        self.pixels[(y * self.width + x) as usize] = color
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Vec3 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Tone mapped and quantised 8-bit view of the film for display formats.
+    pub fn to_screen(&self, display: &DisplayTransform) -> Screen {
+        let mut screen = Screen::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                screen.write_pixel(x, y, display.quantize(self.pixel(x, y), x, y));
+            }
+        }
+        screen
+    }
+}
+
+/// Running sums of the filtered radiance samples around every pixel, their
+/// filter weights and how many samples were taken in each pixel, the state a
+/// progressive render builds up pass by pass.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accumulator {
+    pub width: u32,
+    pub height: u32,
+    pub sums: Vec<Vec3>,
+    pub weights: Vec<f32>,
+    pub counts: Vec<u32>,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Accumulator {
+        let len = (width * height) as usize;
+        Accumulator {
+            width,
+            height,
+            sums: vec![Vec3::ZERO; len],
+            weights: vec![0.0; len],
+            counts: vec![0; len],
+        }
+    }
+
+    /// Fewest samples taken by any pixel.
+    pub fn min_count(&self) -> u32 {
+        self.counts.iter().copied().min().unwrap_or(0)
+    }
+
+    /// Adds the samples a tile spread over its own pixels and returns the ones
+    /// that reached the pixels of its neighbours.
+    pub fn merge_tile(&mut self, splats: &TileSplats) -> Overlap {
+        let tile = &splats.tile;
+        let mut overlap = Overlap { splats: Vec::new() };
+        for y in 0..splats.height {
+            for x in 0..splats.width {
+                let i = (y * splats.width + x) as usize;
+                let (image_x, image_y) = (splats.x0 + x, splats.y0 + y);
+                let j = (image_y * self.width + image_x) as usize;
+                if (tile.x0..tile.x1).contains(&image_x) && (tile.y0..tile.y1).contains(&image_y) {
+                    self.sums[j] += splats.sums[i];
+                    self.weights[j] += splats.weights[i];
+                    self.counts[j] += splats.counts[i];
+                } else if splats.weights[i] != 0.0 {
+                    overlap.splats.push((j, splats.sums[i], splats.weights[i]));
+                }
+            }
+        }
+        overlap
+    }
+
+    /// Adds the samples a tile spread over the pixels of its neighbours.
+    pub fn merge_overlap(&mut self, overlap: &Overlap) {
+        for &(j, sum, weight) in &overlap.splats {
+            self.sums[j] += sum;
+            self.weights[j] += weight;
+        }
+    }
+
+    /// Writes the current estimate, the filter weighted mean of the samples
+    /// around each pixel, to `film`.
+    pub fn resolve(&self, film: &mut Film) {
+        for ((pixel, sum), weight) in film.pixels.iter_mut().zip(&self.sums).zip(&self.weights) {
+            *pixel = if *weight == 0.0 {
+                Vec3::ZERO
+            } else {
+                *sum / *weight
+            };
+        }
+    }
+}
+
+/// The part of a tile's samples that the filter spread over the pixels of the
+/// tiles around it, as image pixel indices with their sums and weights.
+pub struct Overlap {
+    splats: Vec<(usize, Vec3, f32)>,
+}
+
+/// The filtered samples of a tile, covering the tile grown by the filter
+/// radius so samples near its edge reach the pixels of the neighbouring tiles.
+pub struct TileSplats {
+    tile: Tile,
+    pub x0: u32,
+    pub y0: u32,
+    pub width: u32,
+    pub height: u32,
+    sums: Vec<Vec3>,
+    weights: Vec<f32>,
+    counts: Vec<u32>,
+    filter: Filter,
+    column_weights: Vec<f32>,
+}
+
+impl TileSplats {
+    /// Splats for `tile` of an `image_width` by `image_height` image.
+    pub fn new(tile: &Tile, filter: Filter, image_width: u32, image_height: u32) -> TileSplats {
+        let margin = (filter.radius - 0.5).max(0.0).ceil() as u32;
+        let x0 = tile.x0.saturating_sub(margin);
+        let y0 = tile.y0.saturating_sub(margin);
+        let width = (tile.x1 + margin).min(image_width) - x0;
+        let height = (tile.y1 + margin).min(image_height) - y0;
+        let len = (width * height) as usize;
+        TileSplats {
+            tile: *tile,
+            x0,
+            y0,
+            width,
+            height,
+            sums: vec![Vec3::ZERO; len],
+            weights: vec![0.0; len],
+            counts: vec![0; len],
+            filter,
+            column_weights: Vec::new(),
+        }
+    }
+
+    /// Adds a sample taken in pixel `(x, y)` at `offset` pixels from its centre
+    /// to every pixel within the filter radius.
+    pub fn add_sample(&mut self, x: u32, y: u32, offset: [f32; 2], color: Vec3) {
+        let i = ((y - self.y0) * self.width + x - self.x0) as usize;
+        self.counts[i] += 1;
+
+        // Film position relative to the splats' first pixel centre
+        let px = (x - self.x0) as f32 + offset[0];
+        let py = (y - self.y0) as f32 + offset[1];
+        let r = self.filter.radius;
+        let first_x = (px - r).ceil().max(0.0) as u32;
+        let first_y = (py - r).ceil().max(0.0) as u32;
+        let last_x = ((px + r).floor() as u32).min(self.width - 1);
+        let last_y = ((py + r).floor() as u32).min(self.height - 1);
+        // The filter is separable, so each column's weight is only needed once
+        self.column_weights.clear();
+        self.column_weights
+            .extend((first_x..=last_x).map(|sx| self.filter.weight_1d(sx as f32 - px)));
+        for sy in first_y..=last_y {
+            let row_weight = self.filter.weight_1d(sy as f32 - py);
+            for (sx, column_weight) in (first_x..=last_x).zip(&self.column_weights) {
+                let weight = row_weight * column_weight;
+                if weight != 0.0 {
+                    let j = (sy * self.width + sx) as usize;
+                    self.sums[j] += color * weight;
+                    self.weights[j] += weight;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        filter::FilterKind,
+        tiles::{self, TileOrder},
+    };
+
+    #[test]
+    fn splats_cover_the_filter_radius_around_the_tile() {
+        // Two 4x4 tiles side by side
+        let tiles = tiles::tiles(8, 4, 4, TileOrder::Scanline);
+        let (left, right) = (&tiles[0], &tiles[1]);
+
+        // Only grown into the image, not past its edges
+        let tent = Filter::new(FilterKind::Tent);
+        let splats = TileSplats::new(left, tent, 8, 4);
+        assert_eq!(
+            (splats.x0, splats.y0, splats.width, splats.height),
+            (0, 0, 5, 4)
+        );
+        let splats = TileSplats::new(right, tent, 8, 4);
+        assert_eq!(
+            (splats.x0, splats.y0, splats.width, splats.height),
+            (3, 0, 5, 4)
+        );
+        let lanczos = Filter::new(FilterKind::Lanczos);
+        let splats = TileSplats::new(right, lanczos, 8, 4);
+        assert_eq!(
+            (splats.x0, splats.y0, splats.width, splats.height),
+            (1, 0, 7, 4)
+        );
+
+        // The default box filter keeps to the tile
+        let splats = TileSplats::new(right, Filter::default(), 8, 4);
+        assert_eq!(
+            (splats.x0, splats.y0, splats.width, splats.height),
+            (4, 0, 4, 4)
+        );
+    }
+
+    #[test]
+    fn samples_near_a_tile_edge_reach_the_neighbouring_tile() {
+        let tiles = tiles::tiles(8, 4, 4, TileOrder::Scanline);
+        let mut splats = TileSplats::new(&tiles[0], Filter::new(FilterKind::Tent), 8, 4);
+        // A quarter pixel right of the centre of the last pixel in the row,
+        // three quarters of a pixel from the first pixel of the next tile
+        let color = Vec3::new(1.0, 2.0, 4.0);
+        splats.add_sample(3, 1, [0.25, 0.0], color);
+
+        let mut accumulator = Accumulator::new(8, 4);
+        let overlap = accumulator.merge_tile(&splats);
+        let index = |x: u32, y: u32| (y * 8 + x) as usize;
+        assert_eq!(accumulator.sums[index(3, 1)], color * 0.75);
+        assert_eq!(accumulator.weights[index(3, 1)], 0.75);
+        assert_eq!(accumulator.counts[index(3, 1)], 1);
+        // The neighbouring tile's pixel waits for the overlap
+        assert_eq!(accumulator.weights[index(4, 1)], 0.0);
+
+        accumulator.merge_overlap(&overlap);
+        assert_eq!(accumulator.sums[index(4, 1)], color * 0.25);
+        assert_eq!(accumulator.weights[index(4, 1)], 0.25);
+        // Only the pixel the sample was taken in counts it
+        assert_eq!(accumulator.counts[index(4, 1)], 0);
+        assert_eq!(accumulator.counts.iter().sum::<u32>(), 1);
+        let total_weight: f32 = accumulator.weights.iter().sum();
+        assert_eq!(total_weight, 1.0);
+    }
+}
@@ -0,0 +1,194 @@
+//! Pixel reconstruction filters, which weigh how much a sample contributes to
+//! each pixel near it.
+
+use std::f32::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterKind {
+    /// Every sample within the radius counts equally. With the default radius
+    /// of half a pixel samples only count towards their own pixel.
+    #[default]
+    Box,
+    /// Weight falling linearly to zero at the radius.
+    Tent,
+    /// Gaussian with a standard deviation of a third of the radius, shifted
+    /// down to reach zero at the radius.
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3, a compromise between
+    /// blurring and ringing.
+    Mitchell,
+    /// Sinc windowed by a sinc stretched over the radius. Sharpest, but rings
+    /// around high contrast edges.
+    Lanczos,
+}
+
+impl FilterKind {
+    pub const NAMES: [&'static str; 5] = ["box", "tent", "gaussian", "mitchell", "lanczos"];
+
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        match name {
+            "box" => Some(FilterKind::Box),
+            "tent" => Some(FilterKind::Tent),
+            "gaussian" => Some(FilterKind::Gaussian),
+            "mitchell" => Some(FilterKind::Mitchell),
+            "lanczos" => Some(FilterKind::Lanczos),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+            FilterKind::Lanczos => "lanczos",
+        }
+    }
+
+    /// Radius in pixels that suits the filter's shape.
+    pub fn default_radius(&self) -> f32 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+/// A separable filter with a square support of `radius` pixels around the
+/// sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f32,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(FilterKind::default())
+    }
+}
+
+impl Filter {
+    /// Smallest radius that reaches every sample of a pixel, whose offsets
+    /// from its centre go up to half a pixel.
+    pub const MIN_RADIUS: f32 = 0.5;
+
+    pub fn new(kind: FilterKind) -> Filter {
+        Filter {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    /// Weight of a sample `(dx, dy)` pixels away from a pixel centre. The
+    /// Mitchell and Lanczos filters have negative lobes.
+    pub fn weight(&self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    /// Weight along one axis, [`Filter::weight`] is the product of the two.
+    /// `d` is the pixel centre's position minus the sample's.
+    pub fn weight_1d(&self, d: f32) -> f32 {
+        let r = self.radius;
+        // Samples are offset by [-0.5, 0.5) from their pixel's centre, so the
+        // box is open at -r for a sample on the edge between two pixels to
+        // count towards exactly one of them
+        let inside = match self.kind {
+            FilterKind::Box => -r < d && d <= r,
+            _ => d.abs() < r,
+        };
+        if !inside {
+            return 0.0;
+        }
+        let d = d.abs();
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - d,
+            FilterKind::Gaussian => {
+                let sigma = r / 3.0;
+                let gaussian = |x: f32| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(d) - gaussian(r)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * d / r, 1.0 / 3.0, 1.0 / 3.0),
+            FilterKind::Lanczos => sinc(d) * sinc(d / r),
+        }
+    }
+}
+
+/// Mitchell-Netravali cubic for `x` in `[0, 2]`.
+fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+    let x2 = x * x;
+    let x3 = x2 * x;
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x3 + (-18.0 + 12.0 * b + 6.0 * c) * x2 + (6.0 - 2.0 * b))
+            / 6.0
+    } else {
+        ((-b - 6.0 * c) * x3
+            + (6.0 * b + 30.0 * c) * x2
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+
+    #[test]
+    fn filters_peak_at_the_centre_and_vanish_at_the_radius() {
+        for name in FilterKind::NAMES {
+            let filter = Filter::new(FilterKind::from_name(name).unwrap());
+            let peak = filter.weight(0.0, 0.0);
+            assert!(peak > 0.0, "{}", name);
+            assert_eq!(filter.weight(-filter.radius, 0.0), 0.0, "{}", name);
+            if filter.kind != FilterKind::Box {
+                assert_eq!(filter.weight(filter.radius, 0.0), 0.0, "{}", name);
+                // No step at the edge of the support
+                assert_approx_eq!(filter.weight(0.0, filter.radius * 0.999), 0.0, 0.01 * peak);
+            }
+            for i in 1..20 {
+                let d = filter.radius * i as f32 / 20.0;
+                assert!(filter.weight(d, 0.0) <= peak, "{}", name);
+                assert_eq!(filter.weight(d, 0.0), filter.weight(-d, 0.0));
+            }
+        }
+
+        // A box sample on the edge between two pixels only counts towards the
+        // one it was taken in
+        let filter = Filter::default();
+        for offset in [-0.5, -0.25, 0.0, 0.49] {
+            let pixels = [-1.0, 0.0, 1.0].map(|pixel: f32| filter.weight_1d(pixel - offset));
+            assert_eq!(pixels, [0.0, 1.0, 0.0], "{}", offset);
+        }
+
+        // The cubic's pieces meet, and its weights over a row of pixels sum to
+        // the same value wherever the sample falls
+        assert_approx_eq!(
+            mitchell(0.9999, 1.0 / 3.0, 1.0 / 3.0),
+            mitchell(1.0, 1.0 / 3.0, 1.0 / 3.0),
+            1e-3
+        );
+        let mitchell = Filter::new(FilterKind::Mitchell);
+        let row_sum = |offset: f32| {
+            (-3..=3)
+                .map(|i| mitchell.weight_1d(i as f32 + offset))
+                .sum::<f32>()
+        };
+        assert_approx_eq!(row_sum(0.0), row_sum(0.37), 1e-4);
+    }
+}
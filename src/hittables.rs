@@ -1,8 +1,18 @@
-use std::sync::Arc;
+use std::{f32::consts::PI, fmt, sync::Arc};
+
+use rand::Rng;
 
 use crate::{
+    bvh::{Primitive, BVH},
     materials::Material,
-    math::{aabb::AABB, interval::Interval, ray::Ray, vec3::Vec3},
+    math::{
+        aabb::AABB,
+        interval::Interval,
+        mat4::{AnimatedTransform, Mat4},
+        ray::Ray,
+        vec3::Vec3,
+    },
+    utils,
 };
 
 pub struct HitInfo {
@@ -12,6 +22,9 @@ pub struct HitInfo {
     pub t: f32,
     pub u: f32,
     pub v: f32,
+    /// Derivatives of the hit point with respect to `u` and `v`, not normalised.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub front_face: bool,
 }
 
@@ -24,11 +37,13 @@ impl HitInfo {
             t: Default::default(),
             u: Default::default(),
             v: Default::default(),
+            dpdu: Default::default(),
+            dpdv: Default::default(),
             front_face: Default::default(),
         }
     }
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vec3) {
-        self.front_face = Vec3::dot(&outward_normal, &ray.direction) < 0.0;
+        self.front_face = Vec3::dot(outward_normal, &ray.direction) < 0.0;
         self.normal = if self.front_face {
             *outward_normal
         } else {
@@ -40,10 +55,29 @@ impl HitInfo {
 pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, t_range: &Interval) -> Option<HitInfo>;
     fn bounding_box(&self) -> &AABB;
+
+    /// Solid angle density with which `random` picks `direction` from `origin`
+    /// at `time`.
+    fn pdf_value(&self, _origin: &Vec3, _direction: &Vec3, _time: f32) -> f32 {
+        0.0
+    }
+
+    /// Direction from `origin` towards the surface for the uniform sample `u`,
+    /// used to sample lights. Only meaningful for hittables with a non-zero
+    /// `pdf_value`.
+    fn random(&self, _origin: &Vec3, _time: f32, _u: [f32; 2]) -> Vec3 {
+        Vec3::RIGHT
+    }
 }
 
+/// Range used when checking whether a sampled direction reaches a surface.
+const SAMPLE_RANGE: Interval = Interval::new(0.001, f32::INFINITY);
+
 pub struct Sphere {
+    /// Centre at time 0.
     center: Vec3,
+    /// Distance the centre moves from time 0 to 1.
+    motion: Vec3,
     radius: f32,
     material: Arc<dyn Material>,
     bounding_box: AABB,
@@ -56,16 +90,35 @@ impl Sphere {
 
         Sphere {
             center,
+            motion: Vec3::ZERO,
             radius,
             material,
             bounding_box,
         }
     }
+
+    /// Sphere that moves in a straight line from `start` at time 0 to `end` at
+    /// time 1.
+    pub fn moving(start: Vec3, end: Vec3, radius: f32, material: Arc<dyn Material>) -> Sphere {
+        let mut sphere = Sphere::new(start, radius, material);
+        let rvec = Vec3::uniform(radius);
+        sphere.motion = end - start;
+        sphere.bounding_box = AABB::combine(
+            &sphere.bounding_box,
+            &AABB::from_points(&(end - rvec), &(end + rvec)),
+        );
+        sphere
+    }
+
+    fn center_at(&self, time: f32) -> Vec3 {
+        self.center + self.motion * time
+    }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, t_range: &Interval) -> Option<HitInfo> {
-        let origin_to_center = self.center - ray.origin;
+        let center = self.center_at(ray.time);
+        let origin_to_center = center - ray.origin;
         let a = ray.direction.length_squared();
         let h = Vec3::dot(&ray.direction, &origin_to_center);
         let c = origin_to_center.length_squared() - self.radius * self.radius;
@@ -90,15 +143,67 @@ impl Hittable for Sphere {
         hit_info.t = t;
         hit_info.point = ray.at(t);
 
-        let outward_normal = (hit_info.point - self.center).normalized();
+        let offset = hit_info.point - center;
+        let outward_normal = offset.normalized();
         hit_info.set_face_normal(ray, &outward_normal);
 
+        // Longitude around the y axis starting at -x, latitude from the bottom pole
+        let theta = (-outward_normal.y).clamp(-1.0, 1.0).acos();
+        let phi = f32::atan2(-outward_normal.z, outward_normal.x) + PI;
+        hit_info.u = phi / (2.0 * PI);
+        hit_info.v = theta / PI;
+        hit_info.dpdu = Vec3::new(offset.z, 0.0, -offset.x) * (2.0 * PI);
+        hit_info.dpdv = Vec3::new(
+            offset.y * phi.cos(),
+            self.radius * theta.sin(),
+            -offset.y * phi.sin(),
+        ) * PI;
+
         Some(hit_info)
     }
 
     fn bounding_box(&self) -> &AABB {
         &self.bounding_box
     }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, time: f32) -> f32 {
+        if self
+            .hit(&Ray::with_time(*origin, *direction, time), &SAMPLE_RANGE)
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        let distance_squared = (self.center_at(time) - *origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            // From the inside every direction hits the sphere
+            return 1.0 / (4.0 * PI);
+        }
+
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: &Vec3, time: f32, u: [f32; 2]) -> Vec3 {
+        let direction = self.center_at(time) - *origin;
+        let distance_squared = direction.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return utils::sample_unit_sphere(u);
+        }
+
+        // Uniform direction inside the cone the sphere subtends
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let cos_theta = 1.0 + u[0] * (cos_theta_max - 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * u[1];
+
+        let w = direction / distance_squared.sqrt();
+        let (u, v) = utils::orthonormal_basis(&w);
+        u * (phi.cos() * sin_theta) + v * (phi.sin() * sin_theta) + w * cos_theta
+    }
 }
 
 pub struct Quad {
@@ -108,6 +213,7 @@ pub struct Quad {
     normal: Vec3,
     d: f32,
     w: Vec3,
+    area: f32,
     material: Arc<dyn Material>,
     bounding_box: AABB,
 }
@@ -130,6 +236,7 @@ impl Quad {
             normal,
             d,
             w,
+            area: perp.length(),
             material,
             bounding_box,
         }
@@ -163,6 +270,10 @@ impl Hittable for Quad {
         hit_info.t = t;
         hit_info.point = hit_point;
         hit_info.set_face_normal(ray, &self.normal);
+        hit_info.u = alpha;
+        hit_info.v = beta;
+        hit_info.dpdu = self.u;
+        hit_info.dpdv = self.v;
 
         Some(hit_info)
     }
@@ -170,6 +281,652 @@ impl Hittable for Quad {
     fn bounding_box(&self) -> &AABB {
         &self.bounding_box
     }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, _time: f32) -> f32 {
+        let Some(hit_info) = self.hit(&Ray::new(*origin, *direction), &SAMPLE_RANGE) else {
+            return 0.0;
+        };
+
+        // Convert the uniform area density to solid angle
+        let direction_length_squared = direction.length_squared();
+        let distance_squared = hit_info.t * hit_info.t * direction_length_squared;
+        let cosine = Vec3::dot(direction, &self.normal).abs() / direction_length_squared.sqrt();
+        if cosine < 1e-6 {
+            return 0.0;
+        }
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Vec3, _time: f32, u: [f32; 2]) -> Vec3 {
+        let point = self.origin + u[0] * self.u + u[1] * self.v;
+        point - *origin
+    }
+}
+
+/// Watertight ray/triangle intersection (Woop, Benthin and Wald 2013).
+/// Returns `t` and the barycentric weights of `p1` and `p2`.
+fn intersect_triangle(
+    ray: &Ray,
+    t_range: &Interval,
+    p0: &Vec3,
+    p1: &Vec3,
+    p2: &Vec3,
+) -> Option<(f32, f32, f32)> {
+    // Transform the triangle into a space where the ray starts at the origin and
+    // points down +z, so the edge tests become 2D and are shared between neighbours.
+    let kz = ray.direction.abs().max_dimension();
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+
+    let d = ray.direction.permuted(kx, ky, kz);
+    let mut p0t = (*p0 - ray.origin).permuted(kx, ky, kz);
+    let mut p1t = (*p1 - ray.origin).permuted(kx, ky, kz);
+    let mut p2t = (*p2 - ray.origin).permuted(kx, ky, kz);
+
+    let sx = -d.x / d.z;
+    let sy = -d.y / d.z;
+    let sz = 1.0 / d.z;
+    for p in [&mut p0t, &mut p1t, &mut p2t] {
+        p.x += sx * p.z;
+        p.y += sy * p.z;
+    }
+
+    let mut e0 = p1t.x * p2t.y - p1t.y * p2t.x;
+    let mut e1 = p2t.x * p0t.y - p2t.y * p0t.x;
+    let mut e2 = p0t.x * p1t.y - p0t.y * p1t.x;
+
+    // Fall back to double precision when an edge function is exactly zero
+    if e0 == 0.0 || e1 == 0.0 || e2 == 0.0 {
+        e0 = (p1t.x as f64 * p2t.y as f64 - p1t.y as f64 * p2t.x as f64) as f32;
+        e1 = (p2t.x as f64 * p0t.y as f64 - p2t.y as f64 * p0t.x as f64) as f32;
+        e2 = (p0t.x as f64 * p1t.y as f64 - p0t.y as f64 * p1t.x as f64) as f32;
+    }
+
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+
+    let det = e0 + e1 + e2;
+    if det == 0.0 {
+        return None;
+    }
+
+    let t_scaled = (e0 * p0t.z + e1 * p1t.z + e2 * p2t.z) * sz;
+    let t = t_scaled / det;
+    if !t_range.surrounds(t) {
+        return None;
+    }
+
+    Some((t, e1 / det, e2 / det))
+}
+
+pub struct Triangle {
+    vertices: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f32, f32); 3]>,
+    normal: Vec3,
+    material: Arc<dyn Material>,
+    bounding_box: AABB,
+}
+
+impl Triangle {
+    pub fn new(p0: Vec3, p1: Vec3, p2: Vec3, material: Arc<dyn Material>) -> Triangle {
+        let normal = Vec3::cross(&(p1 - p0), &(p2 - p0)).normalized();
+        let bounding_box =
+            AABB::combine(&AABB::from_points(&p0, &p1), &AABB::from_points(&p0, &p2));
+
+        Triangle {
+            vertices: [p0, p1, p2],
+            normals: None,
+            uvs: None,
+            normal,
+            material,
+            bounding_box,
+        }
+    }
+
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Triangle {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: [(f32, f32); 3]) -> Triangle {
+        self.uvs = Some(uvs);
+        self
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_range: &Interval) -> Option<HitInfo> {
+        let [p0, p1, p2] = &self.vertices;
+        let (t, b1, b2) = intersect_triangle(ray, t_range, p0, p1, p2)?;
+
+        let mut hit_info = HitInfo::new(self.material.clone());
+        hit_info.t = t;
+        hit_info.point = ray.at(t);
+        fill_triangle_surface(
+            &mut hit_info,
+            ray,
+            &self.vertices,
+            &self.normal,
+            self.normals.as_ref(),
+            self.uvs.as_ref(),
+            (b1, b2),
+        );
+
+        Some(hit_info)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bounding_box
+    }
+}
+
+/// Sets the normal, `u`/`v` and tangents of a triangle hit from its barycentric
+/// coordinates. Without per-vertex UVs the barycentrics themselves are used as `u`/`v`.
+fn fill_triangle_surface(
+    hit_info: &mut HitInfo,
+    ray: &Ray,
+    positions: &[Vec3; 3],
+    geometric_normal: &Vec3,
+    normals: Option<&[Vec3; 3]>,
+    uvs: Option<&[(f32, f32); 3]>,
+    (b1, b2): (f32, f32),
+) {
+    let b0 = 1.0 - b1 - b2;
+    let [p0, p1, p2] = positions;
+
+    hit_info.set_face_normal(ray, geometric_normal);
+    if let Some([n0, n1, n2]) = normals {
+        let shading_normal = (b0 * *n0 + b1 * *n1 + b2 * *n2).normalized();
+        // Keep the interpolated normal on the same side as the geometric one
+        hit_info.normal = if Vec3::dot(&shading_normal, &hit_info.normal) < 0.0 {
+            -shading_normal
+        } else {
+            shading_normal
+        };
+    }
+
+    (hit_info.u, hit_info.v) = match uvs {
+        Some([uv0, uv1, uv2]) => (
+            b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+            b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+        ),
+        None => (b1, b2),
+    };
+
+    (hit_info.dpdu, hit_info.dpdv) = match uvs {
+        Some([uv0, uv1, uv2]) => {
+            // Solve dp = dpdu * du + dpdv * dv along two edges
+            let (du02, dv02) = (uv0.0 - uv2.0, uv0.1 - uv2.1);
+            let (du12, dv12) = (uv1.0 - uv2.0, uv1.1 - uv2.1);
+            let determinant = du02 * dv12 - dv02 * du12;
+            if determinant.abs() < 1e-8 {
+                // Degenerate UVs, any tangents will do
+                utils::orthonormal_basis(geometric_normal)
+            } else {
+                let dp02 = *p0 - *p2;
+                let dp12 = *p1 - *p2;
+                (
+                    (dv12 * dp02 - dv02 * dp12) / determinant,
+                    (du02 * dp12 - du12 * dp02) / determinant,
+                )
+            }
+        }
+        None => (*p1 - *p0, *p2 - *p0),
+    };
+}
+
+/// Vertex and index buffers shared by every triangle of a [`TriangleMesh`].
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Option<Vec<Vec3>>,
+    pub uvs: Option<Vec<(f32, f32)>>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+impl MeshData {
+    fn face_positions(&self, face: usize) -> [Vec3; 3] {
+        self.indices[face].map(|i| self.positions[i as usize])
+    }
+}
+
+/// Why a [`MeshData`] can't be turned into a [`TriangleMesh`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeshError {
+    NoFaces,
+    /// A normal or UV buffer doesn't have one entry per position.
+    AttributeCount {
+        attribute: &'static str,
+        count: usize,
+        positions: usize,
+    },
+    IndexOutOfRange {
+        face: usize,
+        index: u32,
+        positions: usize,
+    },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::NoFaces => write!(f, "mesh has no faces"),
+            MeshError::AttributeCount {
+                attribute,
+                count,
+                positions,
+            } => write!(
+                f,
+                "mesh has {} {} for {} positions",
+                count, attribute, positions
+            ),
+            MeshError::IndexOutOfRange {
+                face,
+                index,
+                positions,
+            } => write!(
+                f,
+                "face {} uses vertex {} of a mesh with {} positions",
+                face, index, positions
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+/// A face of a [`TriangleMesh`], as its BVH sees it.
+#[derive(Clone)]
+struct MeshFace {
+    index: u32,
+    bounding_box: AABB,
+}
+
+impl Primitive for MeshFace {
+    fn bounding_box(&self) -> &AABB {
+        &self.bounding_box
+    }
+}
+
+/// An indexed triangle mesh. The faces are intersected straight from the
+/// shared [`MeshData`] through a BVH over their indices, so the mesh is added
+/// to a scene as one object.
+pub struct TriangleMesh {
+    data: MeshData,
+    material: Arc<dyn Material>,
+    bvh: BVH<MeshFace>,
+}
+
+impl TriangleMesh {
+    pub fn new(data: MeshData, material: Arc<dyn Material>) -> Result<TriangleMesh, MeshError> {
+        let positions = data.positions.len();
+        if data.indices.is_empty() {
+            return Err(MeshError::NoFaces);
+        }
+        let attribute_counts = [
+            ("normals", data.normals.as_ref().map(Vec::len)),
+            ("UVs", data.uvs.as_ref().map(Vec::len)),
+        ];
+        for (attribute, count) in attribute_counts {
+            match count {
+                Some(count) if count != positions => {
+                    return Err(MeshError::AttributeCount {
+                        attribute,
+                        count,
+                        positions,
+                    })
+                }
+                _ => (),
+            }
+        }
+        for (face, indices) in data.indices.iter().enumerate() {
+            if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions) {
+                return Err(MeshError::IndexOutOfRange {
+                    face,
+                    index,
+                    positions,
+                });
+            }
+        }
+
+        let faces: Vec<MeshFace> = (0..data.indices.len())
+            .map(|face| {
+                let [p0, p1, p2] = data.face_positions(face);
+                MeshFace {
+                    index: face as u32,
+                    bounding_box: AABB::combine(
+                        &AABB::from_points(&p0, &p1),
+                        &AABB::from_points(&p0, &p2),
+                    ),
+                }
+            })
+            .collect();
+        let bvh = BVH::new(&faces);
+
+        Ok(TriangleMesh {
+            data,
+            material,
+            bvh,
+        })
+    }
+
+    pub fn data(&self) -> &MeshData {
+        &self.data
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.data.indices.len()
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_range: &Interval) -> Option<HitInfo> {
+        let (t, face, b1, b2) = self.bvh.closest_hit(ray, t_range, |face, t_range| {
+            let face = face.index as usize;
+            let [p0, p1, p2] = self.data.face_positions(face);
+            let (t, b1, b2) = intersect_triangle(ray, t_range, &p0, &p1, &p2)?;
+            Some((t, (t, face, b1, b2)))
+        })?;
+
+        // Only the closest face's surface is worked out
+        let [i0, i1, i2] = self.data.indices[face].map(|i| i as usize);
+        let positions = self.data.face_positions(face);
+        let [p0, p1, p2] = positions;
+        let normal = Vec3::cross(&(p1 - p0), &(p2 - p0)).normalized();
+        let normals = self.data.normals.as_ref().map(|n| [n[i0], n[i1], n[i2]]);
+        let uvs = self.data.uvs.as_ref().map(|uv| [uv[i0], uv[i1], uv[i2]]);
+
+        let mut hit_info = HitInfo::new(self.material.clone());
+        hit_info.t = t;
+        hit_info.point = ray.at(t);
+        fill_triangle_surface(
+            &mut hit_info,
+            ray,
+            &positions,
+            &normal,
+            normals.as_ref(),
+            uvs.as_ref(),
+            (b1, b2),
+        );
+
+        Some(hit_info)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.bvh.bounds()
+    }
+}
+
+/// Closed box with the opposite corners `a` and `b`, made of six outward facing quads.
+pub fn cuboid(a: &Vec3, b: &Vec3, material: Arc<dyn Material>) -> HittableList {
+    let min = Vec3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+    let max = Vec3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+    let dx = Vec3::RIGHT * (max.x - min.x);
+    let dy = Vec3::UP * (max.y - min.y);
+    let dz = Vec3::BACKWARD * (max.z - min.z);
+
+    let mut sides = HittableList::new();
+    let mut add_side = |origin: Vec3, u: Vec3, v: Vec3| {
+        sides.add(Arc::new(Quad::new(origin, u, v, material.clone())));
+    };
+    add_side(Vec3::new(min.x, min.y, max.z), dx, dy); // Front
+    add_side(Vec3::new(max.x, min.y, max.z), -dz, dy); // Right
+    add_side(Vec3::new(max.x, min.y, min.z), -dx, dy); // Back
+    add_side(Vec3::new(min.x, min.y, min.z), dz, dy); // Left
+    add_side(Vec3::new(min.x, max.y, max.z), dx, -dz); // Top
+    add_side(Vec3::new(min.x, min.y, min.z), dx, dz); // Bottom
+    sides
+}
+
+/// Places a shared object in the world with an affine transform, so one mesh
+/// can appear several times without copying it.
+pub struct Transformed {
+    object: Arc<dyn Hittable>,
+    object_to_world: Mat4,
+    world_to_object: Mat4,
+    bounding_box: AABB,
+}
+
+impl Transformed {
+    /// Panics if `object_to_world` can't be inverted.
+    pub fn new(object: Arc<dyn Hittable>, object_to_world: Mat4) -> Transformed {
+        let world_to_object = object_to_world
+            .inverse()
+            .expect("transform must be invertible");
+
+        // The world box has to contain all eight transformed corners
+        let mut min = Vec3::uniform(f32::INFINITY);
+        let mut max = Vec3::uniform(f32::NEG_INFINITY);
+        for corner in corners(object.bounding_box()) {
+            let corner = object_to_world.transform_point(&corner);
+            min = Vec3::new(
+                min.x.min(corner.x),
+                min.y.min(corner.y),
+                min.z.min(corner.z),
+            );
+            max = Vec3::new(
+                max.x.max(corner.x),
+                max.y.max(corner.y),
+                max.z.max(corner.z),
+            );
+        }
+        let bounding_box = AABB::from_points(&min, &max);
+
+        Transformed {
+            object,
+            object_to_world,
+            world_to_object,
+            bounding_box,
+        }
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, ray: &Ray, t_range: &Interval) -> Option<HitInfo> {
+        hit_transformed(
+            self.object.as_ref(),
+            &self.object_to_world,
+            &self.world_to_object,
+            ray,
+            t_range,
+        )
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bounding_box
+    }
+
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, time: f32) -> f32 {
+        let object_direction = self.world_to_object.transform_vector(direction);
+        let pdf = self.object.pdf_value(
+            &self.world_to_object.transform_point(origin),
+            &object_direction,
+            time,
+        );
+        if pdf == 0.0 {
+            return 0.0;
+        }
+
+        // Change of density of directions mapped by the linear part M, which is
+        // |M v|^3 / |det M| for the unit object space direction v
+        let stretch = direction.length() / object_direction.length();
+        pdf * stretch.powi(3) / self.object_to_world.determinant3().abs()
+    }
+
+    fn random(&self, origin: &Vec3, time: f32, u: [f32; 2]) -> Vec3 {
+        let direction = self
+            .object
+            .random(&self.world_to_object.transform_point(origin), time, u);
+        self.object_to_world.transform_vector(&direction)
+    }
+}
+
+fn hit_transformed(
+    object: &dyn Hittable,
+    object_to_world: &Mat4,
+    world_to_object: &Mat4,
+    ray: &Ray,
+    t_range: &Interval,
+) -> Option<HitInfo> {
+    // The direction isn't normalised, so `t` is the same in both spaces
+    let object_ray = Ray::with_time(
+        world_to_object.transform_point(&ray.origin),
+        world_to_object.transform_vector(&ray.direction),
+        ray.time,
+    );
+    let mut hit_info = object.hit(&object_ray, t_range)?;
+
+    hit_info.point = ray.at(hit_info.t);
+    hit_info.normal = world_to_object
+        .transform_normal(&hit_info.normal)
+        .normalized();
+    hit_info.dpdu = object_to_world.transform_vector(&hit_info.dpdu);
+    hit_info.dpdv = object_to_world.transform_vector(&hit_info.dpdv);
+
+    Some(hit_info)
+}
+
+/// Corners of a box.
+fn corners(bounding_box: &AABB) -> impl Iterator<Item = Vec3> + '_ {
+    (0..8).map(move |i| {
+        let pick = |interval: Interval, bit: i32| {
+            if i & bit == 0 {
+                interval.start
+            } else {
+                interval.end
+            }
+        };
+        Vec3::new(
+            pick(bounding_box.x, 1),
+            pick(bounding_box.y, 2),
+            pick(bounding_box.z, 4),
+        )
+    })
+}
+
+/// An object whose transform changes over the shutter interval, for motion blur.
+/// Light sampling isn't supported.
+pub struct Animated {
+    object: Arc<dyn Hittable>,
+    transform: AnimatedTransform,
+    bounding_box: AABB,
+}
+
+impl Animated {
+    pub fn new(object: Arc<dyn Hittable>, transform: AnimatedTransform) -> Animated {
+        // Bound the object at evenly spaced times, then pad by how far any
+        // point can move between them
+        const STEPS: u32 = 32;
+        let object_box = object.bounding_box();
+        let radius = corners(object_box)
+            .map(|corner| corner.length())
+            .fold(0.0, f32::max);
+        let mut min = Vec3::uniform(f32::INFINITY);
+        let mut max = Vec3::uniform(f32::NEG_INFINITY);
+        for step in 0..=STEPS {
+            let at = transform.at(step as f32 / STEPS as f32);
+            for corner in corners(object_box) {
+                let corner = at.transform_point(&corner);
+                min = Vec3::new(
+                    min.x.min(corner.x),
+                    min.y.min(corner.y),
+                    min.z.min(corner.z),
+                );
+                max = Vec3::new(
+                    max.x.max(corner.x),
+                    max.y.max(corner.y),
+                    max.z.max(corner.z),
+                );
+            }
+        }
+        let padding = Vec3::uniform(transform.max_speed(radius) / (2 * STEPS) as f32);
+        let bounding_box = AABB::from_points(&(min - padding), &(max + padding));
+
+        Animated {
+            object,
+            transform,
+            bounding_box,
+        }
+    }
+}
+
+impl Hittable for Animated {
+    fn hit(&self, ray: &Ray, t_range: &Interval) -> Option<HitInfo> {
+        hit_transformed(
+            self.object.as_ref(),
+            &self.transform.at(ray.time),
+            &self.transform.inverse_at(ray.time),
+            ray,
+            t_range,
+        )
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        &self.bounding_box
+    }
+}
+
+/// Smoke or fog of constant density filling a closed boundary. Rays scatter
+/// at a random distance inside according to the medium's phase function
+/// material, so the boundary has to be convex for the entry and exit points
+/// to be found.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    density: f32,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    /// `density` is the chance of scattering per unit length.
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        density: f32,
+        phase_function: Arc<dyn Material>,
+    ) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            density,
+            phase_function,
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_range: &Interval) -> Option<HitInfo> {
+        // The entry can be behind the origin for rays that start inside
+        let entry = self.boundary.hit(ray, &Interval::UNIVERSE)?;
+        let exit = self
+            .boundary
+            .hit(ray, &Interval::new(entry.t + 0.0001, f32::INFINITY))?;
+
+        let t_start = entry.t.max(t_range.start).max(0.0);
+        let t_end = exit.t.min(t_range.end);
+        if t_start >= t_end {
+            return None;
+        }
+
+        // Exponentially distributed free flight distance. Not drawn from the
+        // sampler, see the `sampler` module
+        let ray_length = ray.direction.length();
+        let distance_inside = (t_end - t_start) * ray_length;
+        let distance = -(1.0 - utils::rng().gen::<f32>()).ln() / self.density;
+        if distance > distance_inside {
+            return None;
+        }
+
+        let mut hit_info = HitInfo::new(self.phase_function.clone());
+        hit_info.t = t_start + distance / ray_length;
+        hit_info.point = ray.at(hit_info.t);
+        // Arbitrary, phase functions don't depend on it
+        hit_info.normal = -ray.direction / ray_length;
+        hit_info.front_face = true;
+        Some(hit_info)
+    }
+
+    fn bounding_box(&self) -> &AABB {
+        self.boundary.bounding_box()
+    }
 }
 
 #[derive(Default)]
@@ -180,7 +937,7 @@ pub struct HittableList {
 
 impl HittableList {
     pub fn new() -> HittableList {
-        return Default::default();
+        Default::default()
     }
 
     pub fn add(&mut self, obj: Arc<dyn Hittable>) {
@@ -191,6 +948,18 @@ impl HittableList {
     pub fn reserve(&mut self, count: usize) {
         self.objects.reserve(count);
     }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn objects(&self) -> &[Arc<dyn Hittable>] {
+        &self.objects
+    }
 }
 
 impl Hittable for HittableList {
@@ -199,12 +968,9 @@ impl Hittable for HittableList {
         let mut closest_so_far = t_range.end;
 
         for obj in self.objects.iter() {
-            match obj.hit(ray, &Interval::new(t_range.start, closest_so_far)) {
-                Some(info) => {
-                    closest_so_far = info.t;
-                    hit_info = Some(info);
-                }
-                None => (),
+            if let Some(info) = obj.hit(ray, &Interval::new(t_range.start, closest_so_far)) {
+                closest_so_far = info.t;
+                hit_info = Some(info);
             }
         }
 
@@ -212,82 +978,453 @@ impl Hittable for HittableList {
     }
 
     fn bounding_box(&self) -> &AABB {
-        return &self.bounding_box;
+        &self.bounding_box
     }
-}
 
-pub struct BVHNode {
-    left: Arc<dyn Hittable>,
-    right: Arc<dyn Hittable>,
-    bounding_box: AABB,
+    /// Picks one of the objects uniformly, so the density is their average.
+    fn pdf_value(&self, origin: &Vec3, direction: &Vec3, time: f32) -> f32 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f32 = self
+            .objects
+            .iter()
+            .map(|obj| obj.pdf_value(origin, direction, time))
+            .sum();
+        sum / self.objects.len() as f32
+    }
+
+    fn random(&self, origin: &Vec3, time: f32, u: [f32; 2]) -> Vec3 {
+        // The first dimension picks the object and what's left of it is reused
+        // as a fresh uniform number
+        let scaled = u[0] * self.objects.len() as f32;
+        let index = (scaled as usize).min(self.objects.len() - 1);
+        let remapped = (scaled - index as f32).min(1.0 - f32::EPSILON / 2.0);
+        self.objects[index].random(origin, time, [remapped, u[1]])
+    }
 }
 
-impl BVHNode {
-    pub fn new(objects: &mut [Arc<dyn Hittable>]) -> BVHNode {
-        let mut bounding_box = AABB::EMPTY;
-        for obj in objects.iter() {
-            bounding_box = AABB::combine(&bounding_box, obj.bounding_box());
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+    use crate::materials::DiffuseLight;
+
+    fn test_material() -> Arc<dyn Material> {
+        Arc::new(DiffuseLight { color: Vec3::ONE })
+    }
+
+    #[test]
+    fn triangle_hit_reports_barycentrics() {
+        let triangle = Triangle::new(
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, -1.0),
+            test_material(),
+        );
+        let ray = Ray::new(Vec3::new(0.25, 0.5, 0.0), Vec3::FORWARD);
+        let hit = triangle.hit(&ray, &Interval::UNIVERSE).unwrap();
+
+        assert_approx_eq!(hit.t, 1.0);
+        assert_approx_eq!(hit.u, 0.25);
+        assert_approx_eq!(hit.v, 0.5);
+        assert!(hit.front_face);
+        assert_eq!(hit.normal, Vec3::BACKWARD);
+        assert_eq!(hit.dpdu, Vec3::RIGHT);
+        assert_eq!(hit.dpdv, Vec3::UP);
+
+        // Tangents follow the texture coordinates
+        let triangle = Triangle::new(
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(2.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, -1.0),
+            test_material(),
+        )
+        .with_uvs([(0.0, 1.0), (0.0, 0.0), (0.5, 1.0)]);
+        let hit = triangle.hit(&ray, &Interval::UNIVERSE).unwrap();
+        assert_approx_eq!(hit.dpdu.y, 2.0);
+        assert_approx_eq!(hit.dpdv.x, -2.0);
+        assert_approx_eq!(hit.dpdv.y, 0.0);
+    }
+
+    #[test]
+    fn sphere_and_quad_uvs() {
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, -5.0), 2.0, test_material());
+        // (origin, expected u, expected v)
+        let cases = [
+            (Vec3::new(0.0, 0.0, 0.0), 0.25, 0.5),
+            (Vec3::new(5.0, 0.0, -5.0), 0.5, 0.5),
+            (Vec3::new(0.0, 5.0, -5.0), 0.5, 1.0),
+            (Vec3::new(-12f32.sqrt(), -2.0, -5.0), 0.0, 1.0 / 3.0),
+        ];
+        for (origin, u, v) in cases {
+            let ray = Ray::new(origin, Vec3::new(0.0, 0.0, -5.0) - origin);
+            let hit = sphere.hit(&ray, &Interval::UNIVERSE).unwrap();
+            assert_approx_eq!(hit.u.rem_euclid(1.0), u, 1e-4);
+            assert_approx_eq!(hit.v, v, 1e-4);
+            assert_approx_eq!(Vec3::dot(&hit.dpdu, &hit.normal), 0.0, 1e-4);
+            assert_approx_eq!(Vec3::dot(&hit.dpdv, &hit.normal), 0.0, 1e-4);
         }
 
-        let axis: u32 = bounding_box.longest_axis();
+        // Tangents match a small step in u and v
+        let ray = Ray::new(Vec3::new(1.0, 0.5, 0.0), Vec3::FORWARD);
+        let hit = sphere.hit(&ray, &Interval::UNIVERSE).unwrap();
+        let point_at = |u: f32, v: f32| {
+            let (phi, theta) = (u * 2.0 * PI, v * PI);
+            Vec3::new(
+                -phi.cos() * theta.sin(),
+                -theta.cos(),
+                phi.sin() * theta.sin(),
+            ) * 2.0
+                + Vec3::new(0.0, 0.0, -5.0)
+        };
+        let step = 1e-3;
+        let dpdu = (point_at(hit.u + step, hit.v) - hit.point) / step;
+        let dpdv = (point_at(hit.u, hit.v + step) - hit.point) / step;
+        assert!((dpdu - hit.dpdu).length() < 0.02 * hit.dpdu.length());
+        assert!((dpdv - hit.dpdv).length() < 0.02 * hit.dpdv.length());
 
-        let left;
-        let right;
-        match objects.len() {
-            1 => {
-                left = objects[0].clone();
-                right = objects[0].clone();
-            }
-            2 => {
-                left = objects[0].clone();
-                right = objects[1].clone();
-            }
-            _ => {
-                objects.sort_by(|a, b| {
-                    let a_start = a.bounding_box()[axis as usize].start;
-                    let b_start = b.bounding_box()[axis as usize].start;
-                    a_start.total_cmp(&b_start)
-                });
+        let quad = Quad::new(
+            Vec3::new(-1.0, -1.0, -1.0),
+            Vec3::RIGHT * 2.0,
+            Vec3::UP * 4.0,
+            test_material(),
+        );
+        let ray = Ray::new(Vec3::new(-0.5, 2.0, 0.0), Vec3::FORWARD);
+        let hit = quad.hit(&ray, &Interval::UNIVERSE).unwrap();
+        assert_approx_eq!(hit.u, 0.25);
+        assert_approx_eq!(hit.v, 0.75);
+        assert_eq!(hit.dpdu, Vec3::RIGHT * 2.0);
+        assert_eq!(hit.dpdv, Vec3::UP * 4.0);
+    }
 
-                let mid = objects.len() / 2;
-                left = Arc::new(BVHNode::new(&mut objects[..mid]));
-                right = Arc::new(BVHNode::new(&mut objects[mid..]));
-            }
+    #[test]
+    fn triangle_miss() {
+        let triangle = Triangle::new(
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(1.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, -1.0),
+            test_material(),
+        );
+        let ray = Ray::new(Vec3::new(0.75, 0.75, 0.0), Vec3::FORWARD);
+        assert!(triangle.hit(&ray, &Interval::UNIVERSE).is_none());
+    }
+
+    #[test]
+    fn invalid_meshes_are_rejected() {
+        let mesh = |normals: Option<Vec<Vec3>>, indices: Vec<[u32; 3]>| {
+            let data = MeshData {
+                positions: vec![Vec3::ZERO, Vec3::RIGHT, Vec3::UP],
+                normals,
+                uvs: None,
+                indices,
+            };
+            TriangleMesh::new(data, test_material()).err()
+        };
+        assert_eq!(mesh(None, vec![[0, 1, 2]]), None);
+        assert_eq!(mesh(None, vec![]), Some(MeshError::NoFaces));
+        assert_eq!(
+            mesh(Some(vec![Vec3::UP]), vec![[0, 1, 2]]),
+            Some(MeshError::AttributeCount {
+                attribute: "normals",
+                count: 1,
+                positions: 3
+            })
+        );
+        assert_eq!(
+            mesh(None, vec![[0, 1, 2], [2, 1, 3]]),
+            Some(MeshError::IndexOutOfRange {
+                face: 1,
+                index: 3,
+                positions: 3
+            })
+        );
+    }
+
+    #[test]
+    fn mesh_shared_edge_is_watertight() {
+        let mesh = TriangleMesh::new(
+            MeshData {
+                positions: vec![
+                    Vec3::new(-1.0, -1.0, -1.0),
+                    Vec3::new(1.0, -1.0, -1.0),
+                    Vec3::new(1.0, 1.0, -1.0),
+                    Vec3::new(-1.0, 1.0, -1.0),
+                ],
+                normals: None,
+                uvs: None,
+                indices: vec![[0, 1, 2], [0, 2, 3]],
+            },
+            test_material(),
+        )
+        .unwrap();
+
+        // Rays exactly along the shared diagonal must hit one of the two faces
+        for i in 1..16 {
+            let s = -1.0 + i as f32 / 8.0;
+            let ray = Ray::new(Vec3::new(s, s, 0.0), Vec3::FORWARD);
+            assert!(
+                mesh.hit(&ray, &Interval::UNIVERSE).is_some(),
+                "missed at {s}"
+            );
         }
+    }
 
-        BVHNode {
-            left,
-            right,
-            bounding_box,
+    /// Integrates `pdf_value` over uniformly distributed directions, which has to
+    /// give one wherever the light is visible from.
+    fn integrate_pdf(light: &dyn Hittable, origin: &Vec3) -> f32 {
+        integrate_pdf_at(light, origin, 0.0)
+    }
+
+    fn integrate_pdf_at(light: &dyn Hittable, origin: &Vec3, time: f32) -> f32 {
+        const SAMPLES: u32 = 200_000;
+        let sum: f32 = (0..SAMPLES)
+            .map(|_| light.pdf_value(origin, &utils::random_unit_vector(), time))
+            .sum();
+        sum / SAMPLES as f32 * 4.0 * PI
+    }
+
+    #[test]
+    fn light_pdfs_integrate_to_one() {
+        let quad = Quad::new(
+            Vec3::new(-1.0, 2.0, -1.0),
+            Vec3::RIGHT * 2.0,
+            Vec3::BACKWARD * 1.5,
+            test_material(),
+        );
+        assert_approx_eq!(integrate_pdf(&quad, &Vec3::ZERO), 1.0, 0.03);
+
+        let sphere = Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, test_material());
+        assert_approx_eq!(integrate_pdf(&sphere, &Vec3::ZERO), 1.0, 0.03);
+        assert_approx_eq!(
+            integrate_pdf(&sphere, &Vec3::new(0.0, 0.2, -3.0)),
+            1.0,
+            0.03
+        );
+    }
+
+    #[test]
+    fn moving_lights_are_sampled_where_they_are() {
+        let sphere = Sphere::moving(
+            Vec3::new(-2.0, 0.0, -3.0),
+            Vec3::new(2.0, 0.0, -3.0),
+            0.5,
+            test_material(),
+        );
+        assert_approx_eq!(integrate_pdf_at(&sphere, &Vec3::ZERO, 0.75), 1.0, 0.03);
+        for (time, u) in [(0.0, [0.2, 0.7]), (0.5, [0.9, 0.1]), (1.0, [0.5, 0.5])] {
+            let direction = sphere.random(&Vec3::ZERO, time, u);
+            let ray = Ray::with_time(Vec3::ZERO, direction, time);
+            assert!(sphere.hit(&ray, &SAMPLE_RANGE).is_some(), "{}", time);
+            assert!(sphere.pdf_value(&Vec3::ZERO, &direction, time) > 0.0);
         }
     }
 
-    pub fn from_hittable_list(hittable_list: &mut HittableList) -> BVHNode {
-        BVHNode::new(&mut hittable_list.objects)
+    #[test]
+    fn transformed_sphere() {
+        let sphere = Arc::new(Sphere::new(Vec3::ZERO, 1.0, test_material()));
+        let transform =
+            Mat4::translation(Vec3::new(0.0, 0.0, -5.0)) * Mat4::scale(Vec3::new(1.0, 2.0, 1.0));
+        let ellipsoid = Transformed::new(sphere, transform);
+
+        let bounds = ellipsoid.bounding_box();
+        assert_approx_eq!(bounds.y.start, -2.0);
+        assert_approx_eq!(bounds.z.end, -4.0);
+
+        let ray = Ray::new(Vec3::new(0.0, 10.0, -5.0), Vec3::DOWN);
+        let hit = ellipsoid.hit(&ray, &Interval::UNIVERSE).unwrap();
+        assert_approx_eq!(hit.t, 8.0);
+        assert_approx_eq!(hit.point.y, 2.0);
+        assert!(hit.front_face);
+
+        // Normals use the inverse transpose, not the transform itself
+        let ray = Ray::new(Vec3::new(0.0, 2f32.sqrt(), 0.0), Vec3::FORWARD);
+        let hit = ellipsoid.hit(&ray, &Interval::UNIVERSE).unwrap();
+        assert_approx_eq!(hit.point.z, -5.0 + 0.5f32.sqrt(), 1e-3);
+        let expected = Vec3::new(0.0, 0.5, 1.0).normalized();
+        assert_approx_eq!(hit.normal.y, expected.y, 1e-3);
+        assert_approx_eq!(hit.normal.z, expected.z, 1e-3);
     }
-}
 
-impl Hittable for BVHNode {
-    fn hit(&self, ray: &Ray, t_range: &Interval) -> Option<HitInfo> {
-        if !self.bounding_box.hit(ray, *t_range) {
-            return None;
+    #[test]
+    fn transformed_bounding_box_contains_rotated_corners() {
+        let quad = Arc::new(Quad::new(
+            Vec3::ZERO,
+            Vec3::RIGHT,
+            Vec3::UP,
+            test_material(),
+        ));
+        let rotated = Transformed::new(quad, Mat4::rotation(Vec3::BACKWARD, 45.0));
+        let bounds = rotated.bounding_box();
+        assert_approx_eq!(bounds.x.start, -0.5f32.sqrt());
+        assert_approx_eq!(bounds.x.end, 0.5f32.sqrt());
+        assert_approx_eq!(bounds.y.end, 2f32.sqrt());
+    }
+
+    #[test]
+    fn transformed_light_pdf_integrates_to_one() {
+        let quad = Arc::new(Quad::new(
+            Vec3::ZERO,
+            Vec3::RIGHT,
+            Vec3::BACKWARD,
+            test_material(),
+        ));
+        let transform = Mat4::translation(Vec3::new(-1.0, 2.0, 0.5))
+            * Mat4::rotation(Vec3::new(1.0, 0.0, 1.0), 20.0)
+            * Mat4::scale(Vec3::new(2.0, 1.0, 1.5));
+        let light = Transformed::new(quad, transform);
+        assert_approx_eq!(integrate_pdf(&light, &Vec3::ZERO), 1.0, 0.03);
+        let mut rng = utils::rng();
+        for _ in 0..100 {
+            let direction = light.random(&Vec3::ZERO, 0.0, [rng.gen(), rng.gen()]);
+            assert!(light.pdf_value(&Vec3::ZERO, &direction, 0.0) > 0.0);
         }
+    }
 
-        let left_hit = self.left.hit(ray, t_range);
-        match left_hit {
-            Some(left_hit) => {
-                let new_t_range = Interval::new(t_range.start, left_hit.t);
-                let hit_right = self.right.hit(ray, &new_t_range);
-                match hit_right {
-                    Some(hit_right) => Some(hit_right),
-                    None => Some(left_hit),
+    #[test]
+    fn cuboid_is_closed_with_outward_normals() {
+        let cuboid = cuboid(
+            &Vec3::new(1.0, 2.0, 3.0),
+            &Vec3::new(-1.0, 0.0, 0.0),
+            test_material(),
+        );
+        assert_eq!(cuboid.len(), 6);
+        let bounds = cuboid.bounding_box();
+        assert!(bounds.y.start <= 0.0 && bounds.y.start > -0.01);
+        assert!(bounds.y.end >= 2.0 && bounds.y.end < 2.01);
+
+        let center = Vec3::new(0.0, 1.0, 1.5);
+        for direction in [
+            Vec3::RIGHT,
+            Vec3::LEFT,
+            Vec3::UP,
+            Vec3::DOWN,
+            Vec3::FORWARD,
+            Vec3::BACKWARD,
+        ] {
+            // From outside towards the centre the hit is on the front face
+            let ray = Ray::new(center + direction * 10.0, -direction);
+            let hit = cuboid
+                .hit(&ray, &Interval::new(0.0, f32::INFINITY))
+                .unwrap();
+            assert!(hit.front_face);
+            assert_eq!(hit.normal, direction);
+
+            let ray = Ray::new(center, direction);
+            let hit = cuboid
+                .hit(&ray, &Interval::new(0.0, f32::INFINITY))
+                .unwrap();
+            assert!(!hit.front_face);
+        }
+    }
+
+    #[test]
+    fn moving_sphere_follows_time() {
+        let sphere = Sphere::moving(
+            Vec3::new(0.0, 0.0, -5.0),
+            Vec3::new(2.0, 0.0, -5.0),
+            0.5,
+            test_material(),
+        );
+        let bounds = sphere.bounding_box();
+        assert!(bounds.x.start <= -0.5 && bounds.x.end >= 2.5);
+
+        let ray_at =
+            |x: f32, time: f32| Ray::with_time(Vec3::new(x, 0.0, 0.0), Vec3::FORWARD, time);
+        let range = Interval::new(0.0, f32::INFINITY);
+        assert!(sphere.hit(&ray_at(0.0, 0.0), &range).is_some());
+        assert!(sphere.hit(&ray_at(0.0, 1.0), &range).is_none());
+        let hit = sphere.hit(&ray_at(1.0, 0.5), &range).unwrap();
+        assert_approx_eq!(hit.t, 4.5);
+        assert_eq!(hit.normal, Vec3::BACKWARD);
+    }
+
+    #[test]
+    fn animated_bounding_box_covers_the_motion() {
+        let object = Arc::new(cuboid(
+            &Vec3::ZERO,
+            &Vec3::new(1.0, 2.0, 0.5),
+            test_material(),
+        ));
+        let transform = AnimatedTransform::new(
+            Mat4::translation(Vec3::new(3.0, 0.0, 0.0)),
+            Mat4::translation(Vec3::new(-1.0, 1.0, 0.0))
+                * Mat4::rotation(Vec3::new(1.0, 1.0, 0.0), 160.0)
+                * Mat4::scale(Vec3::uniform(1.5)),
+        )
+        .unwrap();
+        let animated = Animated::new(object.clone(), transform);
+        let bounds = animated.bounding_box();
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let time = rng.gen::<f32>();
+            let local = Vec3::new(
+                rng.gen::<f32>(),
+                2.0 * rng.gen::<f32>(),
+                0.5 * rng.gen::<f32>(),
+            );
+            let p = transform.at(time).transform_point(&local);
+            for axis in 0..3 {
+                assert!(bounds[axis].contains(p[axis]), "{:?} at {}", p, time);
+            }
+        }
+
+        // Rays see the object where it is at their time
+        let ray = Ray::with_time(Vec3::new(3.5, 1.0, 5.0), Vec3::FORWARD, 0.0);
+        let hit = animated
+            .hit(&ray, &Interval::new(0.0, f32::INFINITY))
+            .unwrap();
+        assert_approx_eq!(hit.t, 4.5);
+        let ray = Ray::with_time(ray.origin, ray.direction, 1.0);
+        assert!(animated
+            .hit(&ray, &Interval::new(0.0, f32::INFINITY))
+            .is_none());
+    }
+
+    #[test]
+    fn constant_medium_transmittance() {
+        let boundary = Arc::new(cuboid(
+            &Vec3::new(-1.0, -1.0, -1.0),
+            &Vec3::ONE,
+            test_material(),
+        ));
+        let medium = ConstantMedium::new(boundary, 0.5, test_material());
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::FORWARD * 2.0);
+
+        let trials = 20000;
+        let mut passed = 0;
+        for _ in 0..trials {
+            match medium.hit(&ray, &Interval::new(0.001, f32::INFINITY)) {
+                Some(hit) => {
+                    assert!((-1.0..=1.0).contains(&hit.point.z));
+                    assert_approx_eq!(ray.at(hit.t).z, hit.point.z);
                 }
+                None => passed += 1,
+            }
+        }
+        // Beer-Lambert through two units of the medium
+        let transmittance = passed as f32 / trials as f32;
+        assert_approx_eq!(transmittance, (-0.5f32 * 2.0).exp(), 0.02);
+
+        // Rays starting inside only see the medium in front of them
+        let inside = Ray::new(Vec3::new(0.0, 0.0, 0.5), Vec3::BACKWARD);
+        for _ in 0..100 {
+            if let Some(hit) = medium.hit(&inside, &Interval::new(0.001, f32::INFINITY)) {
+                assert!(hit.point.z > 0.5 && hit.point.z <= 1.0);
             }
-            None => self.right.hit(ray, t_range),
         }
     }
 
-    fn bounding_box(&self) -> &AABB {
-        &self.bounding_box
+    #[test]
+    fn light_samples_point_at_the_light() {
+        let origin = Vec3::new(0.3, -1.0, 0.5);
+        let quad = Quad::new(Vec3::ZERO, Vec3::RIGHT, Vec3::BACKWARD, test_material());
+        let sphere = Sphere::new(Vec3::new(2.0, 1.0, 0.0), 0.5, test_material());
+        for _ in 0..100 {
+            assert!(quad.pdf_value(&origin, &quad.random(&origin, 0.0, [0.3, 0.6]), 0.0) > 0.0);
+            assert!(sphere.pdf_value(&origin, &sphere.random(&origin, 0.0, [0.8, 0.1]), 0.0) > 0.0);
+        }
     }
 }
@@ -0,0 +1,180 @@
+use std::io::{self, Write};
+
+use super::{zlib, ImageWriter};
+use crate::film::Film;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExrCompression {
+    None,
+    /// Deflate over blocks of 16 scanlines.
+    Zip,
+}
+
+impl ExrCompression {
+    fn id(&self) -> u8 {
+        match self {
+            ExrCompression::None => 0,
+            ExrCompression::Zip => 3,
+        }
+    }
+
+    fn lines_per_block(&self) -> u32 {
+        match self {
+            ExrCompression::None => 1,
+            ExrCompression::Zip => 16,
+        }
+    }
+}
+
+/// Scanline OpenEXR with 32-bit float RGB channels.
+pub struct ExrWriter {
+    pub compression: ExrCompression,
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn box2i(width: u32, height: u32) -> Vec<u8> {
+    [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+}
+
+impl ExrWriter {
+    fn header(&self, film: &Film) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&20000630u32.to_le_bytes()); // Magic number
+        header.extend_from_slice(&2u32.to_le_bytes()); // Version 2, single part scanline
+
+        // Channels have to be sorted by name
+        let mut channels = Vec::new();
+        for name in ["B", "G", "R"] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&2i32.to_le_bytes()); // FLOAT
+            channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear and reserved
+            channels.extend_from_slice(&1i32.to_le_bytes()); // x sampling
+            channels.extend_from_slice(&1i32.to_le_bytes()); // y sampling
+        }
+        channels.push(0);
+
+        write_attribute(&mut header, "channels", "chlist", &channels);
+        write_attribute(
+            &mut header,
+            "compression",
+            "compression",
+            &[self.compression.id()],
+        );
+        write_attribute(
+            &mut header,
+            "dataWindow",
+            "box2i",
+            &box2i(film.width, film.height),
+        );
+        write_attribute(
+            &mut header,
+            "displayWindow",
+            "box2i",
+            &box2i(film.width, film.height),
+        );
+        write_attribute(&mut header, "lineOrder", "lineOrder", &[0]); // Increasing y
+        write_attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        write_attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        header.push(0);
+
+        header
+    }
+
+    /// Uncompressed pixel data for scanlines `y0..y1`, one channel after the other per line.
+    fn block_data(film: &Film, y0: u32, y1: u32) -> Vec<u8> {
+        let mut data = Vec::with_capacity(((y1 - y0) * film.width * 12) as usize);
+        for y in y0..y1 {
+            for channel in [2, 1, 0] {
+                for x in 0..film.width {
+                    data.extend_from_slice(&film.pixel(x, y)[channel].to_le_bytes());
+                }
+            }
+        }
+        data
+    }
+}
+
+/// Byte interleaving and delta predictor applied before deflate, so that
+/// similar bytes of neighbouring values end up next to each other.
+fn zip_preprocess(data: &[u8]) -> Vec<u8> {
+    let half = data.len().div_ceil(2);
+    let mut reordered = vec![0u8; data.len()];
+    for (i, &byte) in data.iter().enumerate() {
+        let index = if i % 2 == 0 { i / 2 } else { half + i / 2 };
+        reordered[index] = byte;
+    }
+
+    let mut previous = reordered.first().copied().unwrap_or(0);
+    for value in reordered.iter_mut().skip(1) {
+        let current = *value;
+        *value = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+    reordered
+}
+
+impl ImageWriter for ExrWriter {
+    fn write(&self, film: &Film, out: &mut dyn Write) -> io::Result<()> {
+        let header = self.header(film);
+
+        let lines_per_block = self.compression.lines_per_block();
+        let mut blocks = Vec::new();
+        for y0 in (0..film.height).step_by(lines_per_block as usize) {
+            let y1 = (y0 + lines_per_block).min(film.height);
+            let raw = ExrWriter::block_data(film, y0, y1);
+            let data = match self.compression {
+                ExrCompression::None => raw,
+                ExrCompression::Zip => {
+                    let compressed = zlib::compress(&zip_preprocess(&raw));
+                    // Blocks that don't shrink are stored as is
+                    if compressed.len() < raw.len() {
+                        compressed
+                    } else {
+                        raw
+                    }
+                }
+            };
+            blocks.push((y0, data));
+        }
+
+        // The offset table points at each block from the start of the file
+        let mut offset = (header.len() + blocks.len() * 8) as u64;
+        let mut offsets = Vec::with_capacity(blocks.len() * 8);
+        for (_, data) in blocks.iter() {
+            offsets.extend_from_slice(&offset.to_le_bytes());
+            offset += 8 + data.len() as u64;
+        }
+
+        out.write_all(&header)?;
+        out.write_all(&offsets)?;
+        for (y, data) in blocks.iter() {
+            out.write_all(&(*y as i32).to_le_bytes())?;
+            out.write_all(&(data.len() as i32).to_le_bytes())?;
+            out.write_all(data)?;
+        }
+        Ok(())
+    }
+}
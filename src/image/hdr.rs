@@ -0,0 +1,290 @@
+use std::io::{self, Write};
+
+use super::{DecodedImage, ImageWriter};
+use crate::{film::Film, math::vec3::Vec3};
+
+/// Radiance RGBE (.hdr) with run-length encoded scanlines.
+pub struct HdrWriter;
+
+impl ImageWriter for HdrWriter {
+    fn write(&self, film: &Film, out: &mut dyn Write) -> io::Result<()> {
+        write!(
+            out,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            film.height, film.width
+        )?;
+
+        let width = film.width as usize;
+        let mut scanline: Vec<[u8; 4]> = Vec::with_capacity(width);
+        let mut encoded = Vec::with_capacity(width * 4);
+        for y in 0..film.height {
+            scanline.clear();
+            scanline.extend((0..film.width).map(|x| to_rgbe(&film.pixel(x, y))));
+
+            // The RLE scheme only supports widths in this range
+            if !(8..=0x7FFF).contains(&width) {
+                out.write_all(scanline.as_flattened())?;
+                continue;
+            }
+
+            encoded.clear();
+            encoded.extend_from_slice(&[2, 2, (width >> 8) as u8, width as u8]);
+            for channel in 0..4 {
+                let values: Vec<u8> = scanline.iter().map(|p| p[channel]).collect();
+                encode_rle(&values, &mut encoded);
+            }
+            out.write_all(&encoded)?;
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn to_rgbe(color: &Vec3) -> [u8; 4] {
+    let max = color.x.max(color.y).max(color.z);
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+
+    // max = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let mut exponent = max.log2().floor() as i32 + 1;
+    let mut mantissa = max / 2f32.powi(exponent);
+    if mantissa >= 1.0 {
+        mantissa /= 2.0;
+        exponent += 1;
+    }
+    let scale = mantissa * 256.0 / max;
+
+    [
+        (color.x.max(0.0) * scale) as u8,
+        (color.y.max(0.0) * scale) as u8,
+        (color.z.max(0.0) * scale) as u8,
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
+
+/// Encodes one channel of a scanline as runs (count > 128) and literal dumps.
+fn encode_rle(values: &[u8], out: &mut Vec<u8>) {
+    const MIN_RUN: usize = 4;
+
+    let mut pos = 0;
+    while pos < values.len() {
+        // Find the next run long enough to be worth encoding
+        let mut run_start = pos;
+        let mut run_length = 0;
+        while run_start < values.len() {
+            run_length = values[run_start..]
+                .iter()
+                .take(127)
+                .take_while(|&&v| v == values[run_start])
+                .count();
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+        }
+
+        // Dump everything before the run as literals
+        while pos < run_start {
+            let count = (run_start - pos).min(128);
+            out.push(count as u8);
+            out.extend_from_slice(&values[pos..pos + count]);
+            pos += count;
+        }
+
+        if run_length >= MIN_RUN && run_start < values.len() {
+            out.push(128 + run_length as u8);
+            out.push(values[run_start]);
+            pos = run_start + run_length;
+        }
+    }
+}
+
+pub(super) fn from_rgbe(rgbe: [u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.0; 3];
+    }
+    // Offset by half a step to undo the truncation of the encoder
+    let scale = 2f32.powi(rgbe[3] as i32 - 136);
+    [
+        (rgbe[0] as f32 + 0.5) * scale,
+        (rgbe[1] as f32 + 0.5) * scale,
+        (rgbe[2] as f32 + 0.5) * scale,
+    ]
+}
+
+/// Reads one scanline in any of the flat, old run-length or new run-length
+/// encodings, returning the position after it.
+fn decode_scanline(data: &[u8], mut pos: usize, scanline: &mut [[u8; 4]]) -> Result<usize, String> {
+    let truncated = || String::from("truncated HDR file");
+    let width = scanline.len();
+    let start = data.get(pos..pos + 4).ok_or_else(truncated)?;
+
+    let is_new_rle =
+        (8..=0x7FFF).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0;
+    if is_new_rle {
+        if ((start[2] as usize) << 8 | start[3] as usize) != width {
+            return Err(String::from("HDR scanline width mismatch"));
+        }
+        pos += 4;
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = *data.get(pos).ok_or_else(truncated)? as usize;
+                pos += 1;
+                if count > 128 {
+                    let count = count - 128;
+                    let value = *data.get(pos).ok_or_else(truncated)?;
+                    pos += 1;
+                    for pixel in scanline.get_mut(x..x + count).ok_or_else(truncated)? {
+                        pixel[channel] = value;
+                    }
+                    x += count;
+                } else {
+                    if count == 0 {
+                        return Err(String::from("invalid HDR run length"));
+                    }
+                    let values = data.get(pos..pos + count).ok_or_else(truncated)?;
+                    let pixels = scanline.get_mut(x..x + count).ok_or_else(truncated)?;
+                    for (pixel, &value) in pixels.iter_mut().zip(values) {
+                        pixel[channel] = value;
+                    }
+                    pos += count;
+                    x += count;
+                }
+            }
+        }
+        return Ok(pos);
+    }
+
+    // Flat pixels, where (1, 1, 1, n) repeats the previous pixel
+    let mut x = 0;
+    let mut shift = 0;
+    while x < width {
+        let pixel: [u8; 4] = data
+            .get(pos..pos + 4)
+            .ok_or_else(truncated)?
+            .try_into()
+            .unwrap();
+        pos += 4;
+        if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 && x > 0 {
+            let count = (pixel[3] as usize) << shift;
+            let previous = scanline[x - 1];
+            for value in scanline.get_mut(x..x + count).ok_or_else(truncated)? {
+                *value = previous;
+            }
+            x += count;
+            shift += 8;
+        } else {
+            scanline[x] = pixel;
+            x += 1;
+            shift = 0;
+        }
+    }
+    Ok(pos)
+}
+
+/// Decodes a Radiance RGBE image with the standard `-Y` or `+Y` and `+X` orientation.
+pub(super) fn decode(data: &[u8]) -> Result<DecodedImage, String> {
+    let mut lines = data.split(|&c| c == b'\n');
+    let mut pos = 0;
+    let mut next_line = || {
+        let line = lines.next()?;
+        pos += line.len() + 1;
+        Some(String::from_utf8_lossy(line).trim().to_string())
+    };
+
+    loop {
+        let line = next_line().ok_or_else(|| String::from("truncated HDR header"))?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(format!("unsupported HDR format '{}'", format));
+            }
+        }
+    }
+
+    let resolution = next_line().ok_or_else(|| String::from("missing HDR resolution"))?;
+    let (flip, height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", height, "+X", width] => (false, height, width),
+        ["+Y", height, "+X", width] => (true, height, width),
+        _ => return Err(format!("unsupported HDR resolution line '{}'", resolution)),
+    };
+    let invalid = || String::from("invalid HDR resolution");
+    let width: u32 = width.parse().map_err(|_| invalid())?;
+    let height: u32 = height.parse().map_err(|_| invalid())?;
+
+    let mut samples = vec![0.0; (width * height * 3) as usize];
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for row in 0..height {
+        pos = decode_scanline(data, pos, &mut scanline)?;
+        let y = if flip { height - 1 - row } else { row };
+        let start = (y * width * 3) as usize;
+        for (out, &rgbe) in samples[start..start + width as usize * 3]
+            .chunks_exact_mut(3)
+            .zip(scanline.iter())
+        {
+            out.copy_from_slice(&from_rgbe(rgbe));
+        }
+    }
+
+    Ok(DecodedImage {
+        width,
+        height,
+        channels: 3,
+        samples,
+        is_float: true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgbe_round_trips_within_precision() {
+        for color in [
+            Vec3::new(1.0, 0.5, 0.25),
+            Vec3::new(15.0, 15.0, 15.0),
+            Vec3::new(0.001, 0.002, 0.0),
+        ] {
+            let [r, g, b, e] = to_rgbe(&color);
+            let scale = 2f32.powi(e as i32 - 136);
+            let decoded = Vec3::new(r as f32, g as f32, b as f32) * scale;
+            let max = color.x.max(color.y).max(color.z);
+            assert!((decoded - color).abs().x <= max / 128.0);
+            assert!((decoded - color).abs().y <= max / 128.0);
+            assert!((decoded - color).abs().z <= max / 128.0);
+        }
+        assert_eq!(to_rgbe(&Vec3::ZERO), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rle_mixes_runs_and_literals() {
+        let values = [1, 2, 3, 7, 7, 7, 7, 7, 4];
+        let mut out = Vec::new();
+        encode_rle(&values, &mut out);
+        assert_eq!(out, [3, 1, 2, 3, 133, 7, 1, 4]);
+    }
+
+    #[test]
+    fn decode_round_trip() {
+        for width in [5, 40] {
+            let mut film = Film::new(width, 3);
+            for (i, pixel) in film.pixels.iter_mut().enumerate() {
+                *pixel = Vec3::new((i / 4) as f32, 0.5, (i % 3) as f32 * 0.1);
+            }
+            let mut encoded = Vec::new();
+            HdrWriter.write(&film, &mut encoded).unwrap();
+
+            let decoded = decode(&encoded).unwrap();
+            assert_eq!((decoded.width, decoded.height), (width, 3));
+            for (pixel, samples) in film.pixels.iter().zip(decoded.samples.chunks(3)) {
+                let max = pixel.x.max(pixel.y).max(pixel.z);
+                assert!((pixel.x - samples[0]).abs() <= max / 128.0);
+                assert!((pixel.z - samples[2]).abs() <= max / 128.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,778 @@
+//! JPEG decoder for baseline and progressive DCT images with Huffman coding and
+//! 8-bit samples, which covers what cameras and image editors write.
+
+use std::f32::consts::PI;
+
+use super::DecodedImage;
+
+/// Natural (row major) index of each coefficient in zigzag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+struct HuffmanTable {
+    /// Largest code of each length, -1 when there is none.
+    max_code: [i32; 17],
+    /// Added to a code of each length to get its index in `values`.
+    value_offset: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    fn new(counts: &[u8], values: &[u8]) -> HuffmanTable {
+        let mut max_code = [-1; 17];
+        let mut value_offset = [0; 17];
+        let mut code = 0i32;
+        let mut index = 0i32;
+        for length in 1..=16 {
+            let count = counts[length - 1] as i32;
+            value_offset[length] = index - code;
+            code += count;
+            index += count;
+            if count > 0 {
+                max_code[length] = code - 1;
+            }
+            code <<= 1;
+        }
+        HuffmanTable {
+            max_code,
+            value_offset,
+            values: values.to_vec(),
+        }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u8, String> {
+        let mut code = 0i32;
+        for length in 1..=16 {
+            code = code << 1 | reader.bits(1) as i32;
+            if code <= self.max_code[length] {
+                return self
+                    .values
+                    .get((code + self.value_offset[length]) as usize)
+                    .copied()
+                    .ok_or_else(|| String::from("invalid JPEG Huffman code"));
+            }
+        }
+        Err(String::from("invalid JPEG Huffman code"))
+    }
+}
+
+/// Reads entropy coded data most significant bit first, removing stuffed zero
+/// bytes and padding with zeros once a marker is reached.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u64,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn fill(&mut self) {
+        while self.count <= 56 {
+            let byte = match self.data.get(self.pos) {
+                Some(0xFF) => match self.data.get(self.pos + 1) {
+                    Some(0x00) => {
+                        self.pos += 2;
+                        0xFF
+                    }
+                    _ => 0,
+                },
+                Some(&byte) => {
+                    self.pos += 1;
+                    byte
+                }
+                None => 0,
+            };
+            self.bits |= (byte as u64) << (56 - self.count);
+            self.count += 8;
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        if self.count < count {
+            self.fill();
+        }
+        let value = (self.bits >> (64 - count)) as u32;
+        self.bits <<= count;
+        self.count -= count;
+        value
+    }
+
+    /// Reads a `size` bit magnitude category value as a signed number.
+    fn receive_extend(&mut self, size: u8) -> i32 {
+        let value = self.bits(size as u32) as i32;
+        if size > 0 && value < 1 << (size - 1) {
+            value - (1 << size) + 1
+        } else {
+            value
+        }
+    }
+
+    /// Drops the buffered bits and skips the restart marker that should follow.
+    fn restart(&mut self) -> Result<(), String> {
+        self.bits = 0;
+        self.count = 0;
+        while self.data.get(self.pos) == Some(&0xFF) && self.data.get(self.pos + 1) == Some(&0xFF) {
+            self.pos += 1;
+        }
+        match self.data.get(self.pos..self.pos + 2) {
+            Some([0xFF, 0xD0..=0xD7]) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err(String::from("missing JPEG restart marker")),
+        }
+    }
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quant_table: usize,
+    /// Quantised coefficients of every block in zigzag order, padded to whole MCUs.
+    blocks: Vec<[i32; 64]>,
+    blocks_per_line: usize,
+    dc_prediction: i32,
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+    max_h: usize,
+    max_v: usize,
+    mcus_x: usize,
+    mcus_y: usize,
+}
+
+struct Decoder {
+    quant_tables: [[u16; 64]; 4],
+    dc_tables: [Option<HuffmanTable>; 4],
+    ac_tables: [Option<HuffmanTable>; 4],
+    restart_interval: usize,
+    frame: Option<Frame>,
+    /// Set by an Adobe APP14 segment that says the components are RGB.
+    is_rgb: bool,
+    /// `IDCT[x][u]`, the basis function scaled for the separable inverse DCT.
+    idct: [[f32; 8]; 8],
+}
+
+impl Decoder {
+    fn new() -> Decoder {
+        let mut idct = [[0.0; 8]; 8];
+        for (x, row) in idct.iter_mut().enumerate() {
+            for (u, value) in row.iter_mut().enumerate() {
+                let c = if u == 0 { 1.0 / 2f32.sqrt() } else { 1.0 };
+                *value = c / 2.0 * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos();
+            }
+        }
+        Decoder {
+            quant_tables: [[0; 64]; 4],
+            dc_tables: [None, None, None, None],
+            ac_tables: [None, None, None, None],
+            restart_interval: 0,
+            frame: None,
+            is_rgb: false,
+            idct,
+        }
+    }
+
+    fn read_quant_tables(&mut self, mut data: &[u8]) -> Result<(), String> {
+        while let [info, rest @ ..] = data {
+            let precision = info >> 4;
+            let table = (info & 0x0F) as usize;
+            let size = if precision == 0 { 64 } else { 128 };
+            if table > 3 || rest.len() < size {
+                return Err(String::from("invalid JPEG quantization table"));
+            }
+            for (k, value) in self.quant_tables[table].iter_mut().enumerate() {
+                *value = if precision == 0 {
+                    rest[k] as u16
+                } else {
+                    u16::from_be_bytes([rest[2 * k], rest[2 * k + 1]])
+                };
+            }
+            data = &rest[size..];
+        }
+        Ok(())
+    }
+
+    fn read_huffman_tables(&mut self, mut data: &[u8]) -> Result<(), String> {
+        while data.len() >= 17 {
+            let class = data[0] >> 4;
+            let index = (data[0] & 0x0F) as usize;
+            let counts = &data[1..17];
+            let total: usize = counts.iter().map(|&c| c as usize).sum();
+            let values = data
+                .get(17..17 + total)
+                .ok_or_else(|| String::from("truncated JPEG Huffman table"))?;
+            if index > 3 || class > 1 {
+                return Err(String::from("invalid JPEG Huffman table"));
+            }
+
+            let table = Some(HuffmanTable::new(counts, values));
+            if class == 0 {
+                self.dc_tables[index] = table;
+            } else {
+                self.ac_tables[index] = table;
+            }
+            data = &data[17 + total..];
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self, data: &[u8]) -> Result<(), String> {
+        let invalid = || String::from("invalid JPEG frame header");
+        let [precision, h1, h0, w1, w0, count, rest @ ..] = data else {
+            return Err(invalid());
+        };
+        if *precision != 8 {
+            return Err(format!("unsupported JPEG sample precision {}", precision));
+        }
+        let width = u16::from_be_bytes([*w1, *w0]) as usize;
+        let height = u16::from_be_bytes([*h1, *h0]) as usize;
+        if width == 0 || height == 0 {
+            return Err(String::from(
+                "JPEG images without a height are not supported",
+            ));
+        }
+        if *count != 1 && *count != 3 {
+            return Err(format!("unsupported JPEG component count {}", count));
+        }
+
+        let mut components = Vec::with_capacity(*count as usize);
+        for spec in rest.chunks_exact(3).take(*count as usize) {
+            let h = (spec[1] >> 4) as usize;
+            let v = (spec[1] & 0x0F) as usize;
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) || spec[2] > 3 {
+                return Err(invalid());
+            }
+            components.push(Component {
+                id: spec[0],
+                h,
+                v,
+                quant_table: spec[2] as usize,
+                blocks: Vec::new(),
+                blocks_per_line: 0,
+                dc_prediction: 0,
+            });
+        }
+        if components.len() != *count as usize {
+            return Err(invalid());
+        }
+
+        let max_h = components.iter().map(|c| c.h).max().unwrap();
+        let max_v = components.iter().map(|c| c.v).max().unwrap();
+        let mcus_x = width.div_ceil(8 * max_h);
+        let mcus_y = height.div_ceil(8 * max_v);
+        for component in components.iter_mut() {
+            component.blocks_per_line = mcus_x * component.h;
+            component.blocks = vec![[0; 64]; component.blocks_per_line * mcus_y * component.v];
+        }
+
+        self.frame = Some(Frame {
+            width,
+            height,
+            components,
+            max_h,
+            max_v,
+            mcus_x,
+            mcus_y,
+        });
+        Ok(())
+    }
+
+    /// Decodes one scan, returning the position of the marker that ends it.
+    fn read_scan(&mut self, header: &[u8], data: &[u8], start: usize) -> Result<usize, String> {
+        let invalid = || String::from("invalid JPEG scan header");
+        let frame = self
+            .frame
+            .as_mut()
+            .ok_or_else(|| String::from("JPEG scan before the frame header"))?;
+        let count = *header.first().ok_or_else(invalid)? as usize;
+        let [spectral_start, spectral_end, approximation] = *header
+            .get(1 + 2 * count..4 + 2 * count)
+            .ok_or_else(invalid)?
+        else {
+            unreachable!()
+        };
+        let params = ScanParams {
+            spectral_start: spectral_start as usize,
+            spectral_end: spectral_end as usize,
+            high_bit: approximation >> 4,
+            low_bit: approximation & 0x0F,
+        };
+        // Bands of AC coefficients are only ever sent one component at a time
+        if params.spectral_end > 63
+            || params.spectral_start > params.spectral_end
+            || (params.spectral_start > 0 && count > 1)
+        {
+            return Err(invalid());
+        }
+
+        // (component index, DC table, AC table) for each component in the scan
+        let mut scan = Vec::with_capacity(count);
+        for spec in header[1..].chunks_exact(2).take(count) {
+            let index = frame
+                .components
+                .iter()
+                .position(|c| c.id == spec[0])
+                .ok_or_else(invalid)?;
+            let dc = self.dc_tables[(spec[1] >> 4) as usize & 3].as_ref();
+            let ac = self.ac_tables[(spec[1] & 0x0F) as usize & 3].as_ref();
+            if params.spectral_start == 0 && params.high_bit == 0 && dc.is_none() {
+                return Err(String::from("missing JPEG DC table"));
+            }
+            if params.spectral_end > 0 && ac.is_none() {
+                return Err(String::from("missing JPEG AC table"));
+            }
+            scan.push((index, dc, ac));
+        }
+        if scan.len() != count || count == 0 {
+            return Err(invalid());
+        }
+        for component in frame.components.iter_mut() {
+            component.dc_prediction = 0;
+        }
+
+        // A single component scan isn't interleaved, its MCU is one block and
+        // only covers the component's own size
+        let (mcus_x, mcus_y) = if count == 1 {
+            let component = &frame.components[scan[0].0];
+            let width = (frame.width * component.h).div_ceil(frame.max_h);
+            let height = (frame.height * component.v).div_ceil(frame.max_v);
+            (width.div_ceil(8), height.div_ceil(8))
+        } else {
+            (frame.mcus_x, frame.mcus_y)
+        };
+
+        let mut reader = BitReader {
+            data,
+            pos: start,
+            bits: 0,
+            count: 0,
+        };
+        let mut eob_run = 0;
+        let total_mcus = mcus_x * mcus_y;
+        for mcu in 0..total_mcus {
+            let (mcu_x, mcu_y) = (mcu % mcus_x, mcu / mcus_x);
+            for &(index, dc_table, ac_table) in scan.iter() {
+                let component = &mut frame.components[index];
+                let (blocks_h, blocks_v) = if count == 1 {
+                    (1, 1)
+                } else {
+                    (component.h, component.v)
+                };
+                for by in 0..blocks_v {
+                    for bx in 0..blocks_h {
+                        let x = mcu_x * blocks_h + bx;
+                        let y = mcu_y * blocks_v + by;
+                        let block = &mut component.blocks[y * component.blocks_per_line + x];
+                        if params.spectral_start == 0 {
+                            decode_dc(
+                                &mut reader,
+                                dc_table,
+                                &params,
+                                &mut component.dc_prediction,
+                                block,
+                            )?;
+                        }
+                        if params.spectral_end > 0 {
+                            let ac_table = ac_table.unwrap();
+                            if params.high_bit == 0 {
+                                decode_ac_first(
+                                    &mut reader,
+                                    ac_table,
+                                    &params,
+                                    &mut eob_run,
+                                    block,
+                                )?;
+                            } else {
+                                decode_ac_refine(
+                                    &mut reader,
+                                    ac_table,
+                                    &params,
+                                    &mut eob_run,
+                                    block,
+                                )?;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let is_last = mcu + 1 == total_mcus;
+            if self.restart_interval > 0 && (mcu + 1) % self.restart_interval == 0 && !is_last {
+                reader.restart()?;
+                eob_run = 0;
+                for component in frame.components.iter_mut() {
+                    component.dc_prediction = 0;
+                }
+            }
+        }
+
+        // Skip to the next marker
+        let mut pos = reader.pos;
+        while pos + 1 < data.len()
+            && !(data[pos] == 0xFF && data[pos + 1] != 0 && !(0xD0..=0xD7).contains(&data[pos + 1]))
+        {
+            pos += 1;
+        }
+        Ok(pos)
+    }
+
+    /// Dequantises and transforms every block into a plane of samples, returning
+    /// the plane and its width.
+    fn component_plane(&self, component: &Component) -> (Vec<f32>, usize) {
+        let plane_width = component.blocks_per_line * 8;
+        let mut plane = vec![0.0; component.blocks.len() * 64];
+        let quant_table = &self.quant_tables[component.quant_table];
+        let mut coefficients = [0.0f32; 64];
+        for (i, block) in component.blocks.iter().enumerate() {
+            for k in 0..64 {
+                coefficients[ZIGZAG[k]] = (block[k] * quant_table[k] as i32) as f32;
+            }
+            let x = i % component.blocks_per_line * 8;
+            let y = i / component.blocks_per_line * 8;
+            inverse_dct(&self.idct, &coefficients, &mut plane, plane_width, x, y);
+        }
+        (plane, plane_width)
+    }
+
+    fn into_image(self) -> Result<DecodedImage, String> {
+        let frame = self
+            .frame
+            .as_ref()
+            .ok_or_else(|| String::from("JPEG without a frame"))?;
+        let planes: Vec<_> = frame
+            .components
+            .iter()
+            .map(|component| self.component_plane(component))
+            .collect();
+
+        // Each component's samples sit at the centre of the area they cover,
+        // upsampled components are interpolated bilinearly
+        let sample = |index: usize, x: usize, y: usize| {
+            let component = &frame.components[index];
+            let (plane, plane_width) = (&planes[index].0, planes[index].1);
+            if component.h == frame.max_h && component.v == frame.max_v {
+                return plane[y * plane_width + x];
+            }
+            let scale_x = component.h as f32 / frame.max_h as f32;
+            let scale_y = component.v as f32 / frame.max_v as f32;
+            let plane_height = plane.len() / plane_width;
+            let fx = ((x as f32 + 0.5) * scale_x - 0.5).max(0.0);
+            let fy = ((y as f32 + 0.5) * scale_y - 0.5).max(0.0);
+            let x0 = (fx as usize).min(plane_width - 1);
+            let y0 = (fy as usize).min(plane_height - 1);
+            let x1 = (x0 + 1).min(plane_width - 1);
+            let y1 = (y0 + 1).min(plane_height - 1);
+            let tx = fx - x0 as f32;
+            let ty = fy - y0 as f32;
+            let at = |x: usize, y: usize| plane[y * plane_width + x];
+            let top = at(x0, y0) * (1.0 - tx) + at(x1, y0) * tx;
+            let bottom = at(x0, y1) * (1.0 - tx) + at(x1, y1) * tx;
+            top * (1.0 - ty) + bottom * ty
+        };
+
+        let channels = frame.components.len();
+        let mut samples = Vec::with_capacity(frame.width * frame.height * channels);
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                if channels == 1 {
+                    samples.push(sample(0, x, y) / 255.0);
+                    continue;
+                }
+                let [c0, c1, c2] = [0, 1, 2].map(|i| sample(i, x, y));
+                let rgb = if self.is_rgb {
+                    [c0, c1, c2]
+                } else {
+                    // JFIF YCbCr
+                    let (cb, cr) = (c1 - 128.0, c2 - 128.0);
+                    [
+                        c0 + 1.402 * cr,
+                        c0 - 0.344136 * cb - 0.714136 * cr,
+                        c0 + 1.772 * cb,
+                    ]
+                };
+                samples.extend(rgb.map(|c| c.round().clamp(0.0, 255.0) / 255.0));
+            }
+        }
+
+        Ok(DecodedImage {
+            width: frame.width as u32,
+            height: frame.height as u32,
+            channels,
+            samples,
+            is_float: false,
+        })
+    }
+}
+
+/// Spectral selection and successive approximation of a scan. Baseline
+/// scans cover all coefficients at full precision.
+struct ScanParams {
+    spectral_start: usize,
+    spectral_end: usize,
+    /// Bit position of the previous scan of these coefficients, zero for the first.
+    high_bit: u8,
+    /// Bit position this scan refines down to.
+    low_bit: u8,
+}
+
+fn decode_dc(
+    reader: &mut BitReader,
+    table: Option<&HuffmanTable>,
+    params: &ScanParams,
+    prediction: &mut i32,
+    block: &mut [i32; 64],
+) -> Result<(), String> {
+    if params.high_bit > 0 {
+        // Refinement adds one more bit
+        if reader.bits(1) == 1 {
+            block[0] |= 1 << params.low_bit;
+        }
+        return Ok(());
+    }
+
+    let size = table.unwrap().decode(reader)?;
+    if size > 11 {
+        return Err(String::from("invalid JPEG DC coefficient"));
+    }
+    *prediction += reader.receive_extend(size);
+    block[0] = *prediction * (1 << params.low_bit);
+    Ok(())
+}
+
+/// Reads the length of a run of blocks that have no more coefficients in this band.
+fn read_eob_run(reader: &mut BitReader, run: u8) -> usize {
+    (1 << run) + reader.bits(run as u32) as usize
+}
+
+fn decode_ac_first(
+    reader: &mut BitReader,
+    table: &HuffmanTable,
+    params: &ScanParams,
+    eob_run: &mut usize,
+    block: &mut [i32; 64],
+) -> Result<(), String> {
+    if *eob_run > 0 {
+        *eob_run -= 1;
+        return Ok(());
+    }
+
+    let mut k = params.spectral_start.max(1);
+    while k <= params.spectral_end {
+        let symbol = table.decode(reader)?;
+        let run = symbol >> 4;
+        let size = symbol & 0x0F;
+        if size == 0 {
+            if run == 15 {
+                // Sixteen zeros
+                k += 16;
+                continue;
+            }
+            // End of band, in this and possibly the following blocks
+            *eob_run = read_eob_run(reader, run) - 1;
+            break;
+        }
+        k += run as usize;
+        if k > params.spectral_end {
+            return Err(String::from("JPEG coefficient index out of range"));
+        }
+        block[k] = reader.receive_extend(size) * (1 << params.low_bit);
+        k += 1;
+    }
+    Ok(())
+}
+
+/// Adds a correction bit to an already non-zero coefficient.
+fn refine_coefficient(reader: &mut BitReader, coefficient: &mut i32, bit: i32) {
+    if reader.bits(1) == 1 && *coefficient & bit == 0 {
+        *coefficient += if *coefficient >= 0 { bit } else { -bit };
+    }
+}
+
+/// Successive approximation of AC coefficients, following the libjpeg decoder.
+fn decode_ac_refine(
+    reader: &mut BitReader,
+    table: &HuffmanTable,
+    params: &ScanParams,
+    eob_run: &mut usize,
+    block: &mut [i32; 64],
+) -> Result<(), String> {
+    let bit = 1 << params.low_bit;
+    let mut k = params.spectral_start;
+
+    if *eob_run == 0 {
+        while k <= params.spectral_end {
+            let symbol = table.decode(reader)?;
+            let mut run = (symbol >> 4) as i32;
+            let size = symbol & 0x0F;
+            let mut value = 0;
+            if size == 0 {
+                if run != 15 {
+                    *eob_run = read_eob_run(reader, run as u8);
+                    break;
+                }
+            } else {
+                // Newly non-zero coefficients are always one bit
+                value = if reader.bits(1) == 1 { bit } else { -bit };
+            }
+
+            // Skip `run` zero coefficients, refining the non-zero ones on the way
+            while k <= params.spectral_end {
+                if block[k] != 0 {
+                    refine_coefficient(reader, &mut block[k], bit);
+                } else {
+                    if run == 0 {
+                        break;
+                    }
+                    run -= 1;
+                }
+                k += 1;
+            }
+            if value != 0 {
+                if k > params.spectral_end {
+                    return Err(String::from("JPEG coefficient index out of range"));
+                }
+                block[k] = value;
+            }
+            k += 1;
+        }
+    }
+
+    if *eob_run > 0 {
+        while k <= params.spectral_end {
+            if block[k] != 0 {
+                refine_coefficient(reader, &mut block[k], bit);
+            }
+            k += 1;
+        }
+        *eob_run -= 1;
+    }
+    Ok(())
+}
+
+/// Transforms a block of dequantised coefficients into `plane` at `(x, y)`.
+fn inverse_dct(
+    idct: &[[f32; 8]; 8],
+    coefficients: &[f32; 64],
+    plane: &mut [f32],
+    plane_width: usize,
+    x: usize,
+    y: usize,
+) {
+    // Rows first, then columns
+    let mut rows = [[0.0f32; 8]; 8];
+    for v in 0..8 {
+        for (px, basis) in idct.iter().enumerate() {
+            rows[v][px] = (0..8).map(|u| basis[u] * coefficients[v * 8 + u]).sum();
+        }
+    }
+    for (py, basis) in idct.iter().enumerate() {
+        let row_start = (y + py) * plane_width + x;
+        for px in 0..8 {
+            let value: f32 = (0..8).map(|v| basis[v] * rows[v][px]).sum();
+            plane[row_start + px] = (value + 128.0).clamp(0.0, 255.0);
+        }
+    }
+}
+
+pub(super) fn decode(data: &[u8]) -> Result<DecodedImage, String> {
+    let mut decoder = Decoder::new();
+    let truncated = || String::from("truncated JPEG file");
+
+    let mut pos = 2;
+    loop {
+        // Markers may be preceded by any number of fill bytes
+        if data.get(pos) != Some(&0xFF) {
+            return Err(String::from("expected a JPEG marker"));
+        }
+        while data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        let marker = *data.get(pos + 1).ok_or_else(truncated)?;
+        pos += 2;
+
+        match marker {
+            0xD8 | 0xD0..=0xD7 | 0x01 => continue,
+            0xD9 => break,
+            _ => (),
+        }
+
+        let length = u16::from_be_bytes(
+            data.get(pos..pos + 2)
+                .ok_or_else(truncated)?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let segment = data
+            .get(pos + 2..pos + length.max(2))
+            .ok_or_else(truncated)?;
+        pos += length;
+
+        match marker {
+            0xDB => decoder.read_quant_tables(segment)?,
+            0xC4 => decoder.read_huffman_tables(segment)?,
+            0xC0..=0xC2 => decoder.read_frame(segment)?,
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                return Err(String::from("unsupported JPEG coding process"))
+            }
+            0xDD => {
+                let interval = segment.get(0..2).ok_or_else(truncated)?;
+                decoder.restart_interval = u16::from_be_bytes([interval[0], interval[1]]) as usize;
+            }
+            0xEE if segment.starts_with(b"Adobe") => {
+                // Transform flag 0 means the components are stored as RGB
+                decoder.is_rgb = segment.get(11) == Some(&0);
+            }
+            0xDA => {
+                pos = decoder.read_scan(segment, data, pos)?;
+                if pos + 1 >= data.len() {
+                    break;
+                }
+            }
+            // Application data and comments
+            _ => (),
+        }
+    }
+
+    decoder.into_image()
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+
+    #[test]
+    fn decode_single_block() {
+        // 8x8 grey image with one DC coefficient, with single code Huffman tables
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xDB, 0x00, 0x43, 0x00];
+        data.extend([8; 64]);
+        data.extend([0xFF, 0xC0, 0x00, 0x0B, 8, 0, 8, 0, 8, 1, 1, 0x11, 0]);
+        for (class, symbol) in [(0x00, 3), (0x10, 0)] {
+            data.extend([0xFF, 0xC4, 0x00, 0x14, class, 1]);
+            data.extend([0; 15]);
+            data.push(symbol);
+        }
+        data.extend([0xFF, 0xDA, 0x00, 0x08, 1, 1, 0x00, 0, 63, 0]);
+        // DC size 3, value 5, end of block
+        data.extend([0b0101_0111, 0xFF, 0xD9]);
+
+        let image = decode(&data).unwrap();
+        assert_eq!((image.width, image.height, image.channels), (8, 8, 1));
+        // The DC basis function is 1/8, 5 * 8 / 8 above mid grey
+        for sample in image.samples {
+            assert_approx_eq!(sample * 255.0, 133.0, 1e-3);
+        }
+    }
+}
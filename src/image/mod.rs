@@ -0,0 +1,210 @@
+//! Image file encoders for the final render and decoders for textures. HDR
+//! formats store the film's linear radiance, 8-bit formats a display-ready view
+//! of it.
+
+mod exr;
+mod hdr;
+mod jpeg;
+mod pfm;
+mod png;
+mod ppm;
+pub mod zlib;
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    film::Film,
+    math::vec3::Vec3,
+    tonemap::{self, DisplayTransform},
+};
+
+pub use exr::{ExrCompression, ExrWriter};
+pub use hdr::HdrWriter;
+pub use pfm::PfmWriter;
+pub use png::PngWriter;
+pub use ppm::{PpmAsciiWriter, PpmWriter};
+
+pub trait ImageWriter {
+    fn write(&self, film: &Film, out: &mut dyn Write) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    PpmAscii,
+    Pfm,
+    Hdr,
+    Exr(ExrCompression),
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "ppm-ascii" => Some(ImageFormat::PpmAscii),
+            "pfm" => Some(ImageFormat::Pfm),
+            "hdr" => Some(ImageFormat::Hdr),
+            "exr" => Some(ImageFormat::Exr(ExrCompression::Zip)),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        ImageFormat::from_name(&path.extension()?.to_string_lossy())
+    }
+
+    /// `display` is used by the 8-bit formats, HDR formats store linear values.
+    pub fn writer(&self, display: DisplayTransform) -> Box<dyn ImageWriter> {
+        match self {
+            ImageFormat::Png => Box::new(PngWriter { display }),
+            ImageFormat::Ppm => Box::new(PpmWriter { display }),
+            ImageFormat::PpmAscii => Box::new(PpmAsciiWriter { display }),
+            ImageFormat::Pfm => Box::new(PfmWriter),
+            ImageFormat::Hdr => Box::new(HdrWriter),
+            ImageFormat::Exr(compression) => Box::new(ExrWriter {
+                compression: *compression,
+            }),
+        }
+    }
+}
+
+pub fn save(
+    film: &Film,
+    filepath: &Path,
+    format: ImageFormat,
+    display: DisplayTransform,
+) -> io::Result<()> {
+    let parent_dir = filepath.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(parent_dir)?;
+
+    let mut file = BufWriter::new(File::create(filepath)?);
+    format.writer(display).write(film, &mut file)?;
+    file.flush()
+}
+
+/// How the values of 8-bit (and 16-bit) images are encoded. Float formats are
+/// always linear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+impl ColorSpace {
+    pub const NAMES: [&'static str; 2] = ["srgb", "linear"];
+
+    pub fn from_name(name: &str) -> Option<ColorSpace> {
+        match name {
+            "srgb" => Some(ColorSpace::Srgb),
+            "linear" => Some(ColorSpace::Linear),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(PathBuf, io::Error),
+    Decode { path: PathBuf, message: String },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ImageError::Decode { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// Pixels as stored in the file, top row first, with 1 (grey), 2 (grey and
+/// alpha), 3 (RGB) or 4 (RGBA) interleaved channels.
+struct DecodedImage {
+    width: u32,
+    height: u32,
+    channels: usize,
+    /// Integer formats are normalised to [0, 1].
+    samples: Vec<f32>,
+    /// Whether the samples are linear regardless of the requested colour space.
+    is_float: bool,
+}
+
+impl DecodedImage {
+    fn into_film(self, color_space: ColorSpace) -> Film {
+        let decode = |v: f32| match color_space {
+            ColorSpace::Srgb if !self.is_float => tonemap::srgb_to_linear(v),
+            _ => v,
+        };
+        let mut film = Film::new(self.width, self.height);
+        for (pixel, samples) in film
+            .pixels
+            .iter_mut()
+            .zip(self.samples.chunks_exact(self.channels))
+        {
+            *pixel = match samples {
+                [grey] | [grey, _] => Vec3::uniform(decode(*grey)),
+                [r, g, b, ..] => Vec3::new(decode(*r), decode(*g), decode(*b)),
+                [] => unreachable!(),
+            };
+        }
+        film
+    }
+}
+
+/// Decodes a PNG, JPEG, PPM/PGM, PFM or Radiance HDR image, detected from its
+/// contents, into linear RGB. Alpha is ignored.
+pub fn decode(data: &[u8], color_space: ColorSpace) -> Result<Film, String> {
+    let image = match data {
+        [0x89, b'P', b'N', b'G', ..] => png::decode(data)?,
+        [0xFF, 0xD8, ..] => jpeg::decode(data)?,
+        [b'P', b'F' | b'f', ..] => pfm::decode(data)?,
+        [b'P', b'2' | b'3' | b'5' | b'6', ..] => ppm::decode(data)?,
+        [b'#', b'?', ..] => hdr::decode(data)?,
+        _ => return Err(String::from("unsupported image format")),
+    };
+    if image.width == 0 || image.height == 0 {
+        return Err(String::from("image has no pixels"));
+    }
+    if image.samples.len() != (image.width * image.height) as usize * image.channels {
+        return Err(String::from("image data doesn't match its size"));
+    }
+    Ok(image.into_film(color_space))
+}
+
+pub fn load(path: &Path, color_space: ColorSpace) -> Result<Film, ImageError> {
+    let data = fs::read(path).map_err(|e| ImageError::Io(path.to_path_buf(), e))?;
+    decode(&data, color_space).map_err(|message| ImageError::Decode {
+        path: path.to_path_buf(),
+        message,
+    })
+}
+
+/// Decoded images shared between the textures that use them.
+#[derive(Default)]
+pub struct ImageCache {
+    images: HashMap<(PathBuf, ColorSpace), Arc<Film>>,
+}
+
+impl ImageCache {
+    /// Loads the image the first time it is requested.
+    pub fn load(&mut self, path: &Path, color_space: ColorSpace) -> Result<Arc<Film>, ImageError> {
+        let key = (path.to_path_buf(), color_space);
+        if let Some(image) = self.images.get(&key) {
+            return Ok(image.clone());
+        }
+        let image = Arc::new(load(path, color_space)?);
+        self.images.insert(key, image.clone());
+        Ok(image)
+    }
+}
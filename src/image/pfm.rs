@@ -0,0 +1,92 @@
+use std::io::{self, Write};
+
+use super::{DecodedImage, ImageWriter};
+use crate::film::Film;
+
+/// Portable float map, little-endian RGB.
+pub struct PfmWriter;
+
+impl ImageWriter for PfmWriter {
+    fn write(&self, film: &Film, out: &mut dyn Write) -> io::Result<()> {
+        // A negative scale marks little-endian data
+        write!(out, "PF\n{} {}\n-1.0\n", film.width, film.height)?;
+
+        // Scanlines are stored bottom to top
+        let mut row = Vec::with_capacity(film.width as usize * 12);
+        for y in (0..film.height).rev() {
+            row.clear();
+            for x in 0..film.width {
+                let color = film.pixel(x, y);
+                for c in [color.x, color.y, color.z] {
+                    row.extend_from_slice(&c.to_le_bytes());
+                }
+            }
+            out.write_all(&row)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes colour (PF) and greyscale (Pf) float maps of either byte order.
+pub(super) fn decode(data: &[u8]) -> Result<DecodedImage, String> {
+    // The header is three whitespace separated lines
+    let mut fields = Vec::with_capacity(4);
+    let mut pos = 0;
+    while fields.len() < 4 {
+        while data.get(pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        let start = pos;
+        while data.get(pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(String::from("truncated PFM header"));
+        }
+        fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+    }
+    let start = pos + 1;
+
+    let invalid = || String::from("invalid PFM header");
+    let channels = match fields[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid()),
+    };
+    let width: u32 = fields[1].parse().map_err(|_| invalid())?;
+    let height: u32 = fields[2].parse().map_err(|_| invalid())?;
+    let scale: f32 = fields[3].parse().map_err(|_| invalid())?;
+
+    let row_length = width as usize * channels;
+    let bytes = data
+        .get(start..start + row_length * height as usize * 4)
+        .ok_or_else(|| String::from("truncated PFM file"))?;
+    let values: Vec<f32> = bytes
+        .chunks_exact(4)
+        .map(|v| {
+            let v = [v[0], v[1], v[2], v[3]];
+            // A negative scale marks little-endian data
+            if scale < 0.0 {
+                f32::from_le_bytes(v)
+            } else {
+                f32::from_be_bytes(v)
+            }
+        })
+        .collect();
+
+    // Scanlines are stored bottom to top
+    let samples = values
+        .chunks_exact(row_length)
+        .rev()
+        .flatten()
+        .copied()
+        .collect();
+
+    Ok(DecodedImage {
+        width,
+        height,
+        channels,
+        samples,
+        is_float: true,
+    })
+}
@@ -0,0 +1,407 @@
+use std::io::{self, Write};
+
+use super::{zlib, DecodedImage, ImageWriter};
+use crate::{film::Film, tonemap::DisplayTransform};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// 8-bit RGB PNG.
+pub struct PngWriter {
+    pub display: DisplayTransform,
+}
+
+impl ImageWriter for PngWriter {
+    fn write(&self, film: &Film, out: &mut dyn Write) -> io::Result<()> {
+        let screen = film.to_screen(&self.display);
+        out.write_all(&SIGNATURE)?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&screen.width.to_be_bytes());
+        header.extend_from_slice(&screen.height.to_be_bytes());
+        // 8 bits per channel, RGB, deflate, adaptive filtering, no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(out, b"IHDR", &header)?;
+
+        let pixels: Vec<u8> = screen
+            .buffer
+            .iter()
+            .flat_map(|pixel| [pixel.0, pixel.1, pixel.2])
+            .collect();
+        let filtered = filter_scanlines(&pixels, screen.width as usize * 3, 3);
+        write_chunk(out, b"IDAT", &zlib::compress(&filtered))?;
+
+        write_chunk(out, b"IEND", &[])
+    }
+}
+
+fn write_chunk(out: &mut dyn Write, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(chunk_type)?;
+    out.write_all(data)?;
+
+    let mut crc = zlib::Crc32::new();
+    crc.update(chunk_type);
+    crc.update(data);
+    out.write_all(&crc.finish().to_be_bytes())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Filters every scanline with whichever of the five PNG filters yields the
+/// smallest sum of absolute differences, the heuristic recommended by the spec.
+fn filter_scanlines(pixels: &[u8], stride: usize, bpp: usize) -> Vec<u8> {
+    let rows = pixels.len() / stride;
+    let mut out = Vec::with_capacity(rows * (stride + 1));
+    let zero_row = vec![0u8; stride];
+    let mut candidate = vec![0u8; stride];
+    let mut best = vec![0u8; stride];
+
+    for y in 0..rows {
+        let row = &pixels[y * stride..(y + 1) * stride];
+        let prev = if y == 0 {
+            &zero_row[..]
+        } else {
+            &pixels[(y - 1) * stride..y * stride]
+        };
+
+        let mut best_filter = 0;
+        let mut best_score = u64::MAX;
+        for filter in 0..5u8 {
+            for i in 0..stride {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let b = prev[i];
+                let c = if i >= bpp { prev[i - bpp] } else { 0 };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                };
+                candidate[i] = row[i].wrapping_sub(predicted);
+            }
+
+            let score: u64 = candidate
+                .iter()
+                .map(|&v| (v as i8).unsigned_abs() as u64)
+                .sum();
+            if score < best_score {
+                best_score = score;
+                best_filter = filter;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+
+        out.push(best_filter);
+        out.extend_from_slice(&best);
+    }
+
+    out
+}
+
+/// Adam7 passes as (x offset, y offset, x step, y step).
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    /// Channels stored per pixel, a palette index counts as one.
+    fn channels(&self) -> usize {
+        match self.color_type {
+            0 | 3 => 1,
+            2 => 3,
+            4 => 2,
+            _ => 4,
+        }
+    }
+}
+
+fn parse_header(data: &[u8]) -> Result<Header, String> {
+    if data.len() != 13 {
+        return Err(String::from("invalid PNG header"));
+    }
+    let header = Header {
+        width: u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize,
+        height: u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize,
+        bit_depth: data[8],
+        color_type: data[9],
+        interlaced: data[12] == 1,
+    };
+
+    let valid_depths: &[u8] = match header.color_type {
+        0 => &[1, 2, 4, 8, 16],
+        3 => &[1, 2, 4, 8],
+        2 | 4 | 6 => &[8, 16],
+        _ => return Err(format!("invalid PNG color type {}", header.color_type)),
+    };
+    if !valid_depths.contains(&header.bit_depth) {
+        return Err(format!(
+            "invalid bit depth {} for PNG color type {}",
+            header.bit_depth, header.color_type
+        ));
+    }
+    if header.width == 0 || header.height == 0 || data[10] != 0 || data[11] != 0 || data[12] > 1 {
+        return Err(String::from("unsupported PNG header"));
+    }
+    Ok(header)
+}
+
+/// Reverses the scanline filters in place. `data` holds the rows, each
+/// prefixed by its filter type.
+fn unfilter_scanlines(data: &mut [u8], stride: usize, bpp: usize) -> Result<(), String> {
+    let rows = data.len() / (stride + 1);
+    for y in 0..rows {
+        let (previous, rest) = data.split_at_mut(y * (stride + 1));
+        let prev = (y > 0).then(|| &previous[previous.len() - stride..]);
+        let (filter, row) = rest[..stride + 1].split_first_mut().unwrap();
+
+        for i in 0..stride {
+            let a = if i >= bpp { row[i - bpp] } else { 0 };
+            let b = prev.map_or(0, |prev| prev[i]);
+            let c = if i >= bpp {
+                prev.map_or(0, |prev| prev[i - bpp])
+            } else {
+                0
+            };
+            let predicted = match *filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(format!("invalid PNG filter type {}", filter)),
+            };
+            row[i] = row[i].wrapping_add(predicted);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the `index`th sample of a row packed with `bit_depth` bits per sample.
+fn read_sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            (row[bit / 8] >> shift) as u16 & ((1 << bit_depth) - 1)
+        }
+    }
+}
+
+pub(super) fn decode(data: &[u8]) -> Result<DecodedImage, String> {
+    if !data.starts_with(&SIGNATURE) {
+        return Err(String::from("not a PNG file"));
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    let mut pos = SIGNATURE.len();
+    loop {
+        let truncated = || String::from("truncated PNG file");
+        let length = u32::from_be_bytes(
+            data.get(pos..pos + 4)
+                .ok_or_else(truncated)?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let chunk = data.get(pos + 4..pos + 8 + length).ok_or_else(truncated)?;
+        let crc = data
+            .get(pos + 8 + length..pos + 12 + length)
+            .ok_or_else(truncated)?;
+        pos += 12 + length;
+
+        let mut expected_crc = zlib::Crc32::new();
+        expected_crc.update(chunk);
+        if expected_crc.finish().to_be_bytes() != crc {
+            return Err(String::from("PNG chunk checksum mismatch"));
+        }
+
+        let (chunk_type, chunk_data) = chunk.split_at(4);
+        match chunk_type {
+            b"IHDR" => header = Some(parse_header(chunk_data)?),
+            b"PLTE" => palette = chunk_data.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(chunk_data),
+            b"IEND" => break,
+            // Ancillary chunks have a lowercase first letter and can be skipped
+            _ if chunk_type[0].is_ascii_lowercase() => (),
+            _ => {
+                return Err(format!(
+                    "unsupported critical PNG chunk '{}'",
+                    String::from_utf8_lossy(chunk_type)
+                ))
+            }
+        }
+    }
+
+    let header = header.ok_or_else(|| String::from("missing PNG header"))?;
+    if header.color_type == 3 && palette.is_empty() {
+        return Err(String::from("missing PNG palette"));
+    }
+    let mut raw = zlib::decompress(&compressed)?;
+
+    let channels = header.channels();
+    let bits_per_pixel = channels * header.bit_depth as usize;
+    let bpp = bits_per_pixel.div_ceil(8);
+    let out_channels = if header.color_type == 3 { 3 } else { channels };
+    let max_value = ((1u32 << header.bit_depth) - 1) as f32;
+    let mut samples = vec![0.0; header.width * header.height * out_channels];
+
+    let passes: &[(usize, usize, usize, usize)] = if header.interlaced {
+        &ADAM7
+    } else {
+        &[(0, 0, 1, 1)]
+    };
+    let mut offset = 0;
+    for &(x0, y0, dx, dy) in passes {
+        let pass_width = (header.width + dx - 1 - x0) / dx;
+        let pass_height = (header.height + dy - 1 - y0) / dy;
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+
+        let stride = (pass_width * bits_per_pixel).div_ceil(8);
+        let pass_length = pass_height * (stride + 1);
+        let pass_data = raw
+            .get_mut(offset..offset + pass_length)
+            .ok_or_else(|| String::from("not enough PNG image data"))?;
+        offset += pass_length;
+        unfilter_scanlines(pass_data, stride, bpp)?;
+
+        for (py, row) in pass_data.chunks_exact(stride + 1).enumerate() {
+            let row = &row[1..];
+            for px in 0..pass_width {
+                let pixel = ((y0 + py * dy) * header.width + x0 + px * dx) * out_channels;
+                if header.color_type == 3 {
+                    let index = read_sample(row, px, header.bit_depth) as usize;
+                    let color = palette
+                        .get(index * 3..index * 3 + 3)
+                        .ok_or_else(|| String::from("PNG palette index out of range"))?;
+                    for (c, &value) in color.iter().enumerate() {
+                        samples[pixel + c] = value as f32 / 255.0;
+                    }
+                } else {
+                    for c in 0..channels {
+                        let value = read_sample(row, px * channels + c, header.bit_depth);
+                        samples[pixel + c] = value as f32 / max_value;
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(DecodedImage {
+        width: header.width as u32,
+        height: header.height as u32,
+        channels: out_channels,
+        samples,
+        is_float: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::vec3::Vec3;
+
+    #[test]
+    fn decode_round_trip() {
+        let mut film = Film::new(37, 11);
+        for y in 0..film.height {
+            for x in 0..film.width {
+                let v = (x * 7 + y * 3) as f32 / 300.0;
+                film.write_pixel(x, y, Vec3::new(v, 1.0 - v, (x % 2) as f32));
+            }
+        }
+        let writer = PngWriter {
+            display: DisplayTransform::default(),
+        };
+        let mut encoded = Vec::new();
+        writer.write(&film, &mut encoded).unwrap();
+
+        let decoded = decode(&encoded).unwrap();
+        assert_eq!(
+            (decoded.width, decoded.height, decoded.channels),
+            (37, 11, 3)
+        );
+        let screen = film.to_screen(&writer.display);
+        for (pixel, samples) in screen.buffer.iter().zip(decoded.samples.chunks(3)) {
+            assert_eq!(pixel.0, (samples[0] * 255.0).round() as u8);
+            assert_eq!(pixel.2, (samples[2] * 255.0).round() as u8);
+        }
+    }
+
+    #[test]
+    fn decode_interlaced_palette() {
+        // 3x3 palette image with 2-bit indices and Adam7 interlacing, written by
+        // hand: palette black, red, green, blue with index (x + y) % 4.
+        let mut raw = Vec::new();
+        for &(x0, y0, dx, dy) in ADAM7.iter() {
+            for y in (y0..3).step_by(dy) {
+                let indices: Vec<u8> = (x0..3).step_by(dx).map(|x| ((x + y) % 4) as u8).collect();
+                if indices.is_empty() {
+                    continue;
+                }
+                let mut packed = vec![0u8; (indices.len() * 2).div_ceil(8)];
+                for (i, index) in indices.iter().enumerate() {
+                    packed[i / 4] |= index << (6 - 2 * (i % 4));
+                }
+                raw.push(0);
+                raw.extend_from_slice(&packed);
+            }
+        }
+
+        let mut png = SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &[0, 0, 0, 3, 0, 0, 0, 3, 2, 3, 0, 0, 1]).unwrap();
+        write_chunk(
+            &mut png,
+            b"PLTE",
+            &[0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255],
+        )
+        .unwrap();
+        write_chunk(&mut png, b"IDAT", &zlib::compress(&raw)).unwrap();
+        write_chunk(&mut png, b"IEND", &[]).unwrap();
+
+        let decoded = decode(&png).unwrap();
+        let palette = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ];
+        for y in 0..3 {
+            for x in 0..3 {
+                let i = (y * 3 + x) * 3;
+                assert_eq!(decoded.samples[i..i + 3], palette[(x + y) % 4]);
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Write};
+
+use super::{DecodedImage, ImageWriter};
+use crate::{film::Film, tonemap::DisplayTransform};
+
+/// Binary (P6) PPM.
+pub struct PpmWriter {
+    pub display: DisplayTransform,
+}
+
+impl ImageWriter for PpmWriter {
+    fn write(&self, film: &Film, out: &mut dyn Write) -> io::Result<()> {
+        let screen = film.to_screen(&self.display);
+        write!(out, "P6\n{} {}\n255\n", screen.width, screen.height)?;
+        let bytes: Vec<u8> = screen
+            .buffer
+            .iter()
+            .flat_map(|pixel| [pixel.0, pixel.1, pixel.2])
+            .collect();
+        out.write_all(&bytes)
+    }
+}
+
+/// Plain text (P3) PPM.
+pub struct PpmAsciiWriter {
+    pub display: DisplayTransform,
+}
+
+impl ImageWriter for PpmAsciiWriter {
+    fn write(&self, film: &Film, out: &mut dyn Write) -> io::Result<()> {
+        let screen = film.to_screen(&self.display);
+        write!(out, "P3\n{} {}\n255\n", screen.width, screen.height)?;
+        for pixel in screen.buffer.iter() {
+            writeln!(out, "{} {} {}", pixel.0, pixel.1, pixel.2)?
+        }
+        Ok(())
+    }
+}
+
+/// Reads the whitespace separated header fields, skipping `#` comments.
+struct HeaderReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl HeaderReader<'_> {
+    fn next_token(&mut self) -> Result<&str, String> {
+        loop {
+            match self.data.get(self.pos) {
+                Some(b'#') => {
+                    while self.data.get(self.pos).is_some_and(|&c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => self.pos += 1,
+                Some(_) => break,
+                None => return Err(String::from("truncated PPM file")),
+            }
+        }
+        let start = self.pos;
+        while self
+            .data
+            .get(self.pos)
+            .is_some_and(|c| !c.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.data[start..self.pos])
+            .map_err(|_| String::from("invalid PPM header"))
+    }
+
+    fn next_number(&mut self) -> Result<u32, String> {
+        let token = self.next_token()?;
+        token
+            .parse()
+            .map_err(|_| format!("invalid number '{}' in PPM file", token))
+    }
+}
+
+/// Decodes binary and plain PPM (P6, P3) and PGM (P5, P2) images.
+pub(super) fn decode(data: &[u8]) -> Result<DecodedImage, String> {
+    let mut reader = HeaderReader { data, pos: 0 };
+    let magic = reader.next_token()?.to_string();
+    let width = reader.next_number()?;
+    let height = reader.next_number()?;
+    let max_value = reader.next_number()?;
+    if !(1..=65535).contains(&max_value) {
+        return Err(format!("invalid PPM maximum value {}", max_value));
+    }
+
+    let channels = if magic == "P3" || magic == "P6" { 3 } else { 1 };
+    let count = (width * height) as usize * channels;
+    let samples = match magic.as_str() {
+        "P2" | "P3" => (0..count)
+            .map(|_| reader.next_number().map(|v| v as f32 / max_value as f32))
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => {
+            // A single whitespace character separates the header from the data
+            let start = reader.pos + 1;
+            let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+            let bytes = data
+                .get(start..start + count * bytes_per_sample)
+                .ok_or_else(|| String::from("truncated PPM file"))?;
+            if bytes_per_sample == 1 {
+                bytes.iter().map(|&v| v as f32 / max_value as f32).collect()
+            } else {
+                bytes
+                    .chunks_exact(2)
+                    .map(|v| u16::from_be_bytes([v[0], v[1]]) as f32 / max_value as f32)
+                    .collect()
+            }
+        }
+    };
+
+    Ok(DecodedImage {
+        width,
+        height,
+        channels,
+        samples,
+        is_float: false,
+    })
+}
@@ -0,0 +1,793 @@
+//! Minimal zlib/deflate (RFC 1950/1951) encoder and decoder, and the checksums
+//! used by PNG.
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32 { crc: 0xFFFFFFFF }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc = CRC_TABLE[((self.crc ^ byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.crc ^ 0xFFFFFFFF
+    }
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // 5552 is the largest block that can't overflow before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+    (b << 16) | a
+}
+
+pub(super) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(super) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+pub(super) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(super) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which the code length code lengths are stored.
+pub(super) const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const WINDOW_SIZE: usize = 1 << 15;
+const HASH_SIZE: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 128;
+const TOKENS_PER_BLOCK: usize = 1 << 15;
+
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            out: Vec::new(),
+            bits: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Huffman codes are stored most significant bit first.
+    fn write_code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - length as u32);
+        self.write_bits(reversed as u32, length as u32);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+/// Compresses `data` into a zlib stream.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window, default compression level
+    let mut out = vec![0x78, 0x9C];
+    out.extend(deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Compresses `data` into a raw deflate stream using dynamic Huffman blocks.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let tokens = lz77(data);
+    let mut writer = BitWriter::new();
+
+    let mut blocks = tokens.chunks(TOKENS_PER_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        write_block(&mut writer, &[], true);
+    }
+    while let Some(block) = blocks.next() {
+        write_block(&mut writer, block, blocks.peek().is_none());
+    }
+
+    writer.finish()
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let v = (data[pos] as u32) << 16 | (data[pos + 1] as u32) << 8 | data[pos + 2] as u32;
+    (v.wrapping_mul(2654435761) >> 17) as usize & (HASH_SIZE - 1)
+}
+
+struct MatchFinder {
+    head: Vec<i32>,
+    prev: Vec<i32>,
+}
+
+impl MatchFinder {
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(data, pos);
+            self.prev[pos % WINDOW_SIZE] = self.head[h];
+            self.head[h] = pos as i32;
+        }
+    }
+
+    /// Longest match for `pos` among the previously inserted positions.
+    fn longest_match(&self, data: &[u8], pos: usize) -> (usize, usize) {
+        if pos + MIN_MATCH > data.len() {
+            return (0, 0);
+        }
+        let max_length = MAX_MATCH.min(data.len() - pos);
+
+        let mut best = (0, 0);
+        let mut candidate = self.head[hash(data, pos)];
+        let mut chain = 0;
+        while candidate >= 0 && chain < MAX_CHAIN {
+            let candidate_pos = candidate as usize;
+            let distance = pos - candidate_pos;
+            if distance > WINDOW_SIZE - 1 {
+                break;
+            }
+
+            if data[candidate_pos + best.0] == data[pos + best.0] {
+                let length = data[candidate_pos..]
+                    .iter()
+                    .zip(&data[pos..pos + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, distance);
+                    if length == max_length {
+                        break;
+                    }
+                }
+            }
+
+            let next = self.prev[candidate_pos % WINDOW_SIZE];
+            if next >= candidate {
+                break; // Slot was overwritten by a newer position
+            }
+            candidate = next;
+            chain += 1;
+        }
+
+        if best.0 >= MIN_MATCH {
+            best
+        } else {
+            (0, 0)
+        }
+    }
+}
+
+/// LZ77 with hash chains and one step of lazy matching.
+fn lz77(data: &[u8]) -> Vec<Token> {
+    let mut finder = MatchFinder {
+        head: vec![-1; HASH_SIZE],
+        prev: vec![-1; WINDOW_SIZE],
+    };
+    let mut tokens = Vec::with_capacity(data.len() / 2);
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = finder.longest_match(data, pos);
+        finder.insert(data, pos);
+
+        if length == 0 {
+            tokens.push(Token::Literal(data[pos]));
+            pos += 1;
+            continue;
+        }
+
+        // Prefer a literal now if the next position starts a longer match
+        if length < MAX_MATCH && pos + 1 < data.len() {
+            let (next_length, _) = finder.longest_match(data, pos + 1);
+            if next_length > length {
+                tokens.push(Token::Literal(data[pos]));
+                pos += 1;
+                continue;
+            }
+        }
+
+        tokens.push(Token::Match {
+            length: length as u16,
+            distance: distance as u16,
+        });
+        for p in pos + 1..pos + length {
+            finder.insert(data, p);
+        }
+        pos += length;
+    }
+
+    tokens
+}
+
+fn length_symbol(length: u16) -> usize {
+    LENGTH_BASE.partition_point(|&base| base <= length) - 1
+}
+
+fn distance_symbol(distance: u16) -> usize {
+    DIST_BASE.partition_point(|&base| base <= distance) - 1
+}
+
+/// Huffman code lengths for `freqs`, limited to `max_length` bits.
+fn code_lengths(freqs: &[u32], max_length: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    loop {
+        let lengths = huffman_lengths(&freqs);
+        if lengths.iter().all(|&l| l <= max_length) {
+            return lengths;
+        }
+        // Flatten the distribution until the tree is shallow enough
+        for f in freqs.iter_mut().filter(|f| **f > 0) {
+            *f = f.div_ceil(2);
+        }
+    }
+}
+
+fn huffman_lengths(freqs: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let used: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+    match used.len() {
+        0 => return lengths,
+        1 => {
+            lengths[used[0]] = 1;
+            return lengths;
+        }
+        _ => (),
+    }
+
+    // Nodes 0..used.len() are leaves, the rest are internal
+    let mut parents: Vec<usize> = vec![usize::MAX; used.len()];
+    let mut heap: BinaryHeap<Reverse<(u32, usize)>> = used
+        .iter()
+        .enumerate()
+        .map(|(node, &symbol)| Reverse((freqs[symbol], node)))
+        .collect();
+    while heap.len() > 1 {
+        let Reverse((freq_a, a)) = heap.pop().unwrap();
+        let Reverse((freq_b, b)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((freq_a + freq_b, node)));
+    }
+
+    // Parents are always created after their children
+    let mut depths = vec![0u8; parents.len()];
+    for node in (0..parents.len() - 1).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+    for (node, &symbol) in used.iter().enumerate() {
+        lengths[symbol] = depths[node];
+    }
+    lengths
+}
+
+pub(super) fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut length_count = [0u16; 16];
+    for &length in lengths.iter().filter(|&&l| l > 0) {
+        length_count[length as usize] += 1;
+    }
+
+    let mut next_code = [0u16; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + length_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                0
+            } else {
+                let code = next_code[length as usize];
+                next_code[length as usize] += 1;
+                code
+            }
+        })
+        .collect()
+}
+
+/// Run-length encodes code lengths into (symbol, extra bits value) pairs.
+fn encode_code_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut encoded = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&l| l == value).count();
+        i += run;
+
+        if value == 0 {
+            while run >= 11 {
+                let n = run.min(138);
+                encoded.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            if run >= 3 {
+                encoded.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            encoded.push((value, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                encoded.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+        encoded.extend(std::iter::repeat_n((value, 0), run));
+    }
+    encoded
+}
+
+fn write_block(writer: &mut BitWriter, tokens: &[Token], is_final: bool) {
+    let mut literal_freqs = [0u32; 286];
+    let mut distance_freqs = [0u32; 30];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literal_freqs[byte as usize] += 1,
+            Token::Match { length, distance } => {
+                literal_freqs[257 + length_symbol(length)] += 1;
+                distance_freqs[distance_symbol(distance)] += 1;
+            }
+        }
+    }
+    literal_freqs[256] = 1;
+
+    let literal_lengths = code_lengths(&literal_freqs, 15);
+    let mut distance_lengths = code_lengths(&distance_freqs, 15);
+    if distance_lengths.iter().all(|&l| l == 0) {
+        // At least one distance code has to be described
+        distance_lengths[0] = 1;
+    }
+    let literal_codes = canonical_codes(&literal_lengths);
+    let distance_codes = canonical_codes(&distance_lengths);
+
+    let literal_count = 257.max(literal_lengths.iter().rposition(|&l| l > 0).unwrap() + 1);
+    let distance_count = 1.max(distance_lengths.iter().rposition(|&l| l > 0).unwrap() + 1);
+
+    let mut all_lengths = literal_lengths[..literal_count].to_vec();
+    all_lengths.extend_from_slice(&distance_lengths[..distance_count]);
+    let encoded_lengths = encode_code_lengths(&all_lengths);
+
+    let mut length_code_freqs = [0u32; 19];
+    for &(symbol, _) in encoded_lengths.iter() {
+        length_code_freqs[symbol as usize] += 1;
+    }
+    let length_code_lengths = code_lengths(&length_code_freqs, 7);
+    let length_codes = canonical_codes(&length_code_lengths);
+    let length_code_count = 4.max(
+        CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&i| length_code_lengths[i] > 0)
+            .unwrap()
+            + 1,
+    );
+
+    writer.write_bits(is_final as u32, 1);
+    writer.write_bits(2, 2); // Dynamic Huffman codes
+    writer.write_bits((literal_count - 257) as u32, 5);
+    writer.write_bits((distance_count - 1) as u32, 5);
+    writer.write_bits((length_code_count - 4) as u32, 4);
+    for &i in CODE_LENGTH_ORDER[..length_code_count].iter() {
+        writer.write_bits(length_code_lengths[i] as u32, 3);
+    }
+    for &(symbol, extra) in encoded_lengths.iter() {
+        let symbol = symbol as usize;
+        writer.write_code(length_codes[symbol], length_code_lengths[symbol]);
+        match symbol {
+            16 => writer.write_bits(extra as u32, 2),
+            17 => writer.write_bits(extra as u32, 3),
+            18 => writer.write_bits(extra as u32, 7),
+            _ => (),
+        }
+    }
+
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => {
+                let symbol = byte as usize;
+                writer.write_code(literal_codes[symbol], literal_lengths[symbol]);
+            }
+            Token::Match { length, distance } => {
+                let length_index = length_symbol(length);
+                let symbol = 257 + length_index;
+                writer.write_code(literal_codes[symbol], literal_lengths[symbol]);
+                writer.write_bits(
+                    (length - LENGTH_BASE[length_index]) as u32,
+                    LENGTH_EXTRA[length_index] as u32,
+                );
+
+                let distance_index = distance_symbol(distance);
+                writer.write_code(
+                    distance_codes[distance_index],
+                    distance_lengths[distance_index],
+                );
+                writer.write_bits(
+                    (distance - DIST_BASE[distance_index]) as u32,
+                    DIST_EXTRA[distance_index] as u32,
+                );
+            }
+        }
+    }
+    writer.write_code(literal_codes[256], literal_lengths[256]);
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u64,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            pos: 0,
+            bits: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn refill(&mut self) {
+        while self.bit_count <= 56 && self.pos < self.data.len() {
+            self.bits |= (self.data[self.pos] as u64) << self.bit_count;
+            self.pos += 1;
+            self.bit_count += 8;
+        }
+    }
+
+    /// The next `count` bits without consuming them, zero padded past the end.
+    fn peek(&mut self, count: u32) -> u32 {
+        if self.bit_count < count {
+            self.refill();
+        }
+        (self.bits & ((1 << count) - 1)) as u32
+    }
+
+    fn consume(&mut self, count: u32) -> Result<(), String> {
+        if self.bit_count < count {
+            return Err(String::from("unexpected end of compressed data"));
+        }
+        self.bits >>= count;
+        self.bit_count -= count;
+        Ok(())
+    }
+
+    fn read_bits(&mut self, count: u32) -> Result<u32, String> {
+        let value = self.peek(count);
+        self.consume(count)?;
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bits >>= self.bit_count % 8;
+        self.bit_count -= self.bit_count % 8;
+    }
+
+    /// Bytes consumed so far, counting a partially read byte as consumed.
+    fn bytes_read(&self) -> usize {
+        self.pos - (self.bit_count / 8) as usize
+    }
+}
+
+/// Lookup table decoder indexed by the next `max_length` bits of input.
+struct HuffmanDecoder {
+    /// Symbol in the upper bits, code length in the lower four. Zero for unused codes.
+    table: Vec<u16>,
+    max_length: u32,
+}
+
+impl HuffmanDecoder {
+    fn new(lengths: &[u8]) -> Result<HuffmanDecoder, String> {
+        let max_length = lengths.iter().copied().max().unwrap_or(0) as u32;
+        let codes = canonical_codes(lengths);
+
+        let mut table = vec![0u16; 1 << max_length];
+        for (symbol, (&length, &code)) in lengths.iter().zip(codes.iter()).enumerate() {
+            if length == 0 {
+                continue;
+            }
+            // Codes are stored most significant bit first
+            let reversed = code.reverse_bits() >> (16 - length);
+            if reversed as usize >= table.len() {
+                return Err(String::from("invalid Huffman code lengths"));
+            }
+            for index in (reversed as usize..table.len()).step_by(1 << length) {
+                table[index] = (symbol as u16) << 4 | length as u16;
+            }
+        }
+        Ok(HuffmanDecoder { table, max_length })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let entry = self.table[reader.peek(self.max_length) as usize];
+        if entry == 0 {
+            return Err(String::from("invalid Huffman code"));
+        }
+        reader.consume((entry & 0xF) as u32)?;
+        Ok(entry >> 4)
+    }
+}
+
+/// Decompresses a zlib stream and verifies its checksum.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let [cmf, flg, ..] = *data else {
+        return Err(String::from("truncated zlib stream"));
+    };
+    if cmf & 0x0F != 8 || !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) {
+        return Err(String::from("invalid zlib header"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(String::from("zlib preset dictionaries are not supported"));
+    }
+
+    let (out, length) = inflate(&data[2..])?;
+    let checksum = data
+        .get(2 + length..2 + length + 4)
+        .ok_or_else(|| String::from("missing zlib checksum"))?;
+    if u32::from_be_bytes(checksum.try_into().unwrap()) != adler32(&out) {
+        return Err(String::from("zlib checksum mismatch"));
+    }
+    Ok(out)
+}
+
+/// Decodes raw deflate data, returning the output and the number of input bytes used.
+pub fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::new();
+
+    loop {
+        let is_final = reader.read_bits(1)? == 1;
+        match reader.read_bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let length = reader.read_bits(16)?;
+                let inverted = reader.read_bits(16)?;
+                if length != !inverted & 0xFFFF {
+                    return Err(String::from("corrupt stored block length"));
+                }
+                for _ in 0..length {
+                    out.push(reader.read_bits(8)? as u8);
+                }
+            }
+            1 => {
+                let mut literal_lengths = [8u8; 288];
+                literal_lengths[144..256].fill(9);
+                literal_lengths[256..280].fill(7);
+                let literals = HuffmanDecoder::new(&literal_lengths)?;
+                let distances = HuffmanDecoder::new(&[5; 30])?;
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            _ => return Err(String::from("invalid deflate block type")),
+        }
+        if is_final {
+            break;
+        }
+    }
+
+    reader.align_to_byte();
+    Ok((out, reader.bytes_read()))
+}
+
+fn read_dynamic_codes(reader: &mut BitReader) -> Result<(HuffmanDecoder, HuffmanDecoder), String> {
+    let literal_count = reader.read_bits(5)? as usize + 257;
+    let distance_count = reader.read_bits(5)? as usize + 1;
+    let code_length_count = reader.read_bits(4)? as usize + 4;
+
+    let mut code_length_lengths = [0u8; 19];
+    for &symbol in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_length_lengths[symbol] = reader.read_bits(3)? as u8;
+    }
+    let code_length_decoder = HuffmanDecoder::new(&code_length_lengths)?;
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_length_decoder.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| String::from("code length repeat without a previous length"))?;
+                (previous, 3 + reader.read_bits(2)?)
+            }
+            17 => (0, 3 + reader.read_bits(3)?),
+            _ => (0, 11 + reader.read_bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count || lengths[256] == 0 {
+        return Err(String::from("invalid code lengths"));
+    }
+
+    let literals = HuffmanDecoder::new(&lengths[..literal_count])?;
+    let distances = HuffmanDecoder::new(&lengths[literal_count..])?;
+    Ok((literals, distances))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &HuffmanDecoder,
+    distances: &HuffmanDecoder,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err(String::from("invalid length symbol"));
+                }
+                let length = LENGTH_BASE[index] as usize
+                    + reader.read_bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+                let index = distances.decode(reader)? as usize;
+                if index >= DIST_BASE.len() {
+                    return Err(String::from("invalid distance symbol"));
+                }
+                let distance = DIST_BASE[index] as usize
+                    + reader.read_bits(DIST_EXTRA[index] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(String::from("distance past the start of the output"));
+                }
+
+                // Copies may overlap the bytes they produce
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0xCBF43926);
+    }
+
+    #[test]
+    fn adler32_check_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+
+    #[test]
+    fn huffman_lengths_are_limited_and_complete() {
+        // Fibonacci frequencies produce the deepest possible tree
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 30 {
+            let n = freqs.len();
+            freqs.push(freqs[n - 1] + freqs[n - 2]);
+        }
+        let lengths = code_lengths(&freqs, 15);
+        assert!(lengths.iter().all(|&l| (1..=15).contains(&l)));
+
+        // Kraft equality holds for a complete prefix code
+        let kraft: f64 = lengths.iter().map(|&l| 0.5f64.powi(l as i32)).sum();
+        assert!((kraft - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn repetitive_data_compresses() {
+        let data: Vec<u8> = (0..10000).map(|i| (i % 7) as u8).collect();
+        assert!(compress(&data).len() < 200);
+    }
+
+    #[test]
+    fn decompress_round_trip() {
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..100_000)
+            .map(|i| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                if i % 1000 < 500 {
+                    (i % 13) as u8
+                } else {
+                    (state >> 24) as u8
+                }
+            })
+            .collect();
+        assert_eq!(decompress(&compress(&data)).unwrap(), data);
+        assert_eq!(decompress(&compress(&[])).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn decompress_fixed_and_stored_blocks() {
+        // zlib.compress(b"hello hello hello") with the fixed code
+        let fixed = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00, 0x3a, 0x2e,
+            0x06, 0x7d,
+        ];
+        assert_eq!(decompress(&fixed).unwrap(), b"hello hello hello");
+
+        // zlib.compress(b"abc", level=0)
+        let stored = [
+            0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, 0x61, 0x62, 0x63, 0x02, 0x4d, 0x01, 0x27,
+        ];
+        assert_eq!(decompress(&stored).unwrap(), b"abc");
+
+        assert!(decompress(&fixed[..10]).is_err());
+    }
+}
@@ -1,526 +1,302 @@
 #![allow(dead_code)]
 
+mod aperture;
+mod bvh;
 mod camera;
+mod checkpoint;
+mod cli;
+mod film;
+mod filter;
 mod hittables;
+mod image;
 mod materials;
 mod math;
+mod noise;
+mod obj;
+mod render;
+mod sampler;
+mod scene_file;
+mod scenes;
 mod screen;
 mod textures;
+mod tiles;
+mod tonemap;
 mod utils;
 
 use std::{
-    f32::INFINITY,
-    fs::{self, File},
-    io::{self, BufWriter, Write},
-    path::Path,
-    sync::Arc,
+    env, fs,
+    io::{self, Write},
+    process,
+    sync::mpsc,
     thread,
+    time::Duration,
 };
 
-use camera::Camera;
-use hittables::{BVHNode, Hittable, HittableList, Quad, Sphere};
-use math::{interval::Interval, ray::Ray, vec3::Vec3};
-use rand::Rng;
-use screen::Screen;
-use textures::{CheckerTexture, SolidColorTexture};
+use bvh::BVH;
+use checkpoint::Checkpoint;
+use film::{Accumulator, Film};
+use filter::Filter;
+use render::RenderSettings;
+use tonemap::DisplayTransform;
 
-fn write_to_file_ppm(screen: &Screen, filepath: &Path) -> Result<(), io::Error> {
-    let parent_dir = filepath.parent().unwrap_or(Path::new(""));
-    fs::create_dir_all(parent_dir)?;
+/// Least time between the images written while a pass is still going.
+const IMAGE_SAVE_INTERVAL: Duration = Duration::from_secs(5);
 
-    let mut file = BufWriter::new(File::create(&filepath)?);
-
-    write!(file, "P3\n{} {}\n255\n", screen.width, screen.height)?;
-    for pixel in screen.buffer.iter() {
-        write!(file, "{} {} {}\n", pixel.0, pixel.1, pixel.2)?
-    }
-
-    Ok(())
-}
-
-fn ray_color(ray: &Ray, world: &impl Hittable, depth: u32, background_color: &Vec3) -> Vec3 {
-    if depth <= 0 {
-        return Vec3::ZERO;
-    }
-    match world.hit(ray, &Interval::new(0.001, INFINITY)) {
-        Some(hit_info) => {
-            let color_from_emission = hit_info.material.emitted(&hit_info);
-            match hit_info.material.scatter(ray, &hit_info) {
-                Some((attenution, scattered_ray)) => {
-                    color_from_emission
-                        + attenution * ray_color(&scattered_ray, world, depth - 1, background_color)
-                }
-                None => color_from_emission,
-            }
-        }
-        None => *background_color,
-    }
-}
-
-fn linear_to_gamma(color: &Vec3) -> Vec3 {
-    Vec3::new(
-        if color.x > 0.0 { color.x.sqrt() } else { 0.0 },
-        if color.y > 0.0 { color.y.sqrt() } else { 0.0 },
-        if color.z > 0.0 { color.z.sqrt() } else { 0.0 },
-    )
-}
-
-fn render(
-    screen: &mut Screen,
-    scene: &impl Hittable,
-    camera: &Camera,
-    background_color: &Vec3,
-    samples: u32,
-    max_depth: u32,
-    thread_count: u32,
-) {
-    let mut colors = vec![Vec3::ZERO; (screen.width * screen.height) as usize];
-    thread::scope(|scope| {
-        let width = screen.width;
-        let height = screen.height;
-
-        let thread_with_extra_sample = samples % thread_count;
-        let base_samples_per_thread = samples / thread_count;
-
-        let mut handles = vec![];
-        handles.reserve(thread_count as usize);
-
-        for i in 0..thread_count {
-            let samples_in_thread = base_samples_per_thread + (i < thread_with_extra_sample) as u32;
-            let handle = scope.spawn(move || {
-                let mut colors_local = vec![Vec3::ZERO; (width * height) as usize];
-
-                for y in 0..height {
-                    for x in 0..width {
-                        let i = (y * width + x) as usize;
-                        for _ in 0..samples_in_thread {
-                            let ray = camera.get_ray(x, y);
-                            colors_local[i] += ray_color(&ray, scene, max_depth, background_color);
-                        }
-                    }
-                }
-                colors_local
-            });
-            handles.push(handle);
+fn main() {
+    let args = match cli::parse_args(env::args().skip(1)) {
+        Ok(cli::Command::Render(args)) => args,
+        Ok(cli::Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
         }
-
-        for h in handles {
-            let colors_local = h.join().unwrap();
-            colors = colors
-                .iter()
-                .zip(colors_local.iter())
-                .map(|(a, b)| *a + *b)
-                .collect();
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            process::exit(2);
         }
-    });
-
-    for y in 0..screen.height {
-        for x in 0..screen.width {
-            let mut color = colors[(y * screen.width + x) as usize];
-            color /= samples as f32;
-            color = linear_to_gamma(&color);
+    };
 
-            screen.write_pixel(
-                x,
-                y,
-                (
-                    (color.x * 255.99) as u8,
-                    (color.y * 255.99) as u8,
-                    (color.z * 255.99) as u8,
-                ),
+    let resumed = args
+        .resume
+        .as_ref()
+        .map(|path| match checkpoint::load(path) {
+            Ok(checkpoint) => checkpoint,
+            Err(err) => {
+                eprintln!("Failed to load checkpoint {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        });
+    // A resumed render keeps the seed and size of the one it continues
+    let seed = match (&resumed, args.seed) {
+        (Some(checkpoint), Some(seed)) if seed != checkpoint.seed => {
+            eprintln!(
+                "--seed {} doesn't match the checkpoint's seed {}",
+                seed, checkpoint.seed
             );
+            process::exit(2);
         }
-    }
-}
-
-fn create_scene(width: u32, height: u32) -> (HittableList, Camera, Vec3) {
-    let ground_mat = Arc::new(materials::Lambertian {
-        albedo: Arc::new(SolidColorTexture {
-            color: Vec3::new(0.4, 0.59, 0.56),
-        }),
-    });
-    let blue_diffuse = Arc::new(materials::Lambertian {
-        albedo: Arc::new(SolidColorTexture {
-            color: Vec3::new(0.1, 0.2, 0.8),
-        }),
-    });
-    let gold_mat = Arc::new(materials::Metal {
-        albedo: Vec3::new(0.944, 0.776, 0.373),
-        roughness: 0.4,
-    });
-    let glass_mat = Arc::new(materials::Dielectric { ior: 1.5 });
-    let glass_inner_mat = Arc::new(materials::Dielectric { ior: 1.0 / 1.5 });
-
-    let mut hittables = HittableList::new();
-
-    hittables.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, 0.0, -1.2),
-        0.5,
-        blue_diffuse.clone(),
-    )));
-    hittables.add(Arc::new(Sphere::new(
-        Vec3::new(1.0, 0.0, -1.0),
-        0.5,
-        gold_mat.clone(),
-    )));
-    hittables.add(Arc::new(Sphere::new(
-        Vec3::new(-1.0, 0.0, -1.0),
-        0.5,
-        glass_mat.clone(),
-    )));
-    hittables.add(Arc::new(Sphere::new(
-        Vec3::new(-1.0, 0.0, -1.0),
-        0.4,
-        glass_inner_mat.clone(),
-    )));
-    hittables.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, -100.5, -1.0),
-        100.0,
-        ground_mat.clone(),
-    )));
-
-    let camera = Camera::new(
-        width,
-        height,
-        Vec3::new(-2.0, 2.0, 1.0),
-        30.0,
-        Vec3::new(0.0, 0.0, -1.0),
-        Vec3::UP,
-        10.0,
-        3.4,
-    );
-
-    let background_color = Vec3::new(0.5, 0.7, 1.0);
-
-    (hittables, camera, background_color)
-}
-
-fn create_final_scene(width: u32, height: u32) -> (HittableList, Camera, Vec3) {
-    let mut rng = rand::thread_rng();
-
-    let mut hittables = HittableList::new();
-    hittables.reserve(22 * 22 + 10);
-
-    // Ground
-    hittables.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, -1000.0, 0.0),
-        1000.0,
-        Arc::new(materials::Lambertian {
-            albedo: Arc::new(CheckerTexture {
-                even_texture: Arc::new(SolidColorTexture {
-                    color: Vec3::new(0.2, 0.3, 0.1),
-                }),
-                odd_texture: Arc::new(SolidColorTexture {
-                    color: Vec3::new(0.9, 0.9, 0.9),
-                }),
-            }),
-        }),
-    )));
-
-    // Big spheres
-    hittables.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, 1.0, 0.0),
-        1.0,
-        Arc::new(materials::Dielectric { ior: 1.5 }),
-    )));
-    hittables.add(Arc::new(Sphere::new(
-        Vec3::new(-4.0, 1.0, 0.0),
-        1.0,
-        Arc::new(materials::Lambertian {
-            albedo: Arc::new(SolidColorTexture {
-                color: (Vec3::new(0.4, 0.2, 0.1)),
-            }),
-        }),
-    )));
-    hittables.add(Arc::new(Sphere::new(
-        Vec3::new(4.0, 1.0, 0.0),
-        1.0,
-        Arc::new(materials::Metal {
-            albedo: Vec3::new(0.7, 0.6, 0.5),
-            roughness: 0.1,
-        }),
-    )));
-
-    // Small spheres
-    for x in -11..11 {
-        for z in -11..11 {
-            let radius = 0.2;
-            let center = Vec3::new(
-                x as f32 + rng.gen_range::<f32, _>(0.1..0.9),
-                radius,
-                z as f32 + 0.9 * rng.gen_range::<f32, _>(0.1..0.9),
-            );
-
-            let material: Arc<dyn materials::Material> = match rng.gen::<f32>() {
-                x if x < 0.7 => Arc::new(materials::Lambertian {
-                    albedo: Arc::new(SolidColorTexture {
-                        color: Vec3::new(rng.gen(), rng.gen(), rng.gen()),
-                    }),
-                }),
-                x if x < 0.9 => Arc::new(materials::Metal {
-                    albedo: Vec3::new(rng.gen(), rng.gen(), rng.gen()),
-                    roughness: rng.gen(),
-                }),
-                _ => Arc::new(materials::Dielectric { ior: 1.5 }),
-            };
+        (Some(checkpoint), _) => checkpoint.seed,
+        (None, seed) => seed.unwrap_or_else(rand::random),
+    };
+    let (width, height) = match &resumed {
+        Some(checkpoint) => {
+            let size = (checkpoint.accumulator.width, checkpoint.accumulator.height);
+            if args.width.is_some_and(|w| w != size.0) || args.height.is_some_and(|h| h != size.1) {
+                eprintln!(
+                    "The checkpoint is {}x{}, it can't be resumed at another size",
+                    size.0, size.1
+                );
+                process::exit(2);
+            }
+            (Some(size.0), Some(size.1))
+        }
+        None => (args.width, args.height),
+    };
 
-            hittables.add(Arc::new(Sphere::new(center, radius, material)));
+    // Settings priority: command line, then the scene file, then the defaults
+    let (scene_settings, scene_file) = match &args.scene {
+        cli::SceneSource::Builtin(_) => (RenderSettings::default(), None),
+        cli::SceneSource::File(path) => match scene_file::load_scene(path) {
+            Ok(scene) => (scene.settings.clone(), Some(scene)),
+            Err(err) => {
+                eprintln!("Failed to load scene: {}", err);
+                process::exit(1);
+            }
+        },
+    };
+    let tone_mapper = args
+        .tone_mapper
+        .unwrap_or(scene_settings.display.tone_mapper);
+    let tone_mapper = match args.white_point {
+        Some(white_point) => match tone_mapper.with_white_point(white_point) {
+            Some(tone_mapper) => tone_mapper,
+            None => {
+                eprintln!("--white-point only applies to the reinhard-extended tone mapper");
+                process::exit(2);
+            }
+        },
+        None => tone_mapper,
+    };
+    let settings = RenderSettings {
+        width: width.unwrap_or(scene_settings.width),
+        height: height.unwrap_or(scene_settings.height),
+        samples_per_pixel: args.samples.unwrap_or(scene_settings.samples_per_pixel),
+        max_depth: args.max_depth.unwrap_or(scene_settings.max_depth),
+        thread_count: args.threads.unwrap_or(scene_settings.thread_count),
+        display: DisplayTransform {
+            tone_mapper,
+            exposure: args.exposure.unwrap_or(scene_settings.display.exposure),
+            dither: args.dither || scene_settings.display.dither,
+        },
+        bvh: args.bvh.unwrap_or(scene_settings.bvh),
+        pass_samples: args.pass_samples.unwrap_or(scene_settings.pass_samples),
+        tile_size: args.tile_size.unwrap_or(scene_settings.tile_size),
+        tile_order: args.tile_order.unwrap_or(scene_settings.tile_order),
+        seed,
+        sampler: args.sampler.unwrap_or(scene_settings.sampler),
+        filter: {
+            let filter = args.filter.map_or(scene_settings.filter, Filter::new);
+            Filter {
+                radius: args.filter_radius.unwrap_or(filter.radius),
+                ..filter
+            }
+        },
+    };
+    let scene_hash = match &args.scene {
+        cli::SceneSource::Builtin(name) => checkpoint::scene_hash(name.as_bytes()),
+        cli::SceneSource::File(path) => match fs::read(path) {
+            Ok(source) => checkpoint::scene_hash(&source),
+            Err(err) => {
+                eprintln!("Failed to load scene: {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        },
+    };
+    // More samples only fit the checkpoint's if they're taken from the same
+    // scene, with the same sampler, and weighted by the same filter
+    if let Some(checkpoint) = &resumed {
+        let mismatch = if checkpoint.scene_hash != scene_hash {
+            Some(String::from("it was rendered from another scene"))
+        } else if checkpoint.filter != settings.filter {
+            Some(format!(
+                "it used the {} filter with a radius of {}",
+                checkpoint.filter.kind.name(),
+                checkpoint.filter.radius
+            ))
+        } else if checkpoint.sampler != settings.sampler {
+            Some(format!("it used the {} sampler", checkpoint.sampler.name()))
+        } else {
+            None
+        };
+        if let Some(reason) = mismatch {
+            eprintln!("The checkpoint can't be resumed here, {}", reason);
+            process::exit(2);
         }
     }
 
-    let camera = Camera::new(
-        width,
-        height,
-        Vec3::new(13.0, 2.0, 3.0),
-        20.0,
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::UP,
-        0.6,
-        10.0,
-    );
+    let checkpoint_path = args
+        .checkpoint
+        .clone()
+        .or_else(|| args.resume.clone())
+        .unwrap_or_else(|| args.output.with_extension("ckpt"));
 
-    let background_color = Vec3::new(0.5, 0.7, 1.0);
-
-    (hittables, camera, background_color)
-}
-
-fn create_quads_scene(width: u32, height: u32) -> (HittableList, Camera, Vec3) {
-    let left_red = Arc::new(materials::Lambertian {
-        albedo: Arc::new(SolidColorTexture {
-            color: Vec3::new(1.0, 0.2, 0.2),
-        }),
-    });
-    let back_green = Arc::new(materials::Lambertian {
-        albedo: Arc::new(SolidColorTexture {
-            color: Vec3::new(0.2, 1.0, 0.2),
-        }),
-    });
-    let right_blue = Arc::new(materials::Lambertian {
-        albedo: Arc::new(SolidColorTexture {
-            color: Vec3::new(0.2, 0.2, 1.0),
-        }),
-    });
-    let upper_orange = Arc::new(materials::Lambertian {
-        albedo: Arc::new(SolidColorTexture {
-            color: Vec3::new(1.0, 0.5, 0.0),
-        }),
-    });
-    let lower_teal = Arc::new(materials::Lambertian {
-        albedo: Arc::new(SolidColorTexture {
-            color: Vec3::new(0.2, 0.8, 0.8),
-        }),
-    });
-
-    let mut hittables = HittableList::new();
-
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(-3.0, -2.0, 5.0),
-        Vec3::new(0.0, 0.0, -4.0),
-        Vec3::new(0.0, 4.0, 0.0),
-        left_red.clone(),
-    )));
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(-2.0, -2.0, 0.0),
-        Vec3::new(4.0, 0.0, 0.0),
-        Vec3::new(0.0, 4.0, 0.0),
-        back_green.clone(),
-    )));
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(3.0, -2.0, 1.0),
-        Vec3::new(0.0, 0.0, 4.0),
-        Vec3::new(0.0, 4.0, 0.0),
-        right_blue.clone(),
-    )));
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(-2.0, 3.0, 1.0),
-        Vec3::new(4.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 4.0),
-        upper_orange.clone(),
-    )));
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(-2.0, -3.0, 5.0),
-        Vec3::new(4.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, -4.0),
-        lower_teal.clone(),
-    )));
-
-    let camera = Camera::new(
-        width,
-        height,
-        Vec3::BACKWARD * 9.0,
-        80.0,
-        Vec3::ZERO,
-        Vec3::UP,
-        0.0,
-        1.0,
-    );
-
-    let background_color = Vec3::new(0.5, 0.7, 1.0);
-
-    (hittables, camera, background_color)
-}
-
-fn create_lights_scene(width: u32, height: u32) -> (HittableList, Camera, Vec3) {
-    let mut hittables = HittableList::new();
-
-    hittables.add(Arc::new(Sphere::new(
-        Vec3::new(0.0, 0.5, 0.0),
-        0.5,
-        Arc::new(materials::Lambertian {
-            albedo: Arc::new(SolidColorTexture {
-                color: Vec3::new(0.2, 0.2, 0.9),
-            }),
-        }),
-    )));
-
-    //Floor
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(-500.0, 0.0, -500.0),
-        Vec3::RIGHT * 1000.0,
-        Vec3::BACKWARD * 1000.0,
-        Arc::new(materials::Lambertian {
-            albedo: Arc::new(SolidColorTexture {
-                color: Vec3::uniform(0.5),
-            }),
-        }),
-    )));
-
-    // Light
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(1.0, 0.0, -0.8),
-        Vec3::UP * 1.0,
-        Vec3::BACKWARD * 1.6,
-        Arc::new(materials::DiffuseLight {
-            color: Vec3::new(1.0, 1.0, 1.0) * 4.0,
-        }),
-    )));
+    let scene = match (&args.scene, scene_file) {
+        (_, Some(mut scene)) => {
+            scene.settings = settings.clone();
+            scene.into_scene()
+        }
+        (cli::SceneSource::Builtin(name), None) => {
+            let create = scenes::builtin(name).expect("scene name was validated");
+            create(settings.width, settings.height, seed)
+        }
+        (cli::SceneSource::File(_), None) => unreachable!(),
+    };
 
-    let camera = Camera::new(
-        width,
-        height,
-        Vec3::new(-0.6, 0.7, 2.0),
-        50.0,
-        Vec3::new(0.0, 0.5, 0.0),
-        Vec3::UP,
-        0.0,
-        1.0,
+    let build_start = std::time::Instant::now();
+    let (world, bvh_stats) = BVH::build(scene.hittables.objects(), &settings.bvh);
+    println!(
+        "Built BVH in {} ms: {} nodes, {} leaves, depth {}, SAH cost {:.1}.",
+        build_start.elapsed().as_millis(),
+        bvh_stats.interior_nodes,
+        bvh_stats.leaves,
+        bvh_stats.max_depth,
+        bvh_stats.sah_cost
     );
 
-    let background_color = Vec3::uniform(0.002);
-
-    (hittables, camera, background_color)
-}
-
-fn create_cornell_scene(width: u32, height: u32) -> (HittableList, Camera, Vec3) {
-    let red_wall = Arc::new(materials::Lambertian {
-        albedo: Arc::new(SolidColorTexture {
-            color: Vec3::new(0.65, 0.05, 0.05),
-        }),
-    });
-    let white_wall = Arc::new(materials::Lambertian {
-        albedo: Arc::new(SolidColorTexture {
-            color: Vec3::uniform(0.73),
-        }),
-    });
-    let green_wall = Arc::new(materials::Lambertian {
-        albedo: Arc::new(SolidColorTexture {
-            color: Vec3::new(0.12, 0.45, 0.15),
-        }),
-    });
-
-    let mut hittables = HittableList::new();
-
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(555.0, 0.0, 0.0),
-        Vec3::UP * 555.0,
-        Vec3::BACKWARD * 555.0,
-        green_wall.clone(),
-    )));
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::UP * 555.0,
-        Vec3::BACKWARD * 555.0,
-        red_wall.clone(),
-    )));
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::RIGHT * 555.0,
-        Vec3::BACKWARD * 555.0,
-        white_wall.clone(),
-    )));
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(555.0, 555.0, 555.0),
-        Vec3::LEFT * 555.0,
-        Vec3::FORWARD * 555.0,
-        white_wall.clone(),
-    )));
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(0.0, 0.0, 555.0),
-        Vec3::RIGHT * 555.0,
-        Vec3::UP * 555.0,
-        white_wall.clone(),
-    )));
-
-    // Light
-    hittables.add(Arc::new(Quad::new(
-        Vec3::new(343.0, 554.0, 332.0),
-        Vec3::LEFT * 130.0,
-        Vec3::FORWARD * 105.0,
-        Arc::new(materials::DiffuseLight {
-            color: Vec3::new(1.0, 1.0, 1.0) * 15.0,
-        }),
-    )));
-
-    let camera = Camera::new(
-        width,
-        height,
-        Vec3::new(278.0, 278.0, -800.0),
-        40.0,
-        Vec3::new(278.0, 278.0, 0.0),
-        Vec3::UP,
-        0.0,
-        1.0,
+    let mut accumulator = match resumed {
+        Some(checkpoint) => checkpoint.accumulator,
+        None => Accumulator::new(settings.width, settings.height),
+    };
+    println!(
+        "Starting render: {}x{}, {} samples, depth {}, {} threads, seed {}.",
+        settings.width,
+        settings.height,
+        settings.samples_per_pixel,
+        settings.max_depth,
+        settings.thread_count,
+        seed
     );
+    if args.resume.is_some() {
+        println!("Resuming from {} samples.", accumulator.min_count());
+    }
+    let start_time = std::time::Instant::now();
 
-    let background_color = Vec3::ZERO;
-
-    (hittables, camera, background_color)
-}
-
-fn main() {
-    let scene_index = 1;
-    let width = 1080 / 2;
-    let height = 1080 / 2;
-    let samples_per_pixel = 200;
-    let max_depth = 20;
-    let thread_count = 8;
-
-    let mut screen = Screen::new(width, height);
-
-    let (mut hittables, camera, background_color) = match scene_index {
-        0 => create_scene(width, height),
-        1 => create_final_scene(width, height),
-        2 => create_quads_scene(width, height),
-        3 => create_lights_scene(width, height),
-        4 => create_cornell_scene(width, height),
-        _ => panic!("Unknown scene"),
+    let resolve = |accumulator: &Accumulator| {
+        let mut film = Film::new(accumulator.width, accumulator.height);
+        accumulator.resolve(&mut film);
+        film
+    };
+    let save_image = |film: &Film| {
+        if let Err(err) = image::save(film, &args.output, args.format, settings.display) {
+            eprintln!("\nFailed to write {}: {}", args.output.display(), err);
+            process::exit(1);
+        }
+    };
+    // Only the accumulator at the end of a pass has all of the pass's samples,
+    // so that's the only one worth resuming from
+    let save_checkpoint = |accumulator: &Accumulator| {
+        let checkpoint = Checkpoint {
+            accumulator: accumulator.clone(),
+            seed,
+            scene_hash,
+            filter: settings.filter,
+            sampler: settings.sampler,
+        };
+        if let Err(err) = checkpoint::save(&checkpoint, &checkpoint_path) {
+            eprintln!("\nFailed to write {}: {}", checkpoint_path.display(), err);
+            process::exit(1);
+        }
     };
-    create_cornell_scene(width, height);
-
-    let world: BVHNode = BVHNode::from_hittable_list(&mut hittables);
-
-    println!("Starting render.");
-    let start_time = std::time::Instant::now();
 
-    render(
-        &mut screen,
-        &world,
-        &camera,
-        &background_color,
-        samples_per_pixel,
-        max_depth,
-        thread_count,
-    );
+    let mut passes = 0;
+    let mut last_save = start_time;
+    thread::scope(|scope| {
+        // The render threads wait while the progress callback runs, so images
+        // are only resolved there and encoded on a thread of their own, in
+        // the order they were resolved
+        let (images, images_to_save) = mpsc::sync_channel::<Film>(1);
+        scope.spawn(move || {
+            for film in images_to_save {
+                save_image(&film);
+            }
+        });
+
+        render::render(
+            &mut accumulator,
+            &world,
+            &scene.lights,
+            &scene.camera,
+            &scene.background_color,
+            &settings,
+            |accumulator, progress| {
+                print!(
+                    "\rPass {}: {}/{} tiles, {}/{} samples ({}:{:02})",
+                    progress.pass,
+                    progress.tiles_done,
+                    progress.tile_count,
+                    accumulator.min_count(),
+                    settings.samples_per_pixel,
+                    start_time.elapsed().as_secs() / 60,
+                    start_time.elapsed().as_secs() % 60
+                );
+                io::stdout().flush().unwrap();
+                if progress.pass_done() {
+                    passes += 1;
+                    // Only fails if the saving thread exited, which ends the
+                    // process
+                    let _ = images.send(resolve(accumulator));
+                    save_checkpoint(accumulator);
+                    last_save = std::time::Instant::now();
+                } else if last_save.elapsed() >= IMAGE_SAVE_INTERVAL {
+                    // Finished tiles show up in the image without waiting for
+                    // the rest of the pass. Skipped while the last image is
+                    // still waiting to be saved
+                    let _ = images.try_send(resolve(accumulator));
+                    last_save = std::time::Instant::now();
+                }
+            },
+        );
+    });
+    println!();
 
     let duration = start_time.elapsed();
     println!(
@@ -529,8 +305,15 @@ fn main() {
         duration.as_secs() % 60
     );
 
-    print!("Saving to file... ");
-    io::stdout().flush().unwrap();
-    write_to_file_ppm(&screen, Path::new("./out/test.ppm")).unwrap();
-    println!("Done!");
+    // Every pass saves, so only a checkpoint that already had enough samples
+    // is left to write
+    if passes == 0 {
+        save_image(&resolve(&accumulator));
+        save_checkpoint(&accumulator);
+    }
+    println!(
+        "Saved {} and {}.",
+        args.output.display(),
+        checkpoint_path.display()
+    );
 }
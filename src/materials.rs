@@ -1,5 +1,4 @@
-use rand::Rng;
-use std::sync::Arc;
+use std::{f32::consts::PI, sync::Arc};
 
 use crate::{
     hittables::HitInfo,
@@ -8,14 +7,53 @@ use crate::{
     utils,
 };
 
+/// A direction picked by `Material::sample`.
+pub struct BsdfSample {
+    /// Unit direction of the scattered ray.
+    pub direction: Vec3,
+    /// BSDF times cosine divided by the pdf, what the incoming light gets multiplied by.
+    /// Phase functions have no cosine term.
+    pub weight: Vec3,
+    /// Solid angle density of `direction`. Meaningless for delta lobes.
+    pub pdf: f32,
+    /// Perfect mirror or refraction that `eval` and `pdf` can't represent.
+    pub is_delta: bool,
+}
+
 pub trait Material: Send + Sync {
-    fn scatter(&self, _ray_in: &Ray, _hit_info: &HitInfo) -> Option<(Vec3, Ray)> {
+    /// Picks a scattered direction for the incoming ray, `None` if the ray is absorbed.
+    /// `uc` chooses between lobes and `u` the direction within a lobe; both are
+    /// uniform in `[0, 1)`.
+    fn sample(
+        &self,
+        _ray_in: &Ray,
+        _hit_info: &HitInfo,
+        _uc: f32,
+        _u: [f32; 2],
+    ) -> Option<BsdfSample> {
         None
     }
 
+    /// BSDF for scattering the incoming ray into the unit vector `direction`,
+    /// without the cosine term. Delta lobes evaluate to zero.
+    fn eval(&self, _ray_in: &Ray, _hit_info: &HitInfo, _direction: &Vec3) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    /// Density with which `sample` picks `direction`. Delta lobes have a zero density.
+    fn pdf(&self, _ray_in: &Ray, _hit_info: &HitInfo, _direction: &Vec3) -> f32 {
+        0.0
+    }
+
     fn emitted(&self, _hit_info: &HitInfo) -> Vec3 {
         Vec3::ZERO
     }
+
+    /// Scatters inside a participating medium rather than off a surface, so
+    /// light arriving from any direction isn't scaled by a cosine.
+    fn is_phase_function(&self) -> bool {
+        false
+    }
 }
 
 pub struct Lambertian {
@@ -23,32 +61,99 @@ pub struct Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _: &Ray, hit_info: &HitInfo) -> Option<(Vec3, Ray)> {
-        let mut dir: Vec3 = hit_info.normal + utils::random_unit_vector();
-        if dir.near_zero() {
-            dir = hit_info.normal;
-        }
+    fn sample(&self, ray_in: &Ray, hit_info: &HitInfo, _: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        let local = utils::sample_cosine_direction(u);
+        let (u, v) = utils::orthonormal_basis(&hit_info.normal);
+        let direction = u * local.x + v * local.y + hit_info.normal * local.z;
         let albedo_color = self.albedo.sample(hit_info.u, hit_info.v, &hit_info.point);
 
-        Some((albedo_color, Ray::new(hit_info.point, dir)))
+        Some(BsdfSample {
+            direction,
+            weight: albedo_color,
+            pdf: self.pdf(ray_in, hit_info, &direction),
+            is_delta: false,
+        })
+    }
+
+    fn eval(&self, _: &Ray, hit_info: &HitInfo, direction: &Vec3) -> Vec3 {
+        if Vec3::dot(&hit_info.normal, direction) <= 0.0 {
+            return Vec3::ZERO;
+        }
+        self.albedo.sample(hit_info.u, hit_info.v, &hit_info.point) / PI
+    }
+
+    fn pdf(&self, _: &Ray, hit_info: &HitInfo, direction: &Vec3) -> f32 {
+        Vec3::dot(&hit_info.normal, direction).max(0.0) / PI
     }
 }
 
+/// Mirror reflection, blurred by a Phong lobe around the mirror direction when
+/// `roughness` is above zero.
 pub struct Metal {
     pub albedo: Vec3,
     pub roughness: f32,
 }
 
+impl Metal {
+    /// Phong exponent that gives the same blur as `roughness`, the inverse of the
+    /// usual `sqrt(2 / (n + 2))` mapping.
+    fn exponent(&self) -> f32 {
+        (2.0 / (self.roughness * self.roughness) - 2.0).max(0.0)
+    }
+
+    fn lobe_pdf(&self, ray_in: &Ray, hit_info: &HitInfo, direction: &Vec3) -> f32 {
+        let reflected = ray_in.direction.normalized().reflected(&hit_info.normal);
+        let cos_alpha = Vec3::dot(&reflected, direction).max(0.0);
+        let exponent = self.exponent();
+        (exponent + 1.0) / (2.0 * PI) * cos_alpha.powf(exponent)
+    }
+}
+
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, hit_info: &HitInfo) -> Option<(Vec3, Ray)> {
-        let reflected_dir = ray_in.direction.reflected(&hit_info.normal);
-        let reflected_dir =
-            reflected_dir.normalized() + self.roughness * utils::random_unit_vector();
-        if Vec3::dot(&reflected_dir, &hit_info.normal) > 0.0 {
-            Some((self.albedo, Ray::new(hit_info.point, reflected_dir)))
-        } else {
-            None
+    fn sample(&self, ray_in: &Ray, hit_info: &HitInfo, _: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        let reflected = ray_in.direction.normalized().reflected(&hit_info.normal);
+        if self.roughness <= 0.0 {
+            return Some(BsdfSample {
+                direction: reflected,
+                weight: self.albedo,
+                pdf: 1.0,
+                is_delta: true,
+            });
+        }
+
+        let cos_alpha = u[0].powf(1.0 / (self.exponent() + 1.0));
+        let sin_alpha = (1.0 - cos_alpha * cos_alpha).sqrt();
+        let phi = 2.0 * PI * u[1];
+        let (u, v) = utils::orthonormal_basis(&reflected);
+        let direction =
+            u * (phi.cos() * sin_alpha) + v * (phi.sin() * sin_alpha) + reflected * cos_alpha;
+
+        // Directions that end up below the surface are absorbed
+        if Vec3::dot(&direction, &hit_info.normal) <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction,
+            weight: self.albedo,
+            pdf: self.lobe_pdf(ray_in, hit_info, &direction),
+            is_delta: false,
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, hit_info: &HitInfo, direction: &Vec3) -> Vec3 {
+        let cosine = Vec3::dot(&hit_info.normal, direction);
+        if self.roughness <= 0.0 || cosine <= 0.0 {
+            return Vec3::ZERO;
+        }
+        // Chosen so that sampled directions are weighted by exactly the albedo
+        self.albedo * (self.lobe_pdf(ray_in, hit_info, direction) / cosine)
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit_info: &HitInfo, direction: &Vec3) -> f32 {
+        if self.roughness <= 0.0 || Vec3::dot(&hit_info.normal, direction) <= 0.0 {
+            return 0.0;
         }
+        self.lobe_pdf(ray_in, hit_info, direction)
     }
 }
 
@@ -57,7 +162,7 @@ pub struct Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit_info: &HitInfo) -> Option<(Vec3, Ray)> {
+    fn sample(&self, ray_in: &Ray, hit_info: &HitInfo, uc: f32, _: [f32; 2]) -> Option<BsdfSample> {
         let ri = if hit_info.front_face {
             1.0 / self.ior
         } else {
@@ -70,15 +175,19 @@ impl Material for Dielectric {
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
         let cannot_refract = ri * sin_theta > 1.0;
-        let direction =
-            if cannot_refract || reflectance(cos_theta, ri) > rand::thread_rng().gen::<f32>() {
-                // Must reflect
-                unit_dir.reflected(&hit_info.normal)
-            } else {
-                unit_dir.refracted(&hit_info.normal, ri)
-            };
+        let direction = if cannot_refract || reflectance(cos_theta, ri) > uc {
+            // Must reflect
+            unit_dir.reflected(&hit_info.normal)
+        } else {
+            unit_dir.refracted(&hit_info.normal, ri)
+        };
 
-        Some((Vec3::ONE, Ray::new(hit_info.point, direction)))
+        Some(BsdfSample {
+            direction,
+            weight: Vec3::ONE,
+            pdf: 1.0,
+            is_delta: true,
+        })
     }
 }
 
@@ -90,6 +199,93 @@ fn reflectance(cos_theta: f32, ior: f32) -> f32 {
     r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
 }
 
+/// Scatters equally in all directions inside a `ConstantMedium`.
+pub struct Isotropic {
+    pub albedo: Arc<dyn Texture>,
+}
+
+impl Material for Isotropic {
+    fn sample(&self, _: &Ray, hit_info: &HitInfo, _: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        Some(BsdfSample {
+            direction: utils::sample_unit_sphere(u),
+            weight: self.albedo.sample(hit_info.u, hit_info.v, &hit_info.point),
+            pdf: 1.0 / (4.0 * PI),
+            is_delta: false,
+        })
+    }
+
+    fn eval(&self, _: &Ray, hit_info: &HitInfo, _: &Vec3) -> Vec3 {
+        self.albedo.sample(hit_info.u, hit_info.v, &hit_info.point) / (4.0 * PI)
+    }
+
+    fn pdf(&self, _: &Ray, _: &HitInfo, _: &Vec3) -> f32 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn is_phase_function(&self) -> bool {
+        true
+    }
+}
+
+/// Henyey-Greenstein phase function for media that mostly scatter forwards
+/// (`g` towards 1, like fog) or backwards (`g` towards -1). `g` is the average
+/// cosine of the scattering angle, zero is isotropic.
+pub struct HenyeyGreenstein {
+    pub albedo: Arc<dyn Texture>,
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    /// Density for an angle with cosine `cos_theta` between the incoming ray's
+    /// direction of travel and the scattered direction.
+    fn phase(&self, cos_theta: f32) -> f32 {
+        let g = self.g;
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.max(1e-8).sqrt())
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn sample(&self, ray_in: &Ray, hit_info: &HitInfo, _: f32, u: [f32; 2]) -> Option<BsdfSample> {
+        let g = self.g;
+        let xi = u[0];
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * xi
+        } else {
+            // Inverse of the cumulative distribution
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u[1];
+
+        let forward = ray_in.direction.normalized();
+        let (u, v) = utils::orthonormal_basis(&forward);
+        let direction =
+            u * (phi.cos() * sin_theta) + v * (phi.sin() * sin_theta) + forward * cos_theta;
+
+        Some(BsdfSample {
+            direction,
+            weight: self.albedo.sample(hit_info.u, hit_info.v, &hit_info.point),
+            pdf: self.phase(cos_theta),
+            is_delta: false,
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, hit_info: &HitInfo, direction: &Vec3) -> Vec3 {
+        self.albedo.sample(hit_info.u, hit_info.v, &hit_info.point)
+            * self.pdf(ray_in, hit_info, direction)
+    }
+
+    fn pdf(&self, ray_in: &Ray, _: &HitInfo, direction: &Vec3) -> f32 {
+        self.phase(Vec3::dot(&ray_in.direction.normalized(), direction))
+    }
+
+    fn is_phase_function(&self) -> bool {
+        true
+    }
+}
+
 pub struct DiffuseLight {
     pub color: Vec3,
 }
@@ -99,3 +295,133 @@ impl Material for DiffuseLight {
         self.color
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use rand::Rng;
+
+    use super::*;
+    use crate::textures::SolidColorTexture;
+
+    fn uniform() -> (f32, [f32; 2]) {
+        let mut rng = utils::rng();
+        (rng.gen(), [rng.gen(), rng.gen()])
+    }
+
+    /// Checks that sampled weights agree with `eval` and `pdf`.
+    fn check_sample_weights(material: Arc<dyn Material>) {
+        let mut hit_info = HitInfo::new(material.clone());
+        hit_info.normal = Vec3::UP;
+        hit_info.front_face = true;
+        let ray_in = Ray::new(Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+
+        for _ in 0..100 {
+            let (uc, u) = uniform();
+            let Some(sample) = material.sample(&ray_in, &hit_info, uc, u) else {
+                continue;
+            };
+            assert!(!sample.is_delta);
+            assert_approx_eq!(sample.direction.length(), 1.0, 1e-4);
+
+            let pdf = material.pdf(&ray_in, &hit_info, &sample.direction);
+            assert_approx_eq!(pdf, sample.pdf, 1e-3);
+            let cosine = Vec3::dot(&hit_info.normal, &sample.direction);
+            let expected = material.eval(&ray_in, &hit_info, &sample.direction) * cosine / pdf;
+            assert_approx_eq!(expected.x, sample.weight.x, 1e-3);
+            assert_approx_eq!(expected.y, sample.weight.y, 1e-3);
+        }
+    }
+
+    #[test]
+    fn sample_weights_match_eval_and_pdf() {
+        check_sample_weights(Arc::new(Lambertian {
+            albedo: Arc::new(SolidColorTexture {
+                color: Vec3::new(0.2, 0.5, 0.8),
+            }),
+        }));
+        check_sample_weights(Arc::new(Metal {
+            albedo: Vec3::new(0.9, 0.6, 0.3),
+            roughness: 0.3,
+        }));
+    }
+
+    #[test]
+    fn smooth_materials_are_delta() {
+        let mut hit_info = HitInfo::new(Arc::new(Dielectric { ior: 1.5 }));
+        let ray_in = Ray::new(Vec3::UP, Vec3::DOWN);
+        hit_info.normal = Vec3::UP;
+        hit_info.front_face = true;
+
+        let mirror = Metal {
+            albedo: Vec3::ONE,
+            roughness: 0.0,
+        };
+        let sample = mirror.sample(&ray_in, &hit_info, 0.5, [0.5, 0.5]).unwrap();
+        assert!(sample.is_delta);
+        assert_eq!(sample.direction, Vec3::UP);
+        assert_eq!(mirror.pdf(&ray_in, &hit_info, &Vec3::UP), 0.0);
+
+        let glass = Dielectric { ior: 1.5 };
+        assert!(
+            glass
+                .sample(&ray_in, &hit_info, 0.5, [0.5, 0.5])
+                .unwrap()
+                .is_delta
+        );
+        assert_eq!(glass.eval(&ray_in, &hit_info, &Vec3::UP), Vec3::ZERO);
+    }
+
+    #[test]
+    fn phase_functions_are_normalised() {
+        let albedo = Arc::new(SolidColorTexture {
+            color: Vec3::uniform(0.5),
+        });
+        let phase_functions: [(Arc<dyn Material>, f32); 3] = [
+            (
+                Arc::new(Isotropic {
+                    albedo: albedo.clone(),
+                }),
+                0.0,
+            ),
+            (
+                Arc::new(HenyeyGreenstein {
+                    albedo: albedo.clone(),
+                    g: 0.7,
+                }),
+                0.7,
+            ),
+            (Arc::new(HenyeyGreenstein { albedo, g: -0.4 }), -0.4),
+        ];
+        let ray_in = Ray::new(Vec3::ZERO, Vec3::new(0.0, 0.0, -2.0));
+
+        for (material, g) in phase_functions {
+            let hit_info = HitInfo::new(material.clone());
+            assert!(material.is_phase_function());
+
+            // Uniform directions over the sphere integrate the density to one
+            let n = 20000;
+            let integral: f32 = (0..n)
+                .map(|_| material.pdf(&ray_in, &hit_info, &utils::random_unit_vector()))
+                .sum::<f32>()
+                * 4.0
+                * PI
+                / n as f32;
+            assert_approx_eq!(integral, 1.0, 0.05);
+
+            // Sampled directions average to a cosine of g with the ray
+            let mut mean_cosine = 0.0;
+            for _ in 0..n {
+                let (uc, u) = uniform();
+                let sample = material.sample(&ray_in, &hit_info, uc, u).unwrap();
+                let pdf = material.pdf(&ray_in, &hit_info, &sample.direction);
+                assert_approx_eq!(pdf, sample.pdf, 1e-2 * pdf);
+                let expected = material.eval(&ray_in, &hit_info, &sample.direction) / pdf;
+                assert_approx_eq!(expected.x, sample.weight.x, 1e-4);
+                mean_cosine += -sample.direction.z / n as f32;
+            }
+            assert_approx_eq!(mean_cosine, g, 0.02);
+        }
+    }
+}
@@ -2,7 +2,8 @@ use std::ops;
 
 use super::{interval::Interval, ray::Ray, vec3::Vec3};
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
 pub struct AABB {
     pub x: Interval,
     pub y: Interval,
@@ -49,12 +50,28 @@ impl AABB {
 
         AABB::new(x, y, z)
     }
-    pub fn hit(&self, ray: &Ray, mut t_range: Interval) -> bool {
+    pub fn hit(&self, ray: &Ray, t_range: Interval) -> bool {
+        let d = &ray.direction;
+        self.hit_inverse(
+            &ray.origin,
+            &Vec3::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z),
+            t_range,
+        )
+    }
+
+    /// Slab test with the reciprocal of the ray direction, so that callers
+    /// testing many boxes against one ray only divide once.
+    pub fn hit_inverse(
+        &self,
+        origin: &Vec3,
+        inverse_direction: &Vec3,
+        mut t_range: Interval,
+    ) -> bool {
         for i in 0..3 {
             let axis_interval = self[i];
 
-            let t0 = (axis_interval.start - ray.origin[i]) / ray.direction[i];
-            let t1 = (axis_interval.end - ray.origin[i]) / ray.direction[i];
+            let t0 = (axis_interval.start - origin[i]) * inverse_direction[i];
+            let t1 = (axis_interval.end - origin[i]) * inverse_direction[i];
 
             let (t0, t1) = if t0 <= t1 { (t0, t1) } else { (t1, t0) };
             t_range.start = t_range.start.max(t0);
@@ -68,6 +85,23 @@ impl AABB {
         true
     }
 
+    /// Zero for empty boxes.
+    pub fn surface_area(&self) -> f32 {
+        let (dx, dy, dz) = (self.x.size(), self.y.size(), self.z.size());
+        if dx < 0.0 || dy < 0.0 || dz < 0.0 {
+            return 0.0;
+        }
+        2.0 * (dx * dy + dy * dz + dz * dx)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        Vec3::new(
+            (self.x.start + self.x.end) / 2.0,
+            (self.y.start + self.y.end) / 2.0,
+            (self.z.start + self.z.end) / 2.0,
+        )
+    }
+
     pub fn longest_axis(&self) -> u32 {
         if self.x.size() >= self.y.size() {
             if self.x.size() >= self.z.size() {
@@ -0,0 +1,536 @@
+use std::{fmt, ops};
+
+use super::vec3::Vec3;
+
+/// Affine transform stored as a row major 4x4 matrix, applied to column vectors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    pub rows: [[f32; 4]; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Self = Mat4::new([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    pub const fn new(rows: [[f32; 4]; 4]) -> Mat4 {
+        Mat4 { rows }
+    }
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        Mat4::new([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn scale(factors: Vec3) -> Mat4 {
+        Mat4::new([
+            [factors.x, 0.0, 0.0, 0.0],
+            [0.0, factors.y, 0.0, 0.0],
+            [0.0, 0.0, factors.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Counter-clockwise rotation around `axis` when looking down the axis
+    /// towards the origin.
+    pub fn rotation(axis: Vec3, angle_deg: f32) -> Mat4 {
+        let a = axis.normalized();
+        let (sin, cos) = angle_deg.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        Mat4::new([
+            [
+                t * a.x * a.x + cos,
+                t * a.x * a.y - sin * a.z,
+                t * a.x * a.z + sin * a.y,
+                0.0,
+            ],
+            [
+                t * a.x * a.y + sin * a.z,
+                t * a.y * a.y + cos,
+                t * a.y * a.z - sin * a.x,
+                0.0,
+            ],
+            [
+                t * a.x * a.z - sin * a.y,
+                t * a.y * a.z + sin * a.x,
+                t * a.z * a.z + cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transposed(&self) -> Mat4 {
+        let mut rows = [[0.0; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.rows[j][i];
+            }
+        }
+        Mat4::new(rows)
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, `None` for singular matrices.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut m = self.rows;
+        let mut inverse = Mat4::IDENTITY.rows;
+        for column in 0..4 {
+            let pivot =
+                (column..4).max_by(|&a, &b| m[a][column].abs().total_cmp(&m[b][column].abs()))?;
+            if m[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            m.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1.0 / m[column][column];
+            for j in 0..4 {
+                m[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            for row in 0..4 {
+                let factor = m[row][column];
+                if row == column || factor == 0.0 {
+                    continue;
+                }
+                for j in 0..4 {
+                    m[row][j] -= factor * m[column][j];
+                    inverse[row][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+        Some(Mat4::new(inverse))
+    }
+
+    /// Determinant of the upper 3x3 part, how much the transform scales volumes.
+    pub fn determinant3(&self) -> f32 {
+        let m = &self.rows;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Splits the transform into `translation * rotation * stretch` with a
+    /// polar decomposition of the upper 3x3 part. The stretch is symmetric and
+    /// holds any scaling and shearing, the rotation is a proper rotation.
+    pub fn decompose(&self) -> (Vec3, Mat4, Mat4) {
+        let translation = Vec3::new(self.rows[0][3], self.rows[1][3], self.rows[2][3]);
+        let mut linear = *self;
+        for row in 0..3 {
+            linear.rows[row][3] = 0.0;
+        }
+
+        // Averaging with the inverse transpose converges to the closest rotation
+        let mut rotation = linear;
+        for _ in 0..100 {
+            let Some(inverse) = rotation.inverse() else {
+                break;
+            };
+            let inverse_transpose = inverse.transposed();
+            let mut next = rotation;
+            let mut change: f32 = 0.0;
+            for i in 0..3 {
+                for j in 0..3 {
+                    next.rows[i][j] = 0.5 * (rotation.rows[i][j] + inverse_transpose.rows[i][j]);
+                    change = change.max((next.rows[i][j] - rotation.rows[i][j]).abs());
+                }
+            }
+            rotation = next;
+            if change < 1e-6 {
+                break;
+            }
+        }
+        // Mirroring goes into the stretch, so that the rotation can be interpolated
+        if rotation.determinant3() < 0.0 {
+            for row in rotation.rows.iter_mut().take(3) {
+                for value in row.iter_mut().take(3) {
+                    *value = -*value;
+                }
+            }
+        }
+
+        let stretch = rotation.transposed() * linear;
+        (translation, rotation, stretch)
+    }
+
+    /// Axis and angle in degrees of a rotation matrix, as taken by `rotation`.
+    pub fn rotation_axis_angle(&self) -> (Vec3, f32) {
+        let m = &self.rows;
+        let cos = ((m[0][0] + m[1][1] + m[2][2] - 1.0) / 2.0).clamp(-1.0, 1.0);
+        let angle = cos.acos();
+        let axis = Vec3::new(m[2][1] - m[1][2], m[0][2] - m[2][0], m[1][0] - m[0][1]);
+        if axis.length() > 1e-4 {
+            return (axis.normalized(), angle.to_degrees());
+        }
+        if cos > 0.0 {
+            return (Vec3::UP, 0.0);
+        }
+
+        // Half turn, the matrix is 2 a a^T - I
+        let k = (0..3).max_by(|&a, &b| m[a][a].total_cmp(&m[b][b])).unwrap();
+        let a_k = ((m[k][k] + 1.0) / 2.0).max(0.0).sqrt();
+        let mut axis = [0.0; 3];
+        for (j, value) in axis.iter_mut().enumerate() {
+            *value = if j == k { a_k } else { m[k][j] / (2.0 * a_k) };
+        }
+        (Vec3::new(axis[0], axis[1], axis[2]).normalized(), 180.0)
+    }
+
+    /// Frobenius norm of the upper 3x3 part, a bound on how much it can
+    /// lengthen a vector.
+    fn norm3(&self) -> f32 {
+        let mut sum = 0.0;
+        for row in self.rows.iter().take(3) {
+            for value in row.iter().take(3) {
+                sum += value * value;
+            }
+        }
+        sum.sqrt()
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        self.transform_vector(p) + Vec3::new(self.rows[0][3], self.rows[1][3], self.rows[2][3])
+    }
+
+    /// Applies the transform without the translation.
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.rows;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// Multiplies by the transpose. Called on the inverse of a transform this
+    /// keeps normals perpendicular to the transformed surface.
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        let m = &self.rows;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Mat4::IDENTITY
+    }
+}
+
+impl ops::Mul<Self> for Mat4 {
+    type Output = Self;
+
+    /// `a * b` applies `b` first, then `a`.
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut rows = [[0.0; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.rows[i][k] * rhs.rows[k][j]).sum();
+            }
+        }
+        Mat4::new(rows)
+    }
+}
+
+/// Inverse of the upper 3x3 part of `m` from its adjugate, for a stretch
+/// that's known to be invertible.
+fn inverse3(m: &Mat4) -> Mat4 {
+    let r = &m.rows;
+    let cofactor = |i: usize, j: usize| {
+        let (i0, i1) = ((i + 1) % 3, (i + 2) % 3);
+        let (j0, j1) = ((j + 1) % 3, (j + 2) % 3);
+        r[i0][j0] * r[i1][j1] - r[i0][j1] * r[i1][j0]
+    };
+    let inverse_determinant = 1.0 / m.determinant3();
+    let mut inverse = Mat4::IDENTITY;
+    for i in 0..3 {
+        for j in 0..3 {
+            inverse.rows[i][j] = cofactor(j, i) * inverse_determinant;
+        }
+    }
+    inverse
+}
+
+/// Why two transforms can't be the ends of an [`AnimatedTransform`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionError {
+    /// An end transform flattens the object, so it can't be inverted.
+    Singular,
+    /// One end mirrors the object and the other doesn't, so the motion passes
+    /// through a flat transform on the way.
+    MirrorChange,
+}
+
+impl fmt::Display for MotionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MotionError::Singular => write!(f, "transform can't be inverted"),
+            MotionError::MirrorChange => write!(
+                f,
+                "transform is mirrored at one end of the motion but not the other"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MotionError {}
+
+/// Transform that moves from `start` at time 0 to `end` at time 1. Translation,
+/// rotation and stretch are interpolated separately, so rotating objects keep
+/// their shape instead of shrinking halfway like with a linear blend of the
+/// matrices.
+#[derive(Debug, Clone, Copy)]
+pub struct AnimatedTransform {
+    start: Mat4,
+    end: Mat4,
+    start_inverse: Mat4,
+    end_inverse: Mat4,
+    translation: (Vec3, Vec3),
+    start_rotation: Mat4,
+    /// Rotation from the start orientation to the end orientation.
+    rotation_axis: Vec3,
+    rotation_angle: f32,
+    stretch: (Mat4, Mat4),
+}
+
+impl AnimatedTransform {
+    /// The stretches from the polar decomposition are both positive or both
+    /// negative definite when the ends are invertible and mirror alike, and so
+    /// is every blend of them, so the transform stays invertible throughout.
+    pub fn new(start: Mat4, end: Mat4) -> Result<AnimatedTransform, MotionError> {
+        let start_inverse = start.inverse().ok_or(MotionError::Singular)?;
+        let end_inverse = end.inverse().ok_or(MotionError::Singular)?;
+        if (start.determinant3() < 0.0) != (end.determinant3() < 0.0) {
+            return Err(MotionError::MirrorChange);
+        }
+
+        let (start_translation, start_rotation, start_stretch) = start.decompose();
+        let (end_translation, end_rotation, end_stretch) = end.decompose();
+        let (rotation_axis, rotation_angle) =
+            (end_rotation * start_rotation.transposed()).rotation_axis_angle();
+        Ok(AnimatedTransform {
+            start,
+            end,
+            start_inverse,
+            end_inverse,
+            translation: (start_translation, end_translation),
+            start_rotation,
+            rotation_axis,
+            rotation_angle,
+            stretch: (start_stretch, end_stretch),
+        })
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.start != self.end
+    }
+
+    /// Transform at `time`, clamped to the [0, 1] range the motion is defined on.
+    pub fn at(&self, time: f32) -> Mat4 {
+        if time <= 0.0 || !self.is_animated() {
+            return self.start;
+        }
+        if time >= 1.0 {
+            return self.end;
+        }
+        Mat4::translation(self.translation_at(time))
+            * self.rotation_at(time)
+            * self.stretch_at(time)
+    }
+
+    /// Inverse of [`AnimatedTransform::at`], put together from the inverses of
+    /// its parts: the reverse translation, the transposed rotation and the
+    /// inverse of the stretch.
+    pub fn inverse_at(&self, time: f32) -> Mat4 {
+        if time <= 0.0 || !self.is_animated() {
+            return self.start_inverse;
+        }
+        if time >= 1.0 {
+            return self.end_inverse;
+        }
+        inverse3(&self.stretch_at(time))
+            * self.rotation_at(time).transposed()
+            * Mat4::translation(-self.translation_at(time))
+    }
+
+    fn translation_at(&self, time: f32) -> Vec3 {
+        let (t0, t1) = self.translation;
+        t0 + (t1 - t0) * time
+    }
+
+    fn rotation_at(&self, time: f32) -> Mat4 {
+        Mat4::rotation(self.rotation_axis, self.rotation_angle * time) * self.start_rotation
+    }
+
+    fn stretch_at(&self, time: f32) -> Mat4 {
+        let (s0, s1) = self.stretch;
+        let mut stretch = Mat4::IDENTITY;
+        for i in 0..3 {
+            for j in 0..3 {
+                stretch.rows[i][j] = s0.rows[i][j] + (s1.rows[i][j] - s0.rows[i][j]) * time;
+            }
+        }
+        stretch
+    }
+
+    /// Upper bound on how fast a point at distance `radius` from the object
+    /// space origin moves per unit of time.
+    pub fn max_speed(&self, radius: f32) -> f32 {
+        let (t0, t1) = self.translation;
+        let (s0, s1) = self.stretch;
+        let mut stretch_change = Mat4::IDENTITY;
+        for i in 0..3 {
+            for j in 0..3 {
+                stretch_change.rows[i][j] = s1.rows[i][j] - s0.rows[i][j];
+            }
+        }
+        let max_stretch = s0.norm3().max(s1.norm3());
+        (t1 - t0).length()
+            + (self.rotation_angle.to_radians().abs() * max_stretch + stretch_change.norm3())
+                * radius
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+
+    fn assert_vec_eq(a: Vec3, b: Vec3) {
+        assert_approx_eq!(a.x, b.x, 1e-4);
+        assert_approx_eq!(a.y, b.y, 1e-4);
+        assert_approx_eq!(a.z, b.z, 1e-4);
+    }
+
+    #[test]
+    fn rotation_is_counter_clockwise() {
+        let rotation = Mat4::rotation(Vec3::UP, 90.0);
+        assert_vec_eq(rotation.transform_vector(&Vec3::RIGHT), Vec3::FORWARD);
+        let rotation = Mat4::rotation(Vec3::BACKWARD, 90.0);
+        assert_vec_eq(rotation.transform_vector(&Vec3::RIGHT), Vec3::UP);
+    }
+
+    #[test]
+    fn composition_and_inverse() {
+        let transform = Mat4::translation(Vec3::new(1.0, 2.0, 3.0))
+            * Mat4::rotation(Vec3::new(1.0, 1.0, 0.0), 30.0)
+            * Mat4::scale(Vec3::new(2.0, 0.5, -1.0));
+        let p = Vec3::new(0.3, -0.7, 1.1);
+
+        // Scaled first, translated last
+        let expected = Mat4::rotation(Vec3::new(1.0, 1.0, 0.0), 30.0)
+            .transform_point(&Vec3::new(0.6, -0.35, -1.1))
+            + Vec3::new(1.0, 2.0, 3.0);
+        assert_vec_eq(transform.transform_point(&p), expected);
+
+        let inverse = transform.inverse().unwrap();
+        assert_vec_eq(inverse.transform_point(&transform.transform_point(&p)), p);
+        let identity = transform * inverse;
+        for (i, row) in identity.rows.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                assert_approx_eq!(*value, if i == j { 1.0 } else { 0.0 }, 1e-5);
+            }
+        }
+        assert_approx_eq!(transform.determinant3(), -1.0, 1e-5);
+        assert!(Mat4::scale(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    fn assert_mat_eq(a: &Mat4, b: &Mat4) {
+        for (row_a, row_b) in a.rows.iter().zip(b.rows.iter()) {
+            for (x, y) in row_a.iter().zip(row_b.iter()) {
+                assert_approx_eq!(x, y, 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn decompose_and_axis_angle() {
+        let axis = Vec3::new(1.0, 2.0, -0.5).normalized();
+        for angle in [0.0, 40.0, 135.0, 180.0] {
+            let rotation = Mat4::rotation(axis, angle);
+            let (found_axis, found_angle) = rotation.rotation_axis_angle();
+            assert_mat_eq(&Mat4::rotation(found_axis, found_angle), &rotation);
+
+            let transform = Mat4::translation(Vec3::new(4.0, -1.0, 2.0))
+                * rotation
+                * Mat4::scale(Vec3::new(2.0, 0.5, -1.5));
+            let (translation, found_rotation, stretch) = transform.decompose();
+            assert_vec_eq(translation, Vec3::new(4.0, -1.0, 2.0));
+            assert!(found_rotation.determinant3() > 0.0);
+            assert_mat_eq(
+                &(Mat4::translation(translation) * found_rotation * stretch),
+                &transform,
+            );
+        }
+    }
+
+    #[test]
+    fn animated_transform_rotates_rigidly() {
+        let start = Mat4::translation(Vec3::new(1.0, 0.0, 0.0));
+        let end = Mat4::translation(Vec3::new(3.0, 2.0, 0.0)) * Mat4::rotation(Vec3::UP, 170.0);
+        let animated = AnimatedTransform::new(start, end).unwrap();
+        assert!(animated.is_animated());
+        assert_mat_eq(&animated.at(0.0), &start);
+        assert_mat_eq(&animated.at(1.0), &end);
+
+        let halfway = animated.at(0.5);
+        assert_vec_eq(
+            halfway.transform_vector(&Vec3::RIGHT),
+            Mat4::rotation(Vec3::UP, 85.0).transform_vector(&Vec3::RIGHT),
+        );
+        assert_vec_eq(
+            halfway.transform_point(&Vec3::ZERO),
+            Vec3::new(2.0, 1.0, 0.0),
+        );
+        assert_approx_eq!(halfway.determinant3(), 1.0, 1e-4);
+        assert!(!AnimatedTransform::new(start, start).unwrap().is_animated());
+    }
+
+    #[test]
+    fn animated_inverse() {
+        let start = Mat4::translation(Vec3::new(1.0, 0.0, 0.0))
+            * Mat4::rotation(Vec3::BACKWARD, 20.0)
+            * Mat4::scale(Vec3::new(0.1, 1.0, 2.0));
+        let end = Mat4::translation(Vec3::new(-2.0, 4.0, 1.0))
+            * Mat4::rotation(Vec3::new(1.0, 1.0, 1.0), 120.0)
+            * Mat4::scale(Vec3::new(3.0, 0.2, 1.0));
+        for (start, end) in [
+            (start, end),
+            (
+                start * Mat4::scale(Vec3::new(1.0, -1.0, 1.0)),
+                end * Mat4::scale(Vec3::new(-1.0, 1.0, 1.0)),
+            ),
+        ] {
+            let animated = AnimatedTransform::new(start, end).unwrap();
+            for i in 0..=10 {
+                let time = i as f32 / 10.0;
+                assert_mat_eq(
+                    &(animated.at(time) * animated.inverse_at(time)),
+                    &Mat4::IDENTITY,
+                );
+            }
+        }
+
+        let flat = Mat4::scale(Vec3::new(1.0, 0.0, 1.0));
+        assert_eq!(
+            AnimatedTransform::new(start, flat).err(),
+            Some(MotionError::Singular)
+        );
+        let mirrored = end * Mat4::scale(Vec3::new(1.0, 1.0, -1.0));
+        assert_eq!(
+            AnimatedTransform::new(start, mirrored).err(),
+            Some(MotionError::MirrorChange)
+        );
+    }
+}
@@ -1,4 +1,5 @@
 pub mod aabb;
 pub mod interval;
+pub mod mat4;
 pub mod ray;
 pub mod vec3;
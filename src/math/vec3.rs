@@ -51,6 +51,7 @@ impl Vec3 {
         *self - 2.0 * Vec3::dot(self, normal) * *normal
     }
 
+    #[allow(clippy::neg_multiply)]
     pub fn refracted(&self, normal: &Vec3, ior_ratio: f32) -> Vec3 {
         let cos_tetha = Vec3::dot(&(-*self), normal);
         let dir_out_perp = (*self + cos_tetha * *normal) * ior_ratio;
@@ -59,6 +60,28 @@ impl Vec3 {
         dir_out_perp + dir_out_parallel
     }
 
+    pub fn abs(&self) -> Vec3 {
+        Vec3::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+
+    pub fn max_dimension(&self) -> usize {
+        if self.x > self.y {
+            if self.x > self.z {
+                0
+            } else {
+                2
+            }
+        } else if self.y > self.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn permuted(&self, x: usize, y: usize, z: usize) -> Vec3 {
+        Vec3::new(self[x], self[y], self[z])
+    }
+
     pub fn dot(a: &Vec3, b: &Vec3) -> f32 {
         a.x * b.x + a.y * b.y + a.z * b.z
     }
@@ -257,6 +280,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn near_zero_works() {
         assert_eq!(Vec3::ZERO.near_zero(), true);
         assert_eq!(Vec3::ONE.near_zero(), false);
@@ -288,6 +312,20 @@ mod tests {
         assert_eq!(refracted.length(), 1.0);
     }
 
+    #[test]
+    fn max_dimension_works() {
+        assert_eq!(Vec3::new(3.0, 1.0, 2.0).max_dimension(), 0);
+        assert_eq!(Vec3::new(1.0, 3.0, 2.0).max_dimension(), 1);
+        assert_eq!(Vec3::new(1.0, 2.0, 3.0).max_dimension(), 2);
+        assert_eq!(Vec3::new(-10.0, 2.0, 3.0).abs().max_dimension(), 0);
+    }
+
+    #[test]
+    fn permuted_works() {
+        let v = Vec3::new(10.0, -5.5, 7.0);
+        assert_eq!(v.permuted(2, 0, 1), Vec3::new(7.0, 10.0, -5.5));
+    }
+
     #[test]
     fn dot_produt_works() {
         let a = Vec3::new(10.0, -5.5, 7.0);
@@ -405,6 +443,7 @@ mod tests {
 
     #[test]
     #[should_panic]
+    #[allow(clippy::no_effect)]
     fn index_oper_out_of_bounds() {
         let a = Vec3::new(10.0, -5.5, 7.0);
         a[3];
@@ -0,0 +1,346 @@
+//! Gradient and cellular noise for procedural textures.
+
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::math::vec3::Vec3;
+
+pub trait Noise: Send + Sync {
+    /// Smooth noise in roughly [-1, 1].
+    fn noise(&self, p: &Vec3) -> f32;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+}
+
+impl NoiseKind {
+    pub const NAMES: [&'static str; 2] = ["perlin", "simplex"];
+
+    pub fn from_name(name: &str) -> Option<NoiseKind> {
+        match name {
+            "perlin" => Some(NoiseKind::Perlin),
+            "simplex" => Some(NoiseKind::Simplex),
+            _ => None,
+        }
+    }
+
+    pub fn build(&self, seed: u64) -> Arc<dyn Noise> {
+        match self {
+            NoiseKind::Perlin => Arc::new(Perlin::new(seed)),
+            NoiseKind::Simplex => Arc::new(Simplex::new(seed)),
+        }
+    }
+}
+
+/// Shuffled 0..256, repeated so that nested lookups don't need wrapping.
+struct PermutationTable([u8; 512]);
+
+impl PermutationTable {
+    fn new(seed: u64) -> PermutationTable {
+        let mut values: Vec<u8> = (0..=255).collect();
+        values.shuffle(&mut StdRng::seed_from_u64(seed));
+
+        let mut table = [0; 512];
+        for (i, value) in table.iter_mut().enumerate() {
+            *value = values[i % 256];
+        }
+        PermutationTable(table)
+    }
+
+    /// Pseudo-random value in 0..256 for a lattice point.
+    fn hash(&self, x: i32, y: i32, z: i32) -> usize {
+        let p = &self.0;
+        let i = p[(x & 255) as usize] as usize + (y & 255) as usize;
+        let i = p[i] as usize + (z & 255) as usize;
+        p[i] as usize
+    }
+}
+
+/// Dot product of the offset with one of the 12 cube edge directions.
+fn gradient_dot(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+/// Ken Perlin's improved noise, zero at every integer lattice point.
+pub struct Perlin {
+    permutation: PermutationTable,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        Perlin {
+            permutation: PermutationTable::new(seed),
+        }
+    }
+}
+
+impl Noise for Perlin {
+    fn noise(&self, p: &Vec3) -> f32 {
+        let (xi, yi, zi) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (x, y, z) = (p.x - xi, p.y - yi, p.z - zi);
+        let (xi, yi, zi) = (xi as i32, yi as i32, zi as i32);
+
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let corner = |dx: i32, dy: i32, dz: i32| {
+            let hash = self.permutation.hash(xi + dx, yi + dy, zi + dz);
+            gradient_dot(hash, x - dx as f32, y - dy as f32, z - dz as f32)
+        };
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+                lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+            ),
+            lerp(
+                v,
+                lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+                lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+            ),
+        )
+    }
+}
+
+/// Simplex noise after Stefan Gustavson's reference implementation. Cheaper
+/// than Perlin noise per octave and without its axis aligned artefacts.
+pub struct Simplex {
+    permutation: PermutationTable,
+}
+
+impl Simplex {
+    pub fn new(seed: u64) -> Simplex {
+        Simplex {
+            permutation: PermutationTable::new(seed),
+        }
+    }
+}
+
+impl Noise for Simplex {
+    fn noise(&self, p: &Vec3) -> f32 {
+        const SKEW: f32 = 1.0 / 3.0;
+        const UNSKEW: f32 = 1.0 / 6.0;
+
+        // Find the simplex cell and the offset from its first corner
+        let s = (p.x + p.y + p.z) * SKEW;
+        let (i, j, k) = ((p.x + s).floor(), (p.y + s).floor(), (p.z + s).floor());
+        let t = (i + j + k) * UNSKEW;
+        let x0 = Vec3::new(p.x - i + t, p.y - j + t, p.z - k + t);
+        let (i, j, k) = (i as i32, j as i32, k as i32);
+
+        // The middle two corners depend on which of the six tetrahedra we're in
+        let (step1, step2) = if x0.x >= x0.y {
+            if x0.y >= x0.z {
+                ((1, 0, 0), (1, 1, 0))
+            } else if x0.x >= x0.z {
+                ((1, 0, 0), (1, 0, 1))
+            } else {
+                ((0, 0, 1), (1, 0, 1))
+            }
+        } else if x0.y < x0.z {
+            ((0, 0, 1), (0, 1, 1))
+        } else if x0.x < x0.z {
+            ((0, 1, 0), (0, 1, 1))
+        } else {
+            ((0, 1, 0), (1, 1, 0))
+        };
+
+        let corners = [(0, 0, 0), step1, step2, (1, 1, 1)];
+        let mut sum = 0.0;
+        for (n, (di, dj, dk)) in corners.into_iter().enumerate() {
+            let offset = n as f32 * UNSKEW;
+            let x = x0.x - di as f32 + offset;
+            let y = x0.y - dj as f32 + offset;
+            let z = x0.z - dk as f32 + offset;
+            let falloff = 0.6 - x * x - y * y - z * z;
+            if falloff > 0.0 {
+                let hash = self.permutation.hash(i + di, j + dj, k + dk);
+                sum += falloff.powi(4) * gradient_dot(hash, x, y, z);
+            }
+        }
+        32.0 * sum
+    }
+}
+
+/// Cellular noise with one randomly placed feature point per unit cell.
+pub struct Worley {
+    permutation: PermutationTable,
+}
+
+impl Worley {
+    pub fn new(seed: u64) -> Worley {
+        Worley {
+            permutation: PermutationTable::new(seed),
+        }
+    }
+
+    /// Distances to the closest and second closest feature points.
+    pub fn distances(&self, p: &Vec3) -> (f32, f32) {
+        let cell = (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
+        let table = &self.permutation.0;
+
+        let mut closest = (f32::INFINITY, f32::INFINITY);
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (x, y, z) = (cell.0 + dx, cell.1 + dy, cell.2 + dz);
+                    let hash = self.permutation.hash(x, y, z);
+                    let feature = Vec3::new(
+                        x as f32 + table[hash] as f32 / 256.0,
+                        y as f32 + table[hash + 1] as f32 / 256.0,
+                        z as f32 + table[hash + 2] as f32 / 256.0,
+                    );
+                    let distance = (feature - *p).length();
+                    if distance < closest.0 {
+                        closest = (distance, closest.0);
+                    } else if distance < closest.1 {
+                        closest.1 = distance;
+                    }
+                }
+            }
+        }
+        closest
+    }
+}
+
+/// Sums octaves of noise at increasing frequencies and decreasing amplitudes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fractal {
+    /// Frequency of the first octave.
+    pub frequency: f32,
+    pub octaves: u32,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f32,
+    /// Amplitude multiplier between octaves.
+    pub gain: f32,
+}
+
+impl Default for Fractal {
+    fn default() -> Self {
+        Fractal {
+            frequency: 1.0,
+            octaves: 5,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+}
+
+impl Fractal {
+    /// Weighted average of `f` over the octaves.
+    pub fn sum(&self, p: &Vec3, f: impl Fn(&Vec3) -> f32) -> f32 {
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        let mut sum = 0.0;
+        let mut total_amplitude = 0.0;
+        for _ in 0..self.octaves.max(1) {
+            sum += amplitude * f(&(*p * frequency));
+            total_amplitude += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        sum / total_amplitude
+    }
+
+    /// Fractal Brownian motion, in roughly [-1, 1].
+    pub fn fbm(&self, noise: &dyn Noise, p: &Vec3) -> f32 {
+        self.sum(p, |p| noise.noise(p))
+    }
+
+    /// Sum of the absolute noise, in [0, 1] with creases where the noise crosses zero.
+    pub fn turbulence(&self, noise: &dyn Noise, p: &Vec3) -> f32 {
+        self.sum(p, |p| noise.noise(p).abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    fn random_points() -> impl Iterator<Item = Vec3> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..2000).map(move |_| {
+            Vec3::new(
+                rng.gen_range(-50.0..50.0),
+                rng.gen_range(-50.0..50.0),
+                rng.gen_range(-50.0..50.0),
+            )
+        })
+    }
+
+    #[test]
+    fn noise_is_bounded_and_seeded() {
+        for kind in [NoiseKind::Perlin, NoiseKind::Simplex] {
+            let (noise, same_seed, other_seed) = (kind.build(1), kind.build(1), kind.build(2));
+            let mut differs = false;
+            for p in random_points() {
+                let value = noise.noise(&p);
+                assert!((-1.05..=1.05).contains(&value), "{} at {:?}", value, p);
+                assert_eq!(value, same_seed.noise(&p));
+                differs |= value != other_seed.noise(&p);
+            }
+            assert!(differs);
+        }
+    }
+
+    #[test]
+    fn perlin_is_zero_on_the_lattice() {
+        let perlin = Perlin::new(3);
+        for p in [
+            Vec3::ZERO,
+            Vec3::new(4.0, -2.0, 7.0),
+            Vec3::new(-300.0, 1.0, 0.0),
+        ] {
+            assert_eq!(perlin.noise(&p), 0.0);
+        }
+        assert_ne!(perlin.noise(&Vec3::new(0.3, 0.6, 0.2)), 0.0);
+    }
+
+    #[test]
+    fn worley_distances() {
+        let worley = Worley::new(5);
+        for p in random_points() {
+            let (f1, f2) = worley.distances(&p);
+            assert!(f1 <= f2);
+            // A point is never more than a cell diagonal from its own cell's feature
+            assert!(f1 < 3f32.sqrt());
+        }
+    }
+
+    #[test]
+    fn fractal_octaves_are_normalised() {
+        let perlin = Perlin::new(9);
+        let fractal = Fractal {
+            octaves: 8,
+            ..Default::default()
+        };
+        for p in random_points() {
+            assert!(fractal.fbm(&perlin, &p).abs() <= 1.05);
+            assert!((0.0..=1.05).contains(&fractal.turbulence(&perlin, &p)));
+        }
+    }
+}
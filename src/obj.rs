@@ -0,0 +1,664 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    hittables::{HittableList, MeshData, MeshError, TriangleMesh},
+    image::{ColorSpace, ImageCache, ImageError},
+    materials::{self, Material},
+    math::vec3::Vec3,
+    textures::{ImageTexture, SolidColorTexture, Texture, TextureFilter, WrapMode},
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, io::Error),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    Image(ImageError),
+    Mesh(PathBuf, MeshError),
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Image(err) => err.fmt(f),
+            ObjError::Mesh(path, err) => write!(f, "{}: {}", path.display(), err),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+impl From<ImageError> for ObjError {
+    fn from(err: ImageError) -> Self {
+        ObjError::Image(err)
+    }
+}
+
+/// Material description read from an MTL file, before it is mapped onto one of
+/// the renderer's materials.
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub diffuse: Vec3,
+    pub specular: Vec3,
+    pub emission: Vec3,
+    pub shininess: f32,
+    pub ior: f32,
+    pub dissolve: f32,
+    pub illum: u32,
+    pub diffuse_map: Option<PathBuf>,
+}
+
+impl MtlMaterial {
+    fn new(name: String) -> MtlMaterial {
+        MtlMaterial {
+            name,
+            diffuse: Vec3::uniform(0.8),
+            specular: Vec3::ZERO,
+            emission: Vec3::ZERO,
+            shininess: 0.0,
+            ior: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            diffuse_map: None,
+        }
+    }
+
+    /// Picks the closest matching material: emissive surfaces become lights,
+    /// transparent ones glass, specular-only ones metal and the rest diffuse.
+    /// Diffuse maps are loaded through `images`.
+    pub fn to_material(&self, images: &mut ImageCache) -> Result<Arc<dyn Material>, ImageError> {
+        if !self.emission.near_zero() {
+            return Ok(Arc::new(materials::DiffuseLight {
+                color: self.emission,
+            }));
+        }
+
+        if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7) {
+            let ior = if self.ior > 1.0 { self.ior } else { 1.5 };
+            return Ok(Arc::new(materials::Dielectric { ior }));
+        }
+
+        let has_diffuse = !self.diffuse.near_zero() || self.diffuse_map.is_some();
+        if !self.specular.near_zero() && (!has_diffuse || self.illum == 3) {
+            // Map the Phong exponent onto a roughness in [0, 1]
+            let roughness = (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt();
+            return Ok(Arc::new(materials::Metal {
+                albedo: self.specular,
+                roughness,
+            }));
+        }
+
+        // The diffuse map replaces Kd rather than being tinted by it
+        let albedo: Arc<dyn Texture> = match &self.diffuse_map {
+            Some(path) => Arc::new(ImageTexture {
+                image: images.load(path, ColorSpace::Srgb)?,
+                filter: TextureFilter::Bilinear,
+                wrap: WrapMode::Repeat,
+            }),
+            None => Arc::new(SolidColorTexture {
+                color: self.diffuse,
+            }),
+        };
+        Ok(Arc::new(materials::Lambertian { albedo }))
+    }
+}
+
+pub struct ObjGroup {
+    pub name: String,
+    pub material_name: Option<String>,
+    pub mesh: Arc<TriangleMesh>,
+}
+
+pub struct ObjModel {
+    pub groups: Vec<ObjGroup>,
+    pub materials: HashMap<String, MtlMaterial>,
+}
+
+impl ObjModel {
+    pub fn triangle_count(&self) -> usize {
+        self.groups.iter().map(|g| g.mesh.triangle_count()).sum()
+    }
+
+    pub fn to_hittable_list(&self) -> HittableList {
+        let mut list = HittableList::new();
+        for group in self.groups.iter() {
+            list.add(group.mesh.clone());
+        }
+        list
+    }
+}
+
+/// Loads an OBJ file and the MTL libraries it references. Faces without a
+/// `usemtl` (or referencing an unknown material) use `default_material`.
+/// Texture maps are loaded through `images`.
+pub fn load_obj(
+    path: &Path,
+    default_material: Arc<dyn Material>,
+    images: &mut ImageCache,
+) -> Result<ObjModel, ObjError> {
+    let file = File::open(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse_obj(
+        BufReader::new(file),
+        path,
+        base_dir,
+        default_material,
+        true,
+        images,
+    )
+}
+
+/// Loads an OBJ file with every face using `material`, ignoring its MTL files.
+pub fn load_obj_with_material(
+    path: &Path,
+    material: Arc<dyn Material>,
+) -> Result<ObjModel, ObjError> {
+    let file = File::open(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse_obj(
+        BufReader::new(file),
+        path,
+        base_dir,
+        material,
+        false,
+        &mut ImageCache::default(),
+    )
+}
+
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let file = File::open(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse_mtl(BufReader::new(file), path, base_dir)
+}
+
+struct LineParser<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl LineParser<'_> {
+    fn error(&self, message: impl Into<String>) -> ObjError {
+        ObjError::Parse {
+            path: self.path.to_path_buf(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn float(&self, token: Option<&str>, what: &str) -> Result<f32, ObjError> {
+        let token = token.ok_or_else(|| self.error(format!("missing {}", what)))?;
+        token
+            .parse()
+            .map_err(|_| self.error(format!("invalid {} '{}'", what, token)))
+    }
+
+    fn vec3<'t>(&self, tokens: &mut impl Iterator<Item = &'t str>) -> Result<Vec3, ObjError> {
+        Ok(Vec3::new(
+            self.float(tokens.next(), "x component")?,
+            self.float(tokens.next(), "y component")?,
+            self.float(tokens.next(), "z component")?,
+        ))
+    }
+
+    /// Resolves a 1-based (or negative, relative) OBJ index into `0..count`.
+    fn index(&self, token: &str, count: usize, what: &str) -> Result<usize, ObjError> {
+        let index: i64 = token
+            .parse()
+            .map_err(|_| self.error(format!("invalid {} index '{}'", what, token)))?;
+        let resolved = match index {
+            0 => None,
+            i if i > 0 => Some(i as usize - 1),
+            i => count.checked_sub(i.unsigned_abs() as usize),
+        };
+        match resolved {
+            Some(i) if i < count => Ok(i),
+            _ => Err(self.error(format!(
+                "{} index {} out of range ({} defined)",
+                what, index, count
+            ))),
+        }
+    }
+}
+
+/// Indices of a face corner into the position, texcoord and normal arrays.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+#[derive(Default)]
+struct GroupBuilder {
+    name: String,
+    material_name: Option<String>,
+    faces: Vec<[Corner; 3]>,
+}
+
+pub fn parse_obj(
+    reader: impl BufRead,
+    path: &Path,
+    base_dir: &Path,
+    default_material: Arc<dyn Material>,
+    use_mtl_materials: bool,
+    images: &mut ImageCache,
+) -> Result<ObjModel, ObjError> {
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<(f32, f32)> = Vec::new();
+    let mut mtl_materials: HashMap<String, MtlMaterial> = HashMap::new();
+
+    let mut groups: Vec<GroupBuilder> = Vec::new();
+    let mut current = GroupBuilder {
+        name: String::from("default"),
+        ..Default::default()
+    };
+
+    let mut parser = LineParser { path, line: 0 };
+    for line in reader.lines() {
+        parser.line += 1;
+        let line = line.map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+
+        match keyword {
+            "v" => positions.push(parser.vec3(&mut tokens)?),
+            "vn" => normals.push(parser.vec3(&mut tokens)?.normalized()),
+            "vt" => {
+                let u = parser.float(tokens.next(), "u coordinate")?;
+                let v = match tokens.next() {
+                    Some(token) => parser.float(Some(token), "v coordinate")?,
+                    None => 0.0,
+                };
+                uvs.push((u, v));
+            }
+            "f" => {
+                let mut corners: Vec<Corner> = Vec::new();
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let position =
+                        parser.index(parts.next().unwrap(), positions.len(), "vertex")?;
+                    let uv = match parts.next() {
+                        Some("") | None => None,
+                        Some(t) => Some(parser.index(t, uvs.len(), "texture coordinate")?),
+                    };
+                    let normal = match parts.next() {
+                        Some("") | None => None,
+                        Some(t) => Some(parser.index(t, normals.len(), "normal")?),
+                    };
+                    corners.push((position, uv, normal));
+                }
+                if corners.len() < 3 {
+                    return Err(parser.error("face needs at least 3 vertices"));
+                }
+
+                let points: Vec<Vec3> = corners.iter().map(|c| positions[c.0]).collect();
+                for [a, b, c] in triangulate(&points) {
+                    current.faces.push([corners[a], corners[b], corners[c]]);
+                }
+            }
+            "g" | "o" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                let material_name = current.material_name.clone();
+                groups.push(std::mem::take(&mut current));
+                current.name = if name.is_empty() {
+                    String::from("default")
+                } else {
+                    name
+                };
+                current.material_name = material_name;
+            }
+            "usemtl" => {
+                let name = tokens
+                    .next()
+                    .ok_or_else(|| parser.error("usemtl without a material name"))?;
+                if current.material_name.as_deref() != Some(name) {
+                    let group_name = current.name.clone();
+                    groups.push(std::mem::take(&mut current));
+                    current.name = group_name;
+                    current.material_name = Some(name.to_string());
+                }
+            }
+            "mtllib" if use_mtl_materials => {
+                // A missing material library shouldn't lose the geometry, its
+                // groups fall back to the default material
+                for file in tokens {
+                    match load_mtl(&base_dir.join(file)) {
+                        Ok(library) => mtl_materials.extend(library),
+                        Err(err @ ObjError::Io(..)) => {
+                            eprintln!("warning: {}, using the default material", err)
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+            // Smoothing groups, lines, points and free-form geometry are ignored
+            _ => (),
+        }
+    }
+    groups.push(current);
+
+    let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    let mut model = ObjModel {
+        groups: Vec::new(),
+        materials: HashMap::new(),
+    };
+    for group in groups.into_iter().filter(|g| !g.faces.is_empty()) {
+        let material = match group
+            .material_name
+            .as_ref()
+            .and_then(|name| mtl_materials.get(name))
+        {
+            Some(mtl) => match materials.get(&mtl.name) {
+                Some(material) => material.clone(),
+                None => {
+                    let material = mtl.to_material(images)?;
+                    materials.insert(mtl.name.clone(), material.clone());
+                    material
+                }
+            },
+            None => default_material.clone(),
+        };
+
+        let data = build_mesh_data(&group.faces, &positions, &uvs, &normals);
+        model.groups.push(ObjGroup {
+            name: group.name,
+            material_name: group.material_name,
+            mesh: Arc::new(
+                TriangleMesh::new(data, material)
+                    .map_err(|err| ObjError::Mesh(path.to_path_buf(), err))?,
+            ),
+        });
+    }
+    model.materials = mtl_materials;
+
+    Ok(model)
+}
+
+/// Builds a compact vertex buffer for one group, merging corners that share the
+/// same position/texcoord/normal triple.
+fn build_mesh_data(
+    faces: &[[Corner; 3]],
+    positions: &[Vec3],
+    uvs: &[(f32, f32)],
+    normals: &[Vec3],
+) -> MeshData {
+    let all_have_uvs = faces.iter().flatten().all(|c| c.1.is_some());
+    let all_have_normals = faces.iter().flatten().all(|c| c.2.is_some());
+
+    let mut data = MeshData {
+        positions: Vec::new(),
+        normals: all_have_normals.then(Vec::new),
+        uvs: all_have_uvs.then(Vec::new),
+        indices: Vec::with_capacity(faces.len()),
+    };
+
+    let mut vertex_map: HashMap<Corner, u32> = HashMap::new();
+    for face in faces {
+        let indices = face.map(|corner| {
+            *vertex_map.entry(corner).or_insert_with(|| {
+                data.positions.push(positions[corner.0]);
+                if let Some(uv_buffer) = data.uvs.as_mut() {
+                    uv_buffer.push(uvs[corner.1.unwrap()]);
+                }
+                if let Some(normal_buffer) = data.normals.as_mut() {
+                    normal_buffer.push(normals[corner.2.unwrap()]);
+                }
+                (data.positions.len() - 1) as u32
+            })
+        });
+        data.indices.push(indices);
+    }
+
+    data
+}
+
+/// Splits a planar polygon into triangles by ear clipping, which also handles
+/// concave faces. Falls back to a fan for degenerate input.
+fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Newell's method gives a robust polygon normal, project onto its dominant plane
+    let mut normal = Vec3::ZERO;
+    for i in 0..n {
+        let a = points[i];
+        let b = points[(i + 1) % n];
+        normal += Vec3::new(
+            (a.y - b.y) * (a.z + b.z),
+            (a.z - b.z) * (a.x + b.x),
+            (a.x - b.x) * (a.y + b.y),
+        );
+    }
+    let drop_axis = normal.abs().max_dimension();
+    let (ax, ay) = ((drop_axis + 1) % 3, (drop_axis + 2) % 3);
+    let sign = if normal[drop_axis] >= 0.0 { 1.0 } else { -1.0 };
+    let projected: Vec<(f32, f32)> = points.iter().map(|p| (p[ax], p[ay])).collect();
+
+    let cross = |o: usize, a: usize, b: usize| {
+        let (o, a, b) = (projected[o], projected[a], projected[b]);
+        sign * ((a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0))
+    };
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let prev = remaining[(i + m - 1) % m];
+            let cur = remaining[i];
+            let next = remaining[(i + 1) % m];
+            if cross(prev, cur, next) <= 0.0 {
+                return false;
+            }
+            remaining.iter().all(|&other| {
+                other == prev
+                    || other == cur
+                    || other == next
+                    || cross(prev, cur, other) < 0.0
+                    || cross(cur, next, other) < 0.0
+                    || cross(next, prev, other) < 0.0
+            })
+        });
+
+        let Some(i) = ear else {
+            // Degenerate or self-intersecting polygon
+            for i in 1..remaining.len() - 1 {
+                triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+            }
+            return triangles;
+        };
+
+        triangles.push([
+            remaining[(i + m - 1) % m],
+            remaining[i],
+            remaining[(i + 1) % m],
+        ]);
+        remaining.remove(i);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+
+    triangles
+}
+
+pub fn parse_mtl(
+    reader: impl BufRead,
+    path: &Path,
+    base_dir: &Path,
+) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<MtlMaterial> = None;
+
+    let mut parser = LineParser { path, line: 0 };
+    for line in reader.lines() {
+        parser.line += 1;
+        let line = line.map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+
+        if keyword == "newmtl" {
+            let name = tokens
+                .next()
+                .ok_or_else(|| parser.error("newmtl without a material name"))?;
+            if let Some(material) = current.take() {
+                materials.insert(material.name.clone(), material);
+            }
+            current = Some(MtlMaterial::new(name.to_string()));
+            continue;
+        }
+
+        let material = current
+            .as_mut()
+            .ok_or_else(|| parser.error(format!("'{}' before newmtl", keyword)))?;
+        match keyword {
+            "Kd" => material.diffuse = parser.vec3(&mut tokens)?,
+            "Ks" => material.specular = parser.vec3(&mut tokens)?,
+            "Ke" => material.emission = parser.vec3(&mut tokens)?,
+            "Ns" => material.shininess = parser.float(tokens.next(), "Ns")?,
+            "Ni" => material.ior = parser.float(tokens.next(), "Ni")?,
+            "d" => material.dissolve = parser.float(tokens.next(), "d")?,
+            "Tr" => material.dissolve = 1.0 - parser.float(tokens.next(), "Tr")?,
+            "illum" => {
+                let token = tokens
+                    .next()
+                    .ok_or_else(|| parser.error("missing illum model"))?;
+                material.illum = token
+                    .parse()
+                    .map_err(|_| parser.error(format!("invalid illum model '{}'", token)))?;
+            }
+            "map_Kd" => {
+                // Options such as -s or -o come before the file name
+                let file = tokens
+                    .last()
+                    .ok_or_else(|| parser.error("map_Kd without a file name"))?;
+                material.diffuse_map = Some(base_dir.join(file));
+            }
+            _ => (), // Ka, Tf, bump maps etc. have no equivalent
+        }
+    }
+    if let Some(material) = current {
+        materials.insert(material.name.clone(), material);
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        hittables::Hittable,
+        math::{interval::Interval, ray::Ray},
+    };
+
+    fn parse(source: &str) -> Result<ObjModel, ObjError> {
+        let default_material = Arc::new(materials::DiffuseLight { color: Vec3::ONE });
+        parse_obj(
+            source.as_bytes(),
+            Path::new("test.obj"),
+            Path::new(""),
+            default_material,
+            true,
+            &mut ImageCache::default(),
+        )
+    }
+
+    #[test]
+    fn missing_material_library_falls_back_to_the_default() {
+        let model =
+            parse("mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n").unwrap();
+        assert_eq!(model.groups.len(), 1);
+        assert!(model.materials.is_empty());
+    }
+
+    #[test]
+    fn quad_is_triangulated() {
+        let model = parse(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n",
+        )
+        .unwrap();
+        assert_eq!(model.groups.len(), 1);
+        assert_eq!(model.triangle_count(), 2);
+        assert_eq!(model.groups[0].mesh.data().positions.len(), 4);
+
+        let ray = Ray::new(Vec3::new(0.25, 0.75, 1.0), Vec3::FORWARD);
+        let hit = model.groups[0].mesh.hit(&ray, &Interval::UNIVERSE).unwrap();
+        assert!((hit.u - 0.25).abs() < 1e-5);
+        assert!((hit.v - 0.75).abs() < 1e-5);
+    }
+
+    #[test]
+    fn concave_polygon_is_ear_clipped() {
+        // An L shape, a fan from the first vertex would cover the notch
+        let points = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(2.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(1.0, 2.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+        ];
+        let triangles = triangulate(&points);
+        assert_eq!(triangles.len(), 4);
+
+        let area: f32 = triangles
+            .iter()
+            .map(|[a, b, c]| {
+                Vec3::cross(&(points[*b] - points[*a]), &(points[*c] - points[*a])).length() / 2.0
+            })
+            .sum();
+        assert!((area - 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn groups_and_negative_indices() {
+        let model =
+            parse("g first\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\ng second\nv 0 0 1\nf 1 2 -1\n")
+                .unwrap();
+        let names: Vec<&str> = model.groups.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["first", "second"]);
+    }
+
+    #[test]
+    fn out_of_range_index_reports_line() {
+        match parse("v 0 0 0\nv 1 0 0\n\nf 1 2 3\n") {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 4),
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn mtl_maps_onto_materials() {
+        let materials = parse_mtl(
+            "newmtl light\nKe 4 4 4\nnewmtl glass\nNi 1.45\nd 0.1\nnewmtl gold\nKd 0 0 0\nKs 1 0.8 0.3\nNs 200\n"
+                .as_bytes(),
+            Path::new("test.mtl"),
+            Path::new(""),
+        )
+        .unwrap();
+        assert_eq!(materials.len(), 3);
+        assert_eq!(materials["light"].emission, Vec3::uniform(4.0));
+        assert_eq!(materials["glass"].ior, 1.45);
+        assert_eq!(materials["gold"].shininess, 200.0);
+    }
+}
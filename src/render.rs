@@ -0,0 +1,612 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+};
+
+use crate::{
+    bvh::SplitMethod,
+    camera::{Camera, CameraSample},
+    film::{Accumulator, Overlap, TileSplats},
+    filter::Filter,
+    hittables::{HitInfo, Hittable, HittableList},
+    math::{interval::Interval, ray::Ray, vec3::Vec3},
+    sampler::{Sampler, SamplerKind},
+    tiles::{self, Tile, TileOrder},
+    tonemap::DisplayTransform,
+    utils,
+};
+
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    pub thread_count: u32,
+    pub display: DisplayTransform,
+    /// How the BVH over the scene's objects is built.
+    pub bvh: SplitMethod,
+    /// Samples per pixel added by each progressive pass.
+    pub pass_samples: u32,
+    /// Width and height of the tiles the threads work on.
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    /// Seeds the random numbers of every pixel sample, so renders with the
+    /// same seed and settings come out identical.
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    /// Reconstruction filter that spreads every sample over the pixels around it.
+    pub filter: Filter,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width: 1080 / 2,
+            height: 1080 / 2,
+            samples_per_pixel: 200,
+            max_depth: 20,
+            thread_count: thread::available_parallelism().map_or(1, NonZeroUsize::get) as u32,
+            display: DisplayTransform::default(),
+            bvh: SplitMethod::default(),
+            pass_samples: 16,
+            tile_size: 32,
+            tile_order: TileOrder::default(),
+            seed: 0,
+            sampler: SamplerKind::default(),
+            filter: Filter::default(),
+        }
+    }
+}
+
+/// Power heuristic weight for a sample from the strategy with density `pdf`,
+/// combined with a strategy of density `other_pdf`.
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b == 0.0 {
+        0.0
+    } else {
+        a / (a + b)
+    }
+}
+
+/// Path tracer that combines BSDF sampling and light sampling with multiple
+/// importance sampling.
+struct Tracer<'a, H: Hittable> {
+    world: &'a H,
+    lights: &'a HittableList,
+    background_color: Vec3,
+}
+
+impl<H: Hittable> Tracer<'_, H> {
+    /// `bsdf_pdf` is the density with which the previous vertex sampled `ray`, or
+    /// `None` for camera rays and delta lobes, where the lights weren't sampled.
+    fn ray_color(
+        &self,
+        ray: &Ray,
+        depth: u32,
+        bsdf_pdf: Option<f32>,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        if depth == 0 {
+            return Vec3::ZERO;
+        }
+        let Some(hit_info) = self.world.hit(ray, &Interval::new(0.001, f32::INFINITY)) else {
+            return self.background_color;
+        };
+
+        let mut color = hit_info.material.emitted(&hit_info);
+        if let Some(bsdf_pdf) = bsdf_pdf {
+            let light_pdf = self.lights.pdf_value(&ray.origin, &ray.direction, ray.time);
+            if light_pdf > 0.0 {
+                color *= power_heuristic(bsdf_pdf, light_pdf);
+            }
+        }
+
+        // Every bounce takes the same dimensions, used or not, so they line up
+        // between the samples of a pixel
+        let light_u = sampler.get_2d();
+        let bsdf_uc = sampler.get_1d();
+        let bsdf_u = sampler.get_2d();
+        let Some(sample) = hit_info.material.sample(ray, &hit_info, bsdf_uc, bsdf_u) else {
+            return color;
+        };
+        if !sample.is_delta {
+            color += self.sample_light(ray, &hit_info, light_u);
+        }
+
+        let scattered_ray = Ray::with_time(hit_info.point, sample.direction, ray.time);
+        let next_pdf = (!sample.is_delta).then_some(sample.pdf);
+        color + sample.weight * self.ray_color(&scattered_ray, depth - 1, next_pdf, sampler)
+    }
+
+    /// Light arriving at the hit through a direction picked on one of the lights.
+    fn sample_light(&self, ray: &Ray, hit_info: &HitInfo, u: [f32; 2]) -> Vec3 {
+        if self.lights.is_empty() {
+            return Vec3::ZERO;
+        }
+        let direction = self
+            .lights
+            .random(&hit_info.point, ray.time, u)
+            .normalized();
+        let light_pdf = self.lights.pdf_value(&hit_info.point, &direction, ray.time);
+        let cosine = if hit_info.material.is_phase_function() {
+            1.0
+        } else {
+            Vec3::dot(&hit_info.normal, &direction).abs()
+        };
+        let bsdf = hit_info.material.eval(ray, hit_info, &direction);
+        if light_pdf <= 0.0 || bsdf == Vec3::ZERO {
+            return Vec3::ZERO;
+        }
+
+        // Only the lights count here, the background and anything else that
+        // glows are found by the BSDF samples. A light is seen if nothing in
+        // the world is in front of it
+        let shadow_ray = Ray::with_time(hit_info.point, direction, ray.time);
+        let Some(light_hit) = self
+            .lights
+            .hit(&shadow_ray, &Interval::new(0.001, f32::INFINITY))
+        else {
+            return Vec3::ZERO;
+        };
+        let in_front = Interval::new(0.001, light_hit.t * (1.0 - 1e-4));
+        if self.world.hit(&shadow_ray, &in_front).is_some() {
+            return Vec3::ZERO;
+        }
+        let radiance = light_hit.material.emitted(&light_hit);
+        let bsdf_pdf = hit_info.material.pdf(ray, hit_info, &direction);
+        bsdf * radiance * (cosine / light_pdf * power_heuristic(light_pdf, bsdf_pdf))
+    }
+}
+
+/// How far a render has got, reported after every finished tile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Counts from 1.
+    pub pass: u32,
+    pub tiles_done: usize,
+    pub tile_count: usize,
+}
+
+impl Progress {
+    pub fn pass_done(&self) -> bool {
+        self.tiles_done == self.tile_count
+    }
+}
+
+/// Renders progressive passes into `accumulator` until every pixel has
+/// `settings.samples_per_pixel` samples. Each pass adds up to
+/// `settings.pass_samples` samples per pixel, so a render resumed from a
+/// checkpoint picks up where it stopped. `on_progress` sees the accumulated
+/// samples whenever a tile is done. Until its pass ends, the pixels around a
+/// finished tile are still missing what the filter spreads onto them from it,
+/// so only the accumulator at the end of a pass is complete. The threads can't
+/// add tiles while `on_progress` runs, so anything slow belongs elsewhere.
+pub fn render(
+    accumulator: &mut Accumulator,
+    scene: &impl Hittable,
+    lights: &HittableList,
+    camera: &Camera,
+    background_color: &Vec3,
+    settings: &RenderSettings,
+    mut on_progress: impl FnMut(&Accumulator, Progress),
+) {
+    let tracer = Tracer {
+        world: scene,
+        lights,
+        background_color: *background_color,
+    };
+    let tiles = tiles::tiles(
+        accumulator.width,
+        accumulator.height,
+        settings.tile_size,
+        settings.tile_order,
+    );
+
+    // An empty image never gets any samples
+    if accumulator.counts.is_empty() {
+        return;
+    }
+
+    let mut pass = 0;
+    while accumulator.min_count() < settings.samples_per_pixel {
+        pass += 1;
+        render_pass(
+            accumulator,
+            &tiles,
+            &tracer,
+            camera,
+            settings,
+            |accumulator, tiles_done| {
+                let progress = Progress {
+                    pass,
+                    tiles_done,
+                    tile_count: tiles.len(),
+                };
+                on_progress(accumulator, progress)
+            },
+        );
+    }
+}
+
+/// One pass over the image. The threads take tiles from a shared queue and add
+/// the samples of each to its pixels in `accumulator` as soon as it's done.
+/// What the filter spreads over the neighbouring tiles is added at the end of
+/// the pass, in tile order, so the sums don't depend on which thread finished
+/// first.
+fn render_pass<H: Hittable>(
+    accumulator: &mut Accumulator,
+    tiles: &[Tile],
+    tracer: &Tracer<H>,
+    camera: &Camera,
+    settings: &RenderSettings,
+    mut tile_done: impl FnMut(&Accumulator, usize),
+) {
+    let (width, height) = (accumulator.width, accumulator.height);
+    let target = settings.samples_per_pixel;
+    let max_depth = settings.max_depth;
+    let pass_samples = settings.pass_samples.max(1);
+    let seed = settings.seed;
+    // The threads decide how many samples a pixel still needs from the counts
+    // at the start of the pass
+    let counts = &accumulator.counts.clone();
+    let next_tile = AtomicUsize::new(0);
+    let shared = Mutex::new(accumulator);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..settings.thread_count.max(1) {
+            let sender = sender.clone();
+            let next_tile = &next_tile;
+            let shared = &shared;
+            scope.spawn(move || {
+                let mut sampler = settings.sampler.create(target, seed);
+                loop {
+                    let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                    let Some(tile) = tiles.get(index) else {
+                        break;
+                    };
+                    let mut splats = TileSplats::new(tile, settings.filter, width, height);
+                    for y in tile.y0..tile.y1 {
+                        for x in tile.x0..tile.x1 {
+                            let count = counts[(y * width + x) as usize];
+                            let n = target.saturating_sub(count).min(pass_samples);
+                            for sample in count..count + n {
+                                utils::seed_sample_rng(seed, x, y, sample);
+                                sampler.start_pixel_sample(x, y, sample);
+                                let camera_sample = CameraSample::new(sampler.as_mut());
+                                let color = match camera.get_ray(x, y, &camera_sample) {
+                                    Some(ray) => {
+                                        tracer.ray_color(&ray, max_depth, None, sampler.as_mut())
+                                            * camera.exposure()
+                                    }
+                                    None => Vec3::ZERO,
+                                };
+                                splats.add_sample(x, y, camera_sample.film_offset, color);
+                            }
+                        }
+                    }
+                    // No other tile writes to this one's pixels, so the order
+                    // the threads take the lock in doesn't change the sums
+                    let overlap = shared.lock().unwrap().merge_tile(&splats);
+                    // Only fails if the receiving thread panicked
+                    let _ = sender.send((index, overlap));
+                }
+            });
+        }
+        drop(sender);
+
+        let mut overlaps: Vec<Option<Overlap>> = tiles.iter().map(|_| None).collect();
+        let mut tiles_done = 0;
+        for (index, overlap) in receiver {
+            overlaps[index] = Some(overlap);
+            tiles_done += 1;
+            if tiles_done < tiles.len() {
+                tile_done(&shared.lock().unwrap(), tiles_done);
+            }
+        }
+        let mut accumulator = shared.lock().unwrap();
+        for overlap in overlaps.iter().flatten() {
+            accumulator.merge_overlap(overlap);
+        }
+        tile_done(&accumulator, tiles_done);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{camera::Projection, filter::FilterKind};
+
+    const SKY: Vec3 = Vec3::new(0.25, 0.5, 1.0);
+
+    /// A 4x3 render of an empty scene in two tiles, which leaves every sample
+    /// the colour of the sky.
+    fn render_sky(
+        accumulator: &mut Accumulator,
+        settings: &RenderSettings,
+        on_progress: impl FnMut(&Accumulator, Progress),
+    ) {
+        let camera = Camera::new(
+            4,
+            3,
+            Vec3::ZERO,
+            Projection::Perspective { vfov_deg: 90.0 },
+            Vec3::FORWARD,
+            Vec3::UP,
+            0.0,
+            1.0,
+        );
+        let world = HittableList::new();
+        render(
+            accumulator,
+            &world,
+            &world,
+            &camera,
+            &SKY,
+            settings,
+            on_progress,
+        );
+    }
+
+    fn sky_settings() -> RenderSettings {
+        RenderSettings {
+            width: 4,
+            height: 3,
+            samples_per_pixel: 5,
+            thread_count: 2,
+            pass_samples: 2,
+            tile_size: 3,
+            ..RenderSettings::default()
+        }
+    }
+
+    /// Renders a lit sphere, with enough bounces for the samples of a pixel to
+    /// differ from each other.
+    fn render_sphere(settings: &RenderSettings) -> Accumulator {
+        use crate::{
+            hittables::{Quad, Sphere},
+            materials::{DiffuseLight, Lambertian},
+            textures::SolidColorTexture,
+        };
+        use std::sync::Arc;
+
+        let camera = Camera::new(
+            12,
+            8,
+            Vec3::ZERO,
+            Projection::Perspective { vfov_deg: 60.0 },
+            Vec3::FORWARD,
+            Vec3::UP,
+            1.0,
+            3.0,
+        );
+        let albedo = Arc::new(SolidColorTexture {
+            color: Vec3::new(0.8, 0.5, 0.2),
+        });
+        let light: Arc<dyn Hittable> = Arc::new(Quad::new(
+            Vec3::new(-1.0, 2.0, -4.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            Arc::new(DiffuseLight {
+                color: Vec3::new(4.0, 4.0, 4.0),
+            }),
+        ));
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Vec3::new(0.0, 0.0, -3.0),
+            1.0,
+            Arc::new(Lambertian { albedo }),
+        )));
+        world.add(light.clone());
+        let mut lights = HittableList::new();
+        lights.add(light);
+
+        let mut accumulator = Accumulator::new(12, 8);
+        render(
+            &mut accumulator,
+            &world,
+            &lights,
+            &camera,
+            &Vec3::new(0.1, 0.1, 0.1),
+            settings,
+            |_, _| (),
+        );
+        accumulator
+    }
+
+    fn sphere_settings(thread_count: u32, tile_size: u32, seed: u64) -> RenderSettings {
+        RenderSettings {
+            width: 12,
+            height: 8,
+            samples_per_pixel: 6,
+            thread_count,
+            pass_samples: 4,
+            tile_size,
+            seed,
+            ..RenderSettings::default()
+        }
+    }
+
+    #[test]
+    fn passes_accumulate_up_to_the_target() {
+        let mut accumulator = Accumulator::new(4, 3);
+        let mut passes = Vec::new();
+        render_sky(&mut accumulator, &sky_settings(), |a, progress| {
+            if progress.pass_done() {
+                passes.push((progress.pass, a.min_count()))
+            }
+        });
+        assert_eq!(passes, [(1, 2), (2, 4), (3, 5)]);
+        assert!(accumulator.counts.iter().all(|&count| count == 5));
+
+        let mut film = crate::film::Film::new(4, 3);
+        accumulator.resolve(&mut film);
+        assert!(film.pixels.iter().all(|&pixel| pixel == SKY));
+    }
+
+    #[test]
+    fn empty_images_render_nothing() {
+        let mut accumulator = Accumulator::new(0, 3);
+        let mut reports = 0;
+        render_sky(&mut accumulator, &sky_settings(), |_, _| reports += 1);
+        assert_eq!(reports, 0);
+    }
+
+    #[test]
+    fn resumed_renders_only_take_the_missing_samples() {
+        let mut accumulator = Accumulator::new(4, 3);
+        accumulator.sums.fill(SKY * 5.0);
+        accumulator.weights.fill(5.0);
+        accumulator.counts.fill(5);
+        accumulator.sums[0] = SKY * 8.0;
+        accumulator.weights[0] = 8.0;
+        accumulator.counts[0] = 8;
+
+        let settings = RenderSettings {
+            samples_per_pixel: 9,
+            ..sky_settings()
+        };
+        let mut passes = Vec::new();
+        render_sky(&mut accumulator, &settings, |a, progress| {
+            if progress.pass_done() {
+                passes.push((progress.pass, a.min_count()))
+            }
+        });
+        assert_eq!(passes, [(1, 7), (2, 9)]);
+        assert!(accumulator.counts.iter().all(|&count| count == 9));
+        assert_eq!(accumulator.weights[0], 9.0);
+    }
+
+    #[test]
+    fn progress_is_reported_for_every_tile() {
+        let mut accumulator = Accumulator::new(4, 3);
+        let mut reports = Vec::new();
+        render_sky(&mut accumulator, &sky_settings(), |a, progress| {
+            // Tiles are added as they finish, before the rest of the pass
+            let pixels_done = a.counts.iter().filter(|&&count| count == 2).count();
+            reports.push((progress, pixels_done));
+        });
+
+        let tiles: Vec<_> = reports
+            .iter()
+            .map(|(progress, _)| (progress.pass, progress.tiles_done, progress.tile_count))
+            .collect();
+        assert_eq!(
+            tiles,
+            [
+                (1, 1, 2),
+                (1, 2, 2),
+                (2, 1, 2),
+                (2, 2, 2),
+                (3, 1, 2),
+                (3, 2, 2)
+            ]
+        );
+        // The tiles are 3x3 and 1x3 pixels, and either may finish first. The
+        // other one can be done too by the time the first is reported
+        assert!([3, 9, 12].contains(&reports[0].1), "{:?}", reports[0]);
+        assert_eq!(reports[1].1, 12);
+    }
+
+    #[test]
+    fn wide_filters_normalise_across_tile_edges() {
+        // The weights still normalise a constant image to itself where the
+        // filter reaches over into the neighbouring tile
+        for kind in [
+            FilterKind::Gaussian,
+            FilterKind::Mitchell,
+            FilterKind::Lanczos,
+        ] {
+            let settings = RenderSettings {
+                filter: Filter::new(kind),
+                ..sky_settings()
+            };
+            let mut accumulator = Accumulator::new(4, 3);
+            render_sky(&mut accumulator, &settings, |_, _| ());
+            assert!(accumulator.counts.iter().all(|&count| count == 5));
+            let mut film = crate::film::Film::new(4, 3);
+            accumulator.resolve(&mut film);
+            for pixel in &film.pixels {
+                assert!((*pixel - SKY).length() < 1e-4, "{:?}", kind);
+            }
+        }
+    }
+
+    #[test]
+    fn light_samples_only_see_unblocked_lights() {
+        use crate::{
+            hittables::Quad,
+            materials::{DiffuseLight, Lambertian},
+            textures::SolidColorTexture,
+        };
+        use std::sync::Arc;
+
+        let quad = |y: f32, material: Arc<dyn crate::materials::Material>| -> Arc<dyn Hittable> {
+            Arc::new(Quad::new(
+                Vec3::new(-1.0, y, -1.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 2.0),
+                material,
+            ))
+        };
+        let albedo = Arc::new(SolidColorTexture { color: Vec3::ONE });
+        let floor = quad(0.0, Arc::new(Lambertian { albedo }));
+        let light = quad(2.0, Arc::new(DiffuseLight { color: Vec3::ONE }));
+        // Glows, but isn't one of the lights that are sampled
+        let glowing_blocker = quad(1.0, Arc::new(DiffuseLight { color: Vec3::ONE }));
+        let mut lights = HittableList::new();
+        lights.add(light.clone());
+
+        let direct_light = |objects: &[Arc<dyn Hittable>]| {
+            let mut world = HittableList::new();
+            for object in objects {
+                world.add(object.clone());
+            }
+            let tracer = Tracer {
+                world: &world,
+                lights: &lights,
+                // Bright enough to show if it stood in for a light
+                background_color: Vec3::ONE * 10.0,
+            };
+            let ray = Ray::new(Vec3::new(0.0, 0.5, 0.0), Vec3::DOWN);
+            let hit_info = floor.hit(&ray, &Interval::UNIVERSE).unwrap();
+            tracer.sample_light(&ray, &hit_info, [0.5, 0.5])
+        };
+
+        assert!(direct_light(&[floor.clone(), light.clone()]).x > 0.0);
+        assert_eq!(
+            direct_light(&[floor.clone(), light, glowing_blocker]),
+            Vec3::ZERO
+        );
+    }
+
+    #[test]
+    fn renders_are_reproducible_for_a_seed() {
+        let reference = render_sphere(&sphere_settings(1, 32, 7));
+        assert_eq!(render_sphere(&sphere_settings(1, 32, 7)), reference);
+        assert_ne!(render_sphere(&sphere_settings(1, 32, 8)), reference);
+    }
+
+    #[test]
+    fn renders_dont_depend_on_the_thread_count() {
+        assert_eq!(
+            render_sphere(&sphere_settings(3, 5, 7)),
+            render_sphere(&sphere_settings(1, 32, 7))
+        );
+
+        // With a wide filter the tiles overlap, and the overlaps are added in
+        // the same order whichever thread finishes first
+        let wide = |thread_count| RenderSettings {
+            filter: Filter::new(FilterKind::Gaussian),
+            ..sphere_settings(thread_count, 5, 7)
+        };
+        assert_eq!(render_sphere(&wide(3)), render_sphere(&wide(1)));
+    }
+}
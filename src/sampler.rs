@@ -0,0 +1,608 @@
+//! Generators of the random numbers a pixel sample consumes.
+//!
+//! A sampler hands out the dimensions of a sample one after the other: the
+//! camera takes the film position, lens position and time, then every bounce
+//! takes the light sample and the BSDF sample. Samplers other than
+//! [`Independent`] correlate the numbers of the samples in a pixel so they
+//! cover each dimension more evenly than independent random numbers, which
+//! makes the image converge faster.
+//!
+//! Free flight distances in participating media
+//! ([`ConstantMedium`](crate::hittables::ConstantMedium)) are the exception:
+//! they're drawn during the intersection search, where a ray can pass through
+//! any number of media in an order that depends on the BVH, so they can't take
+//! fixed dimensions and come from the per-sample [`utils::rng`] instead. Renders
+//! of scenes with media still reproduce for a seed, but their noise in the
+//! media doesn't improve with the sampler.
+
+use std::sync::OnceLock;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::utils::{self, splitmix64};
+
+pub trait Sampler {
+    /// Starts sample `index` of pixel `(x, y)`, from the first dimension.
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32);
+
+    fn get_1d(&mut self) -> f32;
+
+    fn get_2d(&mut self) -> [f32; 2];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers.
+    Independent,
+    /// One jittered sample per stratum of each dimension, with the strata
+    /// shuffled between dimensions.
+    Stratified,
+    /// The Halton sequence with Owen scrambled digits.
+    Halton,
+    /// Owen scrambled Sobol (0,2)-sequence padded across dimensions (Burley
+    /// 2020). Best with a power of two samples per pixel.
+    #[default]
+    Sobol,
+    /// A Sobol sequence shared by all pixels and shifted by a blue noise mask,
+    /// which spreads the error of low sample counts as high frequency noise
+    /// (Georgiev and Fajardo 2016).
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub const NAMES: [&'static str; 5] =
+        ["independent", "stratified", "halton", "sobol", "blue-noise"];
+
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "independent" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            "blue-noise" => Some(SamplerKind::BlueNoise),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+            SamplerKind::BlueNoise => "blue-noise",
+        }
+    }
+
+    /// A sampler for renders with `samples_per_pixel` samples. Each render
+    /// thread needs its own.
+    pub fn create(&self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(Independent),
+            SamplerKind::Stratified => Box::new(Stratified {
+                samples_per_pixel: samples_per_pixel.max(1),
+                seed,
+                pixel_seed: 0,
+                index: 0,
+                dimension: 0,
+            }),
+            SamplerKind::Halton => Box::new(Halton {
+                samples_per_pixel: samples_per_pixel.max(1),
+                seed,
+                pixel_seed: 0,
+                index: 0,
+                dimension: 0,
+            }),
+            SamplerKind::Sobol => Box::new(Sobol {
+                seed,
+                pixel_seed: 0,
+                index: 0,
+                dimension: 0,
+                dither: None,
+            }),
+            SamplerKind::BlueNoise => Box::new(Sobol {
+                seed,
+                pixel_seed: splitmix64(seed),
+                index: 0,
+                dimension: 0,
+                dither: Some((0, 0)),
+            }),
+        }
+    }
+}
+
+/// Hash of a pixel for seeding the numbers of its samples.
+fn pixel_seed(seed: u64, x: u32, y: u32) -> u64 {
+    splitmix64(splitmix64(seed) ^ ((y as u64) << 32 | x as u64))
+}
+
+/// Maps 32 random bits to `[0, 1)`.
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Element `i` of a random permutation of `0..n` chosen by `seed`, without
+/// storing the permutation (Kensler 2013).
+fn permutation_element(mut i: u32, n: u32, seed: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            return i.wrapping_add(seed) % n;
+        }
+    }
+}
+
+pub struct Independent;
+
+impl Sampler for Independent {
+    fn start_pixel_sample(&mut self, _: u32, _: u32, _: u32) {}
+
+    fn get_1d(&mut self) -> f32 {
+        utils::rng().gen()
+    }
+
+    fn get_2d(&mut self) -> [f32; 2] {
+        [self.get_1d(), self.get_1d()]
+    }
+}
+
+/// Samples past `samples_per_pixel`, as a resumed render takes, start another
+/// round of strata.
+pub struct Stratified {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl Stratified {
+    /// Stratum of this sample among `count` strata of the next dimension.
+    fn stratum(&mut self, count: u32) -> u32 {
+        let round = (self.index / self.samples_per_pixel) as u64;
+        let hash = splitmix64(self.pixel_seed ^ splitmix64(self.dimension << 32 | round));
+        self.dimension += 1;
+        permutation_element(
+            self.index % self.samples_per_pixel % count,
+            count,
+            hash as u32,
+        )
+    }
+}
+
+impl Sampler for Stratified {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_seed = pixel_seed(self.seed, x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let count = self.samples_per_pixel;
+        let stratum = self.stratum(count);
+        ((stratum as f32 + utils::rng().gen::<f32>()) / count as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn get_2d(&mut self) -> [f32; 2] {
+        let columns = (self.samples_per_pixel as f32).sqrt().ceil() as u32;
+        let rows = self.samples_per_pixel.div_ceil(columns);
+        let stratum = self.stratum(columns * rows);
+        let mut rng = utils::rng();
+        [
+            ((stratum % columns) as f32 + rng.gen::<f32>()) / columns as f32,
+            ((stratum / columns) as f32 + rng.gen::<f32>()) / rows as f32,
+        ]
+        .map(|u| u.min(ONE_MINUS_EPSILON))
+    }
+}
+
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+/// Dimensions past the number of primes get independent random numbers.
+const PRIMES: [u32; 256] = first_primes();
+
+const fn first_primes<const N: usize>() -> [u32; N] {
+    let mut primes = [0; N];
+    let mut count = 0;
+    let mut n = 2;
+    while count < N {
+        let mut i = 0;
+        while i < count && n % primes[i] != 0 {
+            i += 1;
+        }
+        if i == count {
+            primes[count] = n;
+            count += 1;
+        }
+        n += 1;
+    }
+    primes
+}
+
+/// Each pixel gets its own scrambling of the sequence.
+pub struct Halton {
+    samples_per_pixel: u32,
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: usize,
+}
+
+/// Radical inverse of `a` in `base`, with every digit permuted by a hash of
+/// the digits before it. The digits are scrambled down to a resolution of
+/// `1 / strata` (and at least as far as `a` has digits); the uniformly random
+/// digits scrambling would give below that come from a single hash.
+fn owen_scrambled_radical_inverse(base: u32, mut a: u64, strata: u32, hash: u64) -> f32 {
+    let base = base as u64;
+    let inverse_base = 1.0 / base as f64;
+    let mut inverse_base_m = 1.0;
+    let mut reversed_digits: u64 = 0;
+    while a != 0 || inverse_base_m * strata as f64 > 1.0 {
+        let digit_hash = splitmix64(hash ^ reversed_digits);
+        let digit = permutation_element((a % base) as u32, base as u32, digit_hash as u32);
+        reversed_digits = reversed_digits * base + digit as u64;
+        inverse_base_m *= inverse_base;
+        a /= base;
+    }
+    let tail = to_unit(splitmix64(!hash ^ reversed_digits) as u32) as f64;
+    (((reversed_digits as f64 + tail) * inverse_base_m) as f32).min(ONE_MINUS_EPSILON)
+}
+
+impl Sampler for Halton {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_seed = pixel_seed(self.seed, x, y);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        match PRIMES.get(dimension) {
+            Some(&base) => {
+                let hash = splitmix64(self.pixel_seed ^ dimension as u64);
+                owen_scrambled_radical_inverse(
+                    base,
+                    self.index as u64,
+                    self.samples_per_pixel,
+                    hash,
+                )
+            }
+            None => utils::rng().gen(),
+        }
+    }
+
+    fn get_2d(&mut self) -> [f32; 2] {
+        [self.get_1d(), self.get_1d()]
+    }
+}
+
+/// Owen scrambled Sobol points, or with `dither` the blue noise variant.
+pub struct Sobol {
+    seed: u64,
+    /// Seeds the scrambling, fixed for all pixels when dithering.
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+    /// Position of the pixel in the blue noise mask.
+    dither: Option<(u32, u32)>,
+}
+
+/// First dimension of the Sobol sequence, the van der Corput sequence.
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+/// Second dimension of the Sobol sequence. It's linear in the bits of the
+/// index, so it combines the contributions of the index's four bytes.
+fn sobol_1(index: u32) -> u32 {
+    const TABLES: [[u32; 256]; 4] = sobol_1_tables();
+    TABLES
+        .iter()
+        .zip(index.to_le_bytes())
+        .fold(0, |result, (table, byte)| result ^ table[byte as usize])
+}
+
+/// The second Sobol dimension of every value of each byte of the index.
+const fn sobol_1_tables() -> [[u32; 256]; 4] {
+    // Direction numbers, one per bit of the index
+    let mut directions = [0u32; 32];
+    let mut v = 1 << 31;
+    let mut bit = 0;
+    while bit < 32 {
+        directions[bit] = v;
+        v ^= v >> 1;
+        bit += 1;
+    }
+
+    let mut tables = [[0; 256]; 4];
+    let mut byte = 0;
+    while byte < 4 {
+        let mut value = 0;
+        while value < 256 {
+            let mut result = 0;
+            let mut bit = 0;
+            while bit < 8 {
+                if value & (1 << bit) != 0 {
+                    result ^= directions[byte * 8 + bit];
+                }
+                bit += 1;
+            }
+            tables[byte][value] = result;
+            value += 1;
+        }
+        byte += 1;
+    }
+    tables
+}
+
+/// Owen scrambling of the bits of `x`, most significant first (Laine and
+/// Karras 2011, with Burley's constants).
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x ^= x.wrapping_mul(0x3d20adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x05526c56);
+    x ^= x.wrapping_mul(0x53a22864);
+    x.reverse_bits()
+}
+
+impl Sobol {
+    /// Shuffled index and the scrambling seed for the next dimensions, which
+    /// decorrelates them from the earlier ones.
+    fn next_dimensions(&mut self, count: u64) -> (u32, u64) {
+        let hash = splitmix64(self.pixel_seed ^ self.dimension);
+        self.dimension += count;
+        (nested_uniform_scramble(self.index, hash as u32), hash)
+    }
+
+    /// Toroidal shift of the dimension `dimension` for the pixel.
+    fn shift(&self, dimension: u64) -> u32 {
+        let Some((x, y)) = self.dither else {
+            return 0;
+        };
+        let offset = splitmix64(dimension ^ self.seed);
+        let mask = blue_noise_mask();
+        let x = (x as usize + offset as usize) % MASK_SIZE;
+        let y = (y as usize + (offset >> 32) as usize) % MASK_SIZE;
+        mask[y * MASK_SIZE + x]
+    }
+}
+
+impl Sampler for Sobol {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        match &mut self.dither {
+            Some(pixel) => *pixel = (x, y),
+            None => self.pixel_seed = pixel_seed(self.seed, x, y),
+        }
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let shift = self.shift(self.dimension);
+        let (index, hash) = self.next_dimensions(1);
+        let x = nested_uniform_scramble(sobol_0(index), splitmix64(hash) as u32);
+        to_unit(x.wrapping_add(shift))
+    }
+
+    fn get_2d(&mut self) -> [f32; 2] {
+        let shifts = [self.shift(self.dimension), self.shift(self.dimension + 1)];
+        let (index, hash) = self.next_dimensions(2);
+        let seeds = splitmix64(hash);
+        let x = nested_uniform_scramble(sobol_0(index), seeds as u32);
+        let y = nested_uniform_scramble(sobol_1(index), (seeds >> 32) as u32);
+        [
+            to_unit(x.wrapping_add(shifts[0])),
+            to_unit(y.wrapping_add(shifts[1])),
+        ]
+    }
+}
+
+const MASK_SIZE: usize = 64;
+
+/// Tileable blue noise: every value of `0..2^32` in even steps, arranged so
+/// that nearby pixels have dissimilar values.
+fn blue_noise_mask() -> &'static [u32] {
+    static MASK: OnceLock<Vec<u32>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let ranks = void_and_cluster(MASK_SIZE, 1.5, 1);
+        let step = (1u64 << 32) / ranks.len() as u64;
+        ranks
+            .iter()
+            .map(|&rank| (rank as u64 * step) as u32)
+            .collect()
+    })
+}
+
+/// Ranks of the pixels of a `size` by `size` blue noise mask, generated with
+/// the void and cluster method (Ulichney 1993) and a Gaussian of `sigma`
+/// pixels measuring how crowded the pattern is around each pixel.
+fn void_and_cluster(size: usize, sigma: f32, seed: u64) -> Vec<usize> {
+    let n = size * size;
+    let wrap = |d: usize| d.min(size - d) as f32;
+    let kernel: Vec<f32> = (0..n)
+        .map(|i| {
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let splat = |energy: &mut [f32], p: usize, sign: f32| {
+        let (px, py) = (p % size, p / size);
+        for (q, e) in energy.iter_mut().enumerate() {
+            let dx = (q % size + size - px) % size;
+            let dy = (q / size + size - py) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    // Tightest cluster among the set pixels, or largest void among the unset ones
+    let extreme = |pattern: &[bool], energy: &[f32], set: bool| {
+        let candidates = (0..n).filter(|&p| pattern[p] == set);
+        if set {
+            candidates.max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+        } else {
+            candidates.min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+        }
+        .unwrap()
+    };
+
+    // Random initial pattern, relaxed by moving points from clusters to voids
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let ones = n / 10;
+    while pattern.iter().filter(|&&set| set).count() < ones {
+        let p = rng.gen_range(0..n);
+        if !pattern[p] {
+            pattern[p] = true;
+            splat(&mut energy, p, 1.0);
+        }
+    }
+    loop {
+        let cluster = extreme(&pattern, &energy, true);
+        pattern[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = extreme(&pattern, &energy, false);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; n];
+    // Points removed from the densest clusters get the ranks below the
+    // initial pattern's size
+    let (mut removing, mut removing_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..ones).rev() {
+        let cluster = extreme(&removing, &removing_energy, true);
+        removing[cluster] = false;
+        splat(&mut removing_energy, cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+    // and pixels filling the largest voids the ranks above
+    for rank in ones..n {
+        let void = extreme(&pattern, &energy, false);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        ranks[void] = rank;
+    }
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Largest gap between sorted values and their ideal, evenly spaced
+    /// positions.
+    fn discrepancy(mut values: Vec<f32>) -> f32 {
+        values.sort_by(f32::total_cmp);
+        let n = values.len() as f32;
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| (v - (i as f32 + 0.5) / n).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn samples_are_in_range_and_well_distributed() {
+        let samples = 64;
+        for name in SamplerKind::NAMES {
+            let mut sampler = SamplerKind::from_name(name).unwrap().create(samples, 3);
+            // Dimensions of the camera and a few bounces
+            let mut dimensions = vec![Vec::new(); 20];
+            for index in 0..samples {
+                sampler.start_pixel_sample(5, 9, index);
+                for pair in dimensions.chunks_mut(2) {
+                    let [u, v] = sampler.get_2d();
+                    pair[0].push(u);
+                    pair[1].push(v);
+                }
+            }
+            for values in dimensions {
+                assert!(values.iter().all(|v| (0.0..1.0).contains(v)), "{}", name);
+                // Independent numbers stray about 1 / sqrt(n) from even
+                // spacing, a (0,2)-sequence less than 1 / n
+                let limit = match name {
+                    "independent" => 0.3,
+                    "stratified" => 0.1,
+                    "halton" => 0.06,
+                    _ => 0.03,
+                };
+                assert!(discrepancy(values) < limit, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn sobol_points_are_stratified() {
+        // Each of the 16 samples falls in its own cell of every 2D elementary
+        // interval partition, 16x1, 8x2, 4x4, 2x8 and 1x16
+        let mut sampler = SamplerKind::Sobol.create(16, 11);
+        sampler.start_pixel_sample(0, 0, 0);
+        let points: Vec<[f32; 2]> = (0..16)
+            .map(|index| {
+                sampler.start_pixel_sample(2, 1, index);
+                sampler.get_1d();
+                sampler.get_2d()
+            })
+            .collect();
+        for columns in [1, 2, 4, 8, 16] {
+            let rows = 16 / columns;
+            let mut cells: Vec<usize> = points
+                .iter()
+                .map(|[u, v]| (v * rows as f32) as usize * columns + (u * columns as f32) as usize)
+                .collect();
+            cells.sort();
+            cells.dedup();
+            assert_eq!(cells.len(), 16);
+        }
+    }
+
+    #[test]
+    fn permutations_and_mask() {
+        for n in [1, 2, 7, 64] {
+            let mut elements: Vec<u32> = (0..n).map(|i| permutation_element(i, n, 12345)).collect();
+            elements.sort();
+            assert_eq!(elements, (0..n).collect::<Vec<_>>());
+        }
+
+        let ranks = void_and_cluster(16, 1.5, 4);
+        let mut sorted = ranks.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..256).collect::<Vec<_>>());
+        // Blue noise has little low frequency content: neighbouring pixels
+        // differ by more than white noise would
+        let neighbour_difference: f32 = (0..256)
+            .map(|i| (ranks[i] as f32 - ranks[(i + 1) % 16 + i / 16 * 16] as f32).abs())
+            .sum::<f32>()
+            / 256.0;
+        assert!(neighbour_difference > 256.0 / 3.0 * 1.1);
+    }
+}
@@ -0,0 +1,562 @@
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    camera::{Camera, Projection},
+    hittables::{self, Animated, ConstantMedium, HittableList, Quad, Sphere, Transformed},
+    materials,
+    math::{
+        mat4::{AnimatedTransform, Mat4},
+        vec3::Vec3,
+    },
+    textures::{CheckerTexture, SolidColorTexture},
+};
+
+pub struct Scene {
+    pub hittables: HittableList,
+    /// Emissive objects that are also sampled directly. They must be part of
+    /// `hittables` as well.
+    pub lights: HittableList,
+    pub camera: Camera,
+    pub background_color: Vec3,
+}
+
+/// Builds a scene for the given output resolution. The seed drives any random
+/// placement in the scene.
+pub type SceneFn = fn(u32, u32, u64) -> Scene;
+
+pub const BUILTIN_SCENES: [(&str, SceneFn); 8] = [
+    ("spheres", create_scene),
+    ("final", create_final_scene),
+    ("quads", create_quads_scene),
+    ("lights", create_lights_scene),
+    ("cornell", create_cornell_scene),
+    ("cornell-boxes", create_cornell_boxes_scene),
+    ("cornell-smoke", create_cornell_smoke_scene),
+    ("motion", create_motion_scene),
+];
+
+pub fn builtin(name: &str) -> Option<SceneFn> {
+    BUILTIN_SCENES
+        .iter()
+        .find(|(scene_name, _)| *scene_name == name)
+        .map(|(_, create)| *create)
+}
+
+pub fn create_scene(width: u32, height: u32, _seed: u64) -> Scene {
+    let ground_mat = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(0.4, 0.59, 0.56),
+        }),
+    });
+    let blue_diffuse = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(0.1, 0.2, 0.8),
+        }),
+    });
+    let gold_mat = Arc::new(materials::Metal {
+        albedo: Vec3::new(0.944, 0.776, 0.373),
+        roughness: 0.4,
+    });
+    let glass_mat = Arc::new(materials::Dielectric { ior: 1.5 });
+    let glass_inner_mat = Arc::new(materials::Dielectric { ior: 1.0 / 1.5 });
+
+    let mut hittables = HittableList::new();
+
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 0.0, -1.2),
+        0.5,
+        blue_diffuse.clone(),
+    )));
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(1.0, 0.0, -1.0),
+        0.5,
+        gold_mat.clone(),
+    )));
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(-1.0, 0.0, -1.0),
+        0.5,
+        glass_mat.clone(),
+    )));
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(-1.0, 0.0, -1.0),
+        0.4,
+        glass_inner_mat.clone(),
+    )));
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -100.5, -1.0),
+        100.0,
+        ground_mat.clone(),
+    )));
+
+    let camera = Camera::new(
+        width,
+        height,
+        Vec3::new(-2.0, 2.0, 1.0),
+        Projection::Perspective { vfov_deg: 30.0 },
+        Vec3::new(0.0, 0.0, -1.0),
+        Vec3::UP,
+        10.0,
+        3.4,
+    );
+
+    let background_color = Vec3::new(0.5, 0.7, 1.0);
+
+    Scene {
+        hittables,
+        lights: HittableList::new(),
+        camera,
+        background_color,
+    }
+}
+
+pub fn create_final_scene(width: u32, height: u32, seed: u64) -> Scene {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut hittables = HittableList::new();
+    hittables.reserve(22 * 22 + 10);
+
+    // Ground
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(materials::Lambertian {
+            albedo: Arc::new(CheckerTexture {
+                even_texture: Arc::new(SolidColorTexture {
+                    color: Vec3::new(0.2, 0.3, 0.1),
+                }),
+                odd_texture: Arc::new(SolidColorTexture {
+                    color: Vec3::new(0.9, 0.9, 0.9),
+                }),
+            }),
+        }),
+    )));
+
+    // Big spheres
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 1.0, 0.0),
+        1.0,
+        Arc::new(materials::Dielectric { ior: 1.5 }),
+    )));
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(-4.0, 1.0, 0.0),
+        1.0,
+        Arc::new(materials::Lambertian {
+            albedo: Arc::new(SolidColorTexture {
+                color: (Vec3::new(0.4, 0.2, 0.1)),
+            }),
+        }),
+    )));
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(4.0, 1.0, 0.0),
+        1.0,
+        Arc::new(materials::Metal {
+            albedo: Vec3::new(0.7, 0.6, 0.5),
+            roughness: 0.1,
+        }),
+    )));
+
+    // Small spheres
+    for x in -11..11 {
+        for z in -11..11 {
+            let radius = 0.2;
+            let center = Vec3::new(
+                x as f32 + rng.gen_range::<f32, _>(0.1..0.9),
+                radius,
+                z as f32 + 0.9 * rng.gen_range::<f32, _>(0.1..0.9),
+            );
+
+            let material: Arc<dyn materials::Material> = match rng.gen::<f32>() {
+                x if x < 0.7 => Arc::new(materials::Lambertian {
+                    albedo: Arc::new(SolidColorTexture {
+                        color: Vec3::new(rng.gen(), rng.gen(), rng.gen()),
+                    }),
+                }),
+                x if x < 0.9 => Arc::new(materials::Metal {
+                    albedo: Vec3::new(rng.gen(), rng.gen(), rng.gen()),
+                    roughness: rng.gen(),
+                }),
+                _ => Arc::new(materials::Dielectric { ior: 1.5 }),
+            };
+
+            hittables.add(Arc::new(Sphere::new(center, radius, material)));
+        }
+    }
+
+    let camera = Camera::new(
+        width,
+        height,
+        Vec3::new(13.0, 2.0, 3.0),
+        Projection::Perspective { vfov_deg: 20.0 },
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::UP,
+        0.6,
+        10.0,
+    );
+
+    let background_color = Vec3::new(0.5, 0.7, 1.0);
+
+    Scene {
+        hittables,
+        lights: HittableList::new(),
+        camera,
+        background_color,
+    }
+}
+
+pub fn create_quads_scene(width: u32, height: u32, _seed: u64) -> Scene {
+    let left_red = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(1.0, 0.2, 0.2),
+        }),
+    });
+    let back_green = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(0.2, 1.0, 0.2),
+        }),
+    });
+    let right_blue = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(0.2, 0.2, 1.0),
+        }),
+    });
+    let upper_orange = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(1.0, 0.5, 0.0),
+        }),
+    });
+    let lower_teal = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(0.2, 0.8, 0.8),
+        }),
+    });
+
+    let mut hittables = HittableList::new();
+
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(-3.0, -2.0, 5.0),
+        Vec3::new(0.0, 0.0, -4.0),
+        Vec3::new(0.0, 4.0, 0.0),
+        left_red.clone(),
+    )));
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(-2.0, -2.0, 0.0),
+        Vec3::new(4.0, 0.0, 0.0),
+        Vec3::new(0.0, 4.0, 0.0),
+        back_green.clone(),
+    )));
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(3.0, -2.0, 1.0),
+        Vec3::new(0.0, 0.0, 4.0),
+        Vec3::new(0.0, 4.0, 0.0),
+        right_blue.clone(),
+    )));
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(-2.0, 3.0, 1.0),
+        Vec3::new(4.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 4.0),
+        upper_orange.clone(),
+    )));
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(-2.0, -3.0, 5.0),
+        Vec3::new(4.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -4.0),
+        lower_teal.clone(),
+    )));
+
+    let camera = Camera::new(
+        width,
+        height,
+        Vec3::BACKWARD * 9.0,
+        Projection::Perspective { vfov_deg: 80.0 },
+        Vec3::ZERO,
+        Vec3::UP,
+        0.0,
+        1.0,
+    );
+
+    let background_color = Vec3::new(0.5, 0.7, 1.0);
+
+    Scene {
+        hittables,
+        lights: HittableList::new(),
+        camera,
+        background_color,
+    }
+}
+
+pub fn create_lights_scene(width: u32, height: u32, _seed: u64) -> Scene {
+    let mut hittables = HittableList::new();
+
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, 0.5, 0.0),
+        0.5,
+        Arc::new(materials::Lambertian {
+            albedo: Arc::new(SolidColorTexture {
+                color: Vec3::new(0.2, 0.2, 0.9),
+            }),
+        }),
+    )));
+
+    //Floor
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(-500.0, 0.0, -500.0),
+        Vec3::RIGHT * 1000.0,
+        Vec3::BACKWARD * 1000.0,
+        Arc::new(materials::Lambertian {
+            albedo: Arc::new(SolidColorTexture {
+                color: Vec3::uniform(0.5),
+            }),
+        }),
+    )));
+
+    // Light
+    let mut lights = HittableList::new();
+    let light = Arc::new(Quad::new(
+        Vec3::new(1.0, 0.0, -0.8),
+        Vec3::UP * 1.0,
+        Vec3::BACKWARD * 1.6,
+        Arc::new(materials::DiffuseLight {
+            color: Vec3::new(1.0, 1.0, 1.0) * 4.0,
+        }),
+    ));
+    hittables.add(light.clone());
+    lights.add(light);
+
+    let camera = Camera::new(
+        width,
+        height,
+        Vec3::new(-0.6, 0.7, 2.0),
+        Projection::Perspective { vfov_deg: 50.0 },
+        Vec3::new(0.0, 0.5, 0.0),
+        Vec3::UP,
+        0.0,
+        1.0,
+    );
+
+    let background_color = Vec3::uniform(0.002);
+
+    Scene {
+        hittables,
+        lights,
+        camera,
+        background_color,
+    }
+}
+
+pub fn create_cornell_scene(width: u32, height: u32, _seed: u64) -> Scene {
+    let red_wall = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(0.65, 0.05, 0.05),
+        }),
+    });
+    let white_wall = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::uniform(0.73),
+        }),
+    });
+    let green_wall = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(0.12, 0.45, 0.15),
+        }),
+    });
+
+    let mut hittables = HittableList::new();
+
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(555.0, 0.0, 0.0),
+        Vec3::UP * 555.0,
+        Vec3::BACKWARD * 555.0,
+        green_wall.clone(),
+    )));
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::UP * 555.0,
+        Vec3::BACKWARD * 555.0,
+        red_wall.clone(),
+    )));
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::RIGHT * 555.0,
+        Vec3::BACKWARD * 555.0,
+        white_wall.clone(),
+    )));
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(555.0, 555.0, 555.0),
+        Vec3::LEFT * 555.0,
+        Vec3::FORWARD * 555.0,
+        white_wall.clone(),
+    )));
+    hittables.add(Arc::new(Quad::new(
+        Vec3::new(0.0, 0.0, 555.0),
+        Vec3::RIGHT * 555.0,
+        Vec3::UP * 555.0,
+        white_wall.clone(),
+    )));
+
+    // Light
+    let mut lights = HittableList::new();
+    let light = Arc::new(Quad::new(
+        Vec3::new(343.0, 554.0, 332.0),
+        Vec3::LEFT * 130.0,
+        Vec3::FORWARD * 105.0,
+        Arc::new(materials::DiffuseLight {
+            color: Vec3::new(1.0, 1.0, 1.0) * 15.0,
+        }),
+    ));
+    hittables.add(light.clone());
+    lights.add(light);
+
+    let camera = Camera::new(
+        width,
+        height,
+        Vec3::new(278.0, 278.0, -800.0),
+        Projection::Perspective { vfov_deg: 40.0 },
+        Vec3::new(278.0, 278.0, 0.0),
+        Vec3::UP,
+        0.0,
+        1.0,
+    );
+
+    let background_color = Vec3::ZERO;
+
+    Scene {
+        hittables,
+        lights,
+        camera,
+        background_color,
+    }
+}
+
+/// Sizes and placements of the two blocks in the Cornell box.
+fn cornell_blocks() -> [(Vec3, Mat4); 2] {
+    let place =
+        |angle: f32, offset: Vec3| Mat4::translation(offset) * Mat4::rotation(Vec3::UP, angle);
+    [
+        (
+            Vec3::new(165.0, 330.0, 165.0),
+            place(15.0, Vec3::new(265.0, 0.0, 295.0)),
+        ),
+        (
+            Vec3::new(165.0, 165.0, 165.0),
+            place(-18.0, Vec3::new(130.0, 0.0, 65.0)),
+        ),
+    ]
+}
+
+/// The Cornell box with its two rotated white blocks.
+pub fn create_cornell_boxes_scene(width: u32, height: u32, seed: u64) -> Scene {
+    let mut scene = create_cornell_scene(width, height, seed);
+    let white = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::uniform(0.73),
+        }),
+    });
+
+    for (size, transform) in cornell_blocks() {
+        let block = hittables::cuboid(&Vec3::ZERO, &size, white.clone());
+        scene
+            .hittables
+            .add(Arc::new(Transformed::new(Arc::new(block), transform)));
+    }
+    scene
+}
+
+/// The Cornell box with blocks of dark smoke and bright, forward scattering fog.
+pub fn create_cornell_smoke_scene(width: u32, height: u32, seed: u64) -> Scene {
+    let mut scene = create_cornell_scene(width, height, seed);
+    let smoke: Arc<dyn materials::Material> = Arc::new(materials::Isotropic {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::uniform(0.05),
+        }),
+    });
+    let fog: Arc<dyn materials::Material> = Arc::new(materials::HenyeyGreenstein {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::uniform(0.95),
+        }),
+        g: 0.5,
+    });
+
+    for ((size, transform), material) in cornell_blocks().into_iter().zip([smoke, fog]) {
+        let block = hittables::cuboid(&Vec3::ZERO, &size, material.clone());
+        let boundary = Arc::new(Transformed::new(Arc::new(block), transform));
+        scene
+            .hittables
+            .add(Arc::new(ConstantMedium::new(boundary, 0.01, material)));
+    }
+    scene
+}
+
+/// Moving and spinning objects over a checkered floor, blurred by the shutter.
+pub fn create_motion_scene(width: u32, height: u32, _seed: u64) -> Scene {
+    let ground = Arc::new(materials::Lambertian {
+        albedo: Arc::new(CheckerTexture {
+            even_texture: Arc::new(SolidColorTexture {
+                color: Vec3::new(0.2, 0.3, 0.1),
+            }),
+            odd_texture: Arc::new(SolidColorTexture {
+                color: Vec3::uniform(0.9),
+            }),
+        }),
+    });
+    let red = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(0.8, 0.1, 0.1),
+        }),
+    });
+    let steel = Arc::new(materials::Metal {
+        albedo: Vec3::new(0.7, 0.6, 0.5),
+        roughness: 0.1,
+    });
+    let blue = Arc::new(materials::Lambertian {
+        albedo: Arc::new(SolidColorTexture {
+            color: Vec3::new(0.1, 0.2, 0.8),
+        }),
+    });
+
+    let mut hittables = HittableList::new();
+    hittables.add(Arc::new(Sphere::new(
+        Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground,
+    )));
+
+    // A ball bouncing up and one rolling sideways
+    hittables.add(Arc::new(Sphere::moving(
+        Vec3::new(-2.2, 0.6, 0.0),
+        Vec3::new(-2.2, 1.4, 0.0),
+        0.6,
+        red,
+    )));
+    hittables.add(Arc::new(Sphere::moving(
+        Vec3::new(1.6, 0.6, 0.5),
+        Vec3::new(2.6, 0.6, 0.5),
+        0.6,
+        steel,
+    )));
+
+    // A box spinning a quarter turn around its vertical axis
+    let block = hittables::cuboid(&Vec3::new(-0.6, 0.0, -0.6), &Vec3::new(0.6, 1.2, 0.6), blue);
+    let spin = AnimatedTransform::new(Mat4::IDENTITY, Mat4::rotation(Vec3::UP, 90.0)).unwrap();
+    hittables.add(Arc::new(Animated::new(Arc::new(block), spin)));
+
+    let camera = Camera::new(
+        width,
+        height,
+        Vec3::new(0.0, 2.5, 7.0),
+        Projection::Perspective { vfov_deg: 40.0 },
+        Vec3::new(0.0, 0.7, 0.0),
+        Vec3::UP,
+        0.0,
+        1.0,
+    )
+    .with_shutter(0.0, 1.0);
+
+    Scene {
+        hittables,
+        lights: HittableList::new(),
+        camera,
+        background_color: Vec3::new(0.5, 0.7, 1.0),
+    }
+}
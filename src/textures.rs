@@ -1,6 +1,10 @@
 use std::sync::Arc;
 
-use crate::math::vec3::Vec3;
+use crate::{
+    film::Film,
+    math::vec3::Vec3,
+    noise::{Fractal, Noise, Worley},
+};
 
 pub trait Texture: Send + Sync {
     fn sample(&self, u: f32, v: f32, p: &Vec3) -> Vec3;
@@ -36,3 +40,285 @@ impl Texture for CheckerTexture {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+}
+
+impl TextureFilter {
+    pub const NAMES: [&'static str; 2] = ["nearest", "bilinear"];
+
+    pub fn from_name(name: &str) -> Option<TextureFilter> {
+        match name {
+            "nearest" => Some(TextureFilter::Nearest),
+            "bilinear" => Some(TextureFilter::Bilinear),
+            _ => None,
+        }
+    }
+}
+
+/// How texel coordinates outside the image are brought back inside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    /// Repeat, flipping every other copy.
+    Mirror,
+}
+
+impl WrapMode {
+    pub const NAMES: [&'static str; 3] = ["repeat", "clamp", "mirror"];
+
+    pub fn from_name(name: &str) -> Option<WrapMode> {
+        match name {
+            "repeat" => Some(WrapMode::Repeat),
+            "clamp" => Some(WrapMode::Clamp),
+            "mirror" => Some(WrapMode::Mirror),
+            _ => None,
+        }
+    }
+
+    fn apply(&self, i: i64, size: u32) -> u32 {
+        let size = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        i as u32
+    }
+}
+
+/// Texture from a decoded image, which can be shared by several textures.
+/// `(0, 0)` is the bottom left corner of the image and `(1, 1)` the top right.
+pub struct ImageTexture {
+    pub image: Arc<Film>,
+    pub filter: TextureFilter,
+    pub wrap: WrapMode,
+}
+
+impl ImageTexture {
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        self.image.pixel(
+            self.wrap.apply(x, self.image.width),
+            self.wrap.apply(y, self.image.height),
+        )
+    }
+}
+
+impl Texture for ImageTexture {
+    fn sample(&self, u: f32, v: f32, _: &Vec3) -> Vec3 {
+        // Images are stored top row first
+        let x = u * self.image.width as f32;
+        let y = (1.0 - v) * self.image.height as f32;
+
+        match self.filter {
+            TextureFilter::Nearest => self.texel(x.floor() as i64, y.floor() as i64),
+            TextureFilter::Bilinear => {
+                // Texel values sit at the centre of each texel
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let top = self.texel(x0, y0) * (1.0 - tx) + self.texel(x0 + 1, y0) * tx;
+                let bottom = self.texel(x0, y0 + 1) * (1.0 - tx) + self.texel(x0 + 1, y0 + 1) * tx;
+                top * (1.0 - ty) + bottom * ty
+            }
+        }
+    }
+}
+
+/// Maps a value in [0, 1] to a colour by interpolating between stops.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorRamp {
+    /// Positions in increasing order and their colours.
+    stops: Vec<(f32, Vec3)>,
+}
+
+impl ColorRamp {
+    pub fn new(mut stops: Vec<(f32, Vec3)>) -> ColorRamp {
+        assert!(!stops.is_empty(), "color ramp has no stops");
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        ColorRamp { stops }
+    }
+
+    /// Black to white.
+    pub fn greyscale() -> ColorRamp {
+        ColorRamp::new(vec![(0.0, Vec3::ZERO), (1.0, Vec3::ONE)])
+    }
+
+    pub fn sample(&self, t: f32) -> Vec3 {
+        let next = self.stops.partition_point(|stop| stop.0 <= t);
+        if next == 0 {
+            return self.stops[0].1;
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].1;
+        }
+        let (start, start_color) = self.stops[next - 1];
+        let (end, end_color) = self.stops[next];
+        let f = (t - start) / (end - start);
+        start_color * (1.0 - f) + end_color * f
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoisePattern {
+    /// Fractal noise, remapped to [0, 1].
+    Fbm,
+    Turbulence,
+    /// Bands along the x axis, bent by turbulence.
+    Marble {
+        distortion: f32,
+    },
+    /// Rings around the y axis, bent by fractal noise.
+    Wood {
+        distortion: f32,
+    },
+}
+
+/// Solid texture from gradient noise, evaluated at the hit point so it needs no UVs.
+pub struct NoiseTexture {
+    pub noise: Arc<dyn Noise>,
+    pub fractal: Fractal,
+    pub pattern: NoisePattern,
+    pub ramp: ColorRamp,
+}
+
+impl Texture for NoiseTexture {
+    fn sample(&self, _: f32, _: f32, p: &Vec3) -> Vec3 {
+        let noise = self.noise.as_ref();
+        let t = match self.pattern {
+            NoisePattern::Fbm => 0.5 + 0.5 * self.fractal.fbm(noise, p),
+            NoisePattern::Turbulence => self.fractal.turbulence(noise, p),
+            NoisePattern::Marble { distortion } => {
+                let phase =
+                    p.x * self.fractal.frequency + distortion * self.fractal.turbulence(noise, p);
+                0.5 + 0.5 * phase.sin()
+            }
+            NoisePattern::Wood { distortion } => {
+                let radius = (p.x * p.x + p.z * p.z).sqrt() * self.fractal.frequency;
+                (radius + distortion * self.fractal.fbm(noise, p)).rem_euclid(1.0)
+            }
+        };
+        self.ramp.sample(t)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorleyFeature {
+    /// Distance to the closest feature point, dark spots at each point.
+    F1,
+    /// Distance to the second closest feature point.
+    F2,
+    /// `F2 - F1`, zero along the cell borders.
+    Edges,
+}
+
+impl WorleyFeature {
+    pub const NAMES: [&'static str; 3] = ["f1", "f2", "edges"];
+
+    pub fn from_name(name: &str) -> Option<WorleyFeature> {
+        match name {
+            "f1" => Some(WorleyFeature::F1),
+            "f2" => Some(WorleyFeature::F2),
+            "edges" => Some(WorleyFeature::Edges),
+            _ => None,
+        }
+    }
+}
+
+/// Solid Voronoi cell texture.
+pub struct WorleyTexture {
+    pub worley: Worley,
+    pub fractal: Fractal,
+    pub feature: WorleyFeature,
+    pub ramp: ColorRamp,
+}
+
+impl Texture for WorleyTexture {
+    fn sample(&self, _: f32, _: f32, p: &Vec3) -> Vec3 {
+        let t = self.fractal.sum(p, |p| {
+            let (f1, f2) = self.worley.distances(p);
+            match self.feature {
+                WorleyFeature::F1 => f1,
+                WorleyFeature::F2 => f2,
+                WorleyFeature::Edges => f2 - f1,
+            }
+        });
+        self.ramp.sample(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+
+    /// 2x1 image, black on the left and white on the right.
+    fn texture(filter: TextureFilter, wrap: WrapMode) -> ImageTexture {
+        let mut image = Film::new(2, 1);
+        image.write_pixel(1, 0, Vec3::ONE);
+        ImageTexture {
+            image: Arc::new(image),
+            filter,
+            wrap,
+        }
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let sample = |wrap, u| {
+            texture(TextureFilter::Nearest, wrap)
+                .sample(u, 0.5, &Vec3::ZERO)
+                .x
+        };
+        assert_eq!(sample(WrapMode::Repeat, 0.25), 0.0);
+        assert_eq!(sample(WrapMode::Repeat, 1.25), 0.0);
+        assert_eq!(sample(WrapMode::Repeat, -0.25), 1.0);
+        assert_eq!(sample(WrapMode::Clamp, 1.25), 1.0);
+        assert_eq!(sample(WrapMode::Clamp, -3.0), 0.0);
+        assert_eq!(sample(WrapMode::Mirror, 1.25), 1.0);
+        assert_eq!(sample(WrapMode::Mirror, 1.75), 0.0);
+        assert_eq!(sample(WrapMode::Mirror, -0.25), 0.0);
+    }
+
+    #[test]
+    fn bilinear_interpolates_between_texel_centres() {
+        let clamped = texture(TextureFilter::Bilinear, WrapMode::Clamp);
+        assert_approx_eq!(clamped.sample(0.25, 0.5, &Vec3::ZERO).x, 0.0);
+        assert_approx_eq!(clamped.sample(0.5, 0.5, &Vec3::ZERO).x, 0.5);
+        assert_approx_eq!(clamped.sample(0.625, 0.5, &Vec3::ZERO).x, 0.75);
+        assert_approx_eq!(clamped.sample(1.0, 0.5, &Vec3::ZERO).x, 1.0);
+
+        // Repeating blends the right edge into the left one
+        let repeated = texture(TextureFilter::Bilinear, WrapMode::Repeat);
+        assert_approx_eq!(repeated.sample(1.0, 0.5, &Vec3::ZERO).x, 0.5);
+    }
+
+    #[test]
+    fn color_ramp_interpolates_and_clamps() {
+        let ramp = ColorRamp::new(vec![
+            (1.0, Vec3::new(0.0, 0.0, 1.0)),
+            (0.0, Vec3::new(1.0, 0.0, 0.0)),
+            (0.5, Vec3::new(0.0, 1.0, 0.0)),
+        ]);
+        assert_eq!(ramp.sample(-1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(ramp.sample(0.5), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(ramp.sample(2.0), Vec3::new(0.0, 0.0, 1.0));
+        let between = ramp.sample(0.75);
+        assert_approx_eq!(between.y, 0.5);
+        assert_approx_eq!(between.z, 0.5);
+    }
+}
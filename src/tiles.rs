@@ -0,0 +1,174 @@
+//! Division of the image into square tiles, the unit of work of the render
+//! threads.
+
+/// Pixels `x0..x1` by `y0..y1` of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+}
+
+/// The order in which tiles are handed to the threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileOrder {
+    /// Rows of tiles from the top left.
+    Scanline,
+    /// Outwards from the centre of the image, where the subject usually is.
+    #[default]
+    Spiral,
+    /// Along a Hilbert curve, so consecutive tiles are neighbours and share
+    /// more of the scene in the caches.
+    Hilbert,
+}
+
+impl TileOrder {
+    pub const NAMES: [&'static str; 3] = ["scanline", "spiral", "hilbert"];
+
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+}
+
+/// Covers a `width` by `height` image with tiles of `size` pixels in `order`.
+/// Tiles on the right and bottom edges are cut to the image.
+pub fn tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = width.div_ceil(size) as i64;
+    let rows = height.div_ceil(size) as i64;
+    let tile = |column: i64, row: i64| {
+        let x0 = column as u32 * size;
+        let y0 = row as u32 * size;
+        Tile {
+            x0,
+            y0,
+            x1: (x0 + size).min(width),
+            y1: (y0 + size).min(height),
+        }
+    };
+    let inside =
+        |&(column, row): &(i64, i64)| (0..columns).contains(&column) && (0..rows).contains(&row);
+
+    let cells: Vec<(i64, i64)> = match order {
+        TileOrder::Scanline => (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .collect(),
+        TileOrder::Spiral => spiral((columns - 1) / 2, (rows - 1) / 2)
+            .filter(inside)
+            .take((columns * rows) as usize)
+            .collect(),
+        TileOrder::Hilbert => {
+            let n = (columns.max(rows) as u64).next_power_of_two() as i64;
+            (0..n * n).map(|d| hilbert(n, d)).filter(inside).collect()
+        }
+    };
+    cells
+        .into_iter()
+        .map(|(column, row)| tile(column, row))
+        .collect()
+}
+
+/// Square spiral of cells around `(x, y)`: right, down, left and up with run
+/// lengths 1, 1, 2, 2, 3, 3, ...
+fn spiral(x: i64, y: i64) -> impl Iterator<Item = (i64, i64)> {
+    const DIRECTIONS: [(i64, i64); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let steps = (0..).flat_map(|run: usize| {
+        let (dx, dy) = DIRECTIONS[run % 4];
+        std::iter::repeat_n((dx, dy), run / 2 + 1)
+    });
+    std::iter::once((x, y)).chain(steps.scan((x, y), |cell, (dx, dy)| {
+        *cell = (cell.0 + dx, cell.1 + dy);
+        Some(*cell)
+    }))
+}
+
+/// Cell at distance `d` along the Hilbert curve filling an `n` by `n` grid,
+/// `n` a power of two.
+fn hilbert(n: i64, d: i64) -> (i64, i64) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_every_pixel_once() {
+        for order in TileOrder::NAMES.map(|name| TileOrder::from_name(name).unwrap()) {
+            for (width, height, size) in [(100, 60, 16), (7, 13, 32), (64, 64, 8), (1, 1, 1)] {
+                let mut covered = vec![0; (width * height) as usize];
+                for tile in tiles(width, height, size, order) {
+                    assert!(tile.width() <= size && tile.height() <= size);
+                    for y in tile.y0..tile.y1 {
+                        for x in tile.x0..tile.x1 {
+                            covered[(y * width + x) as usize] += 1;
+                        }
+                    }
+                }
+                assert!(
+                    covered.iter().all(|&n| n == 1),
+                    "{:?} {}x{}",
+                    order,
+                    width,
+                    height
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn orders() {
+        let spiral = tiles(50, 50, 10, TileOrder::Spiral);
+        assert_eq!(
+            spiral[0],
+            Tile {
+                x0: 20,
+                y0: 20,
+                x1: 30,
+                y1: 30
+            }
+        );
+        assert_eq!(spiral[1].x0, 30);
+
+        // Consecutive tiles of a Hilbert curve share an edge
+        let hilbert = tiles(80, 80, 10, TileOrder::Hilbert);
+        for pair in hilbert.windows(2) {
+            let distance = pair[0].x0.abs_diff(pair[1].x0) + pair[0].y0.abs_diff(pair[1].y0);
+            assert_eq!(distance, 10);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Display transform from linear film radiance to 8-bit sRGB.
+
+use crate::math::vec3::Vec3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapper {
+    /// Hard clip at 1.
+    Clamp,
+    Reinhard,
+    /// Reinhard that maps `white_point` to 1 instead of infinity.
+    ExtendedReinhard {
+        white_point: f32,
+    },
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    /// John Hable's Uncharted 2 curve.
+    Hable,
+    AgX,
+}
+
+impl ToneMapper {
+    pub const NAMES: [&'static str; 6] = [
+        "clamp",
+        "reinhard",
+        "reinhard-extended",
+        "aces",
+        "hable",
+        "agx",
+    ];
+
+    pub fn from_name(name: &str) -> Option<ToneMapper> {
+        match name {
+            "clamp" => Some(ToneMapper::Clamp),
+            "reinhard" => Some(ToneMapper::Reinhard),
+            "reinhard-extended" => Some(ToneMapper::ExtendedReinhard { white_point: 4.0 }),
+            "aces" => Some(ToneMapper::Aces),
+            "hable" => Some(ToneMapper::Hable),
+            "agx" => Some(ToneMapper::AgX),
+            _ => None,
+        }
+    }
+
+    /// The same operator mapping `white_point` to 1, `None` for the ones
+    /// without a white point.
+    pub fn with_white_point(self, white_point: f32) -> Option<ToneMapper> {
+        match self {
+            ToneMapper::ExtendedReinhard { .. } => {
+                Some(ToneMapper::ExtendedReinhard { white_point })
+            }
+            _ => None,
+        }
+    }
+
+    /// Maps linear scene radiance to linear display values in [0, 1].
+    pub fn map(&self, color: Vec3) -> Vec3 {
+        let color = Vec3::new(color.x.max(0.0), color.y.max(0.0), color.z.max(0.0));
+        match *self {
+            ToneMapper::Clamp => map_channels(color, |c| c),
+            ToneMapper::Reinhard => map_channels(color, |c| c / (1.0 + c)),
+            ToneMapper::ExtendedReinhard { white_point } => {
+                let white_squared = white_point * white_point;
+                map_channels(color, |c| c * (1.0 + c / white_squared) / (1.0 + c))
+            }
+            ToneMapper::Aces => map_channels(color * 0.6, |c| {
+                (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)
+            }),
+            ToneMapper::Hable => {
+                const WHITE: f32 = 11.2;
+                const EXPOSURE_BIAS: f32 = 2.0;
+                let white_scale = 1.0 / hable_partial(WHITE);
+                map_channels(color, |c| hable_partial(c * EXPOSURE_BIAS) * white_scale)
+            }
+            ToneMapper::AgX => agx(color),
+        }
+    }
+}
+
+fn map_channels(color: Vec3, f: impl Fn(f32) -> f32) -> Vec3 {
+    Vec3::new(
+        f(color.x).clamp(0.0, 1.0),
+        f(color.y).clamp(0.0, 1.0),
+        f(color.z).clamp(0.0, 1.0),
+    )
+}
+
+fn hable_partial(x: f32) -> f32 {
+    const A: f32 = 0.15; // Shoulder strength
+    const B: f32 = 0.50; // Linear strength
+    const C: f32 = 0.10; // Linear angle
+    const D: f32 = 0.20; // Toe strength
+    const E: f32 = 0.02; // Toe numerator
+    const F: f32 = 0.30; // Toe denominator
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+/// Multiplies `v` by the 3x3 matrix with the given columns.
+fn mul_columns(columns: &[[f32; 3]; 3], v: Vec3) -> Vec3 {
+    let [c0, c1, c2] = columns;
+    Vec3::new(
+        c0[0] * v.x + c1[0] * v.y + c2[0] * v.z,
+        c0[1] * v.x + c1[1] * v.y + c2[1] * v.z,
+        c0[2] * v.x + c1[2] * v.y + c2[2] * v.z,
+    )
+}
+
+/// Minimal AgX with the default look, after Benjamin Wrensch's polynomial fit.
+#[allow(clippy::excessive_precision)]
+fn agx(color: Vec3) -> Vec3 {
+    const INSET: [[f32; 3]; 3] = [
+        [0.842479062253094, 0.0423282422610123, 0.0423756549057051],
+        [0.0784335999999992, 0.878468636469772, 0.0784336],
+        [0.0792237451477643, 0.0791661274605434, 0.879142973793104],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.19687900512017, -0.0528968517574562, -0.0529716355144438],
+        [-0.0980208811401368, 1.15190312990417, -0.0980434501171241],
+        [-0.0990297440797205, -0.0989611768448433, 1.15107367264116],
+    ];
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let contrast = |x: f32| {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+    let encode = |c: f32| {
+        let log = c.max(1e-10).log2().clamp(MIN_EV, MAX_EV);
+        contrast((log - MIN_EV) / (MAX_EV - MIN_EV))
+    };
+
+    let v = mul_columns(&INSET, color);
+    let v = Vec3::new(encode(v.x), encode(v.y), encode(v.z));
+    let v = mul_columns(&OUTSET, v);
+    // The curve outputs display encoded values, go back to linear
+    map_channels(v, |c| c.max(0.0).powf(2.2))
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse of `linear_to_srgb`, for decoding 8-bit textures.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Triangular noise in [-1, 1] that only depends on the pixel position.
+fn dither_noise(x: u32, y: u32, channel: u32) -> f32 {
+    let hash = |mut h: u32| {
+        h ^= h >> 16;
+        h = h.wrapping_mul(0x7feb352d);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x846ca68b);
+        h ^ (h >> 16)
+    };
+    let seed = x.wrapping_mul(73856093) ^ y.wrapping_mul(19349663) ^ channel.wrapping_mul(83492791);
+    let a = hash(seed) as f32 / u32::MAX as f32;
+    let b = hash(seed ^ 0x9e3779b9) as f32 / u32::MAX as f32;
+    a + b - 1.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayTransform {
+    pub tone_mapper: ToneMapper,
+    /// Exposure adjustment in stops.
+    pub exposure: f32,
+    /// Add up to one 8-bit step of noise before quantising to hide banding.
+    pub dither: bool,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        DisplayTransform {
+            tone_mapper: ToneMapper::Clamp,
+            exposure: 0.0,
+            dither: false,
+        }
+    }
+}
+
+impl DisplayTransform {
+    /// sRGB encoded display value in [0, 1] for a linear radiance.
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let exposed = color * 2f32.powf(self.exposure);
+        let mapped = self.tone_mapper.map(exposed);
+        Vec3::new(
+            linear_to_srgb(mapped.x),
+            linear_to_srgb(mapped.y),
+            linear_to_srgb(mapped.z),
+        )
+    }
+
+    pub fn quantize(&self, color: Vec3, x: u32, y: u32) -> (u8, u8, u8) {
+        let display = self.apply(color);
+        let to_byte = |c: f32, channel: u32| {
+            let noise = if self.dither {
+                dither_noise(x, y, channel) / 255.0
+            } else {
+                0.0
+            };
+            ((c + noise).clamp(0.0, 1.0) * 255.0).round() as u8
+        };
+        (
+            to_byte(display.x, 0),
+            to_byte(display.y, 1),
+            to_byte(display.z, 2),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_approx_eq::assert_approx_eq;
+
+    use super::*;
+
+    #[test]
+    fn srgb_transfer_function() {
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert_approx_eq!(linear_to_srgb(1.0), 1.0);
+        assert_approx_eq!(linear_to_srgb(0.18), 0.4613, 1e-3);
+        for i in 0..=10 {
+            let c = i as f32 / 10.0;
+            assert_approx_eq!(srgb_to_linear(linear_to_srgb(c)), c, 1e-5);
+        }
+    }
+
+    #[test]
+    fn tone_mappers_are_bounded_and_monotonic() {
+        for name in ToneMapper::NAMES {
+            let mapper = ToneMapper::from_name(name).unwrap();
+            let mut previous = -1.0;
+            for i in 0..200 {
+                let c = mapper.map(Vec3::uniform(i as f32 * 0.1)).y;
+                assert!((0.0..=1.0).contains(&c), "{} out of range", name);
+                assert!(c >= previous - 1e-5, "{} not monotonic", name);
+                previous = c;
+            }
+            assert!(mapper.map(Vec3::ZERO).y < 0.01, "{} black level", name);
+        }
+    }
+
+    #[test]
+    fn exposure_doubles_per_stop() {
+        let transform = DisplayTransform {
+            exposure: 1.0,
+            ..Default::default()
+        };
+        let mapped = transform.apply(Vec3::uniform(0.25));
+        assert_approx_eq!(mapped.x, linear_to_srgb(0.5));
+    }
+
+    #[test]
+    fn dither_stays_within_one_step() {
+        let transform = DisplayTransform {
+            dither: true,
+            ..Default::default()
+        };
+        for x in 0..32 {
+            let (r, _, _) = transform.quantize(Vec3::uniform(0.2), x, 7);
+            let exact = linear_to_srgb(0.2) * 255.0;
+            assert!((r as f32 - exact).abs() <= 1.5);
+        }
+    }
+}
@@ -1,13 +1,69 @@
+use std::cell::Cell;
+
 use crate::math::vec3::Vec3;
-use rand::Rng;
+use rand::{Rng, RngCore};
+
+thread_local! {
+    static SAMPLE_RNG_STATE: Cell<u64> = const { Cell::new(0x853c_49e6_748f_ea9b) };
+}
+
+/// Random numbers for rendering: a PCG32 generator (O'Neill 2014) with one
+/// state per thread. The renderer reseeds it before every pixel sample with
+/// [`seed_sample_rng`], so an image only depends on the seed and not on which
+/// thread took which sample.
+#[derive(Debug, Clone, Copy)]
+pub struct SampleRng;
+
+pub fn rng() -> SampleRng {
+    SampleRng
+}
+
+/// Starts the stream of sample `sample` of pixel `(x, y)` for the render `seed`.
+pub fn seed_sample_rng(seed: u64, x: u32, y: u32, sample: u32) {
+    let pixel = (y as u64) << 32 | x as u64;
+    let state = splitmix64(splitmix64(splitmix64(seed) ^ pixel) ^ sample as u64);
+    SAMPLE_RNG_STATE.with(|s| s.set(state));
+}
 
-pub fn sample_unit_square() -> Vec3 {
-    let mut rng = rand::thread_rng();
-    Vec3::new(rng.gen::<f32>() - 0.5, rng.gen::<f32>() - 0.5, 0.0)
+pub fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
 }
 
+impl RngCore for SampleRng {
+    fn next_u32(&mut self) -> u32 {
+        const MULTIPLIER: u64 = 6364136223846793005;
+        const INCREMENT: u64 = 1442695040888963407;
+        let old = SAMPLE_RNG_STATE.with(|s| {
+            let old = s.get();
+            s.set(old.wrapping_mul(MULTIPLIER).wrapping_add(INCREMENT));
+            old
+        });
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (self.next_u32() as u64) << 32 | self.next_u32() as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            chunk.copy_from_slice(&self.next_u32().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[allow(clippy::manual_range_contains)]
 pub fn random_unit_vector() -> Vec3 {
-    let mut rng = rand::thread_rng();
+    let mut rng = rng();
     loop {
         let v = Vec3::new(
             rng.gen_range(-1.0..1.0),
@@ -30,12 +86,49 @@ pub fn random_on_hemisphere(normal: &Vec3) -> Vec3 {
     }
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
-    let mut rng = rand::thread_rng();
-    loop {
-        let v = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
-        if v.length_squared() <= 1.0 {
-            return v;
-        }
+/// Two unit vectors that form a right handed orthonormal basis with the unit vector `w`
+/// (Duff et al. 2017).
+pub fn orthonormal_basis(w: &Vec3) -> (Vec3, Vec3) {
+    let sign = 1f32.copysign(w.z);
+    let a = -1.0 / (sign + w.z);
+    let b = w.x * w.y * a;
+    let u = Vec3::new(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x);
+    let v = Vec3::new(b, sign + w.y * w.y * a, -w.y);
+    (u, v)
+}
+
+/// Cosine weighted direction on the hemisphere around +z for the uniform
+/// sample `u`.
+pub fn sample_cosine_direction(u: [f32; 2]) -> Vec3 {
+    let [r1, r2] = u;
+    let phi = 2.0 * std::f32::consts::PI * r1;
+    let r = r2.sqrt();
+    Vec3::new(phi.cos() * r, phi.sin() * r, (1.0 - r2).sqrt())
+}
+
+/// Point in the unit disk in the xy plane for the uniform sample `u`. The
+/// concentric mapping (Shirley and Chiu 1997) keeps the strata of `u` compact.
+pub fn sample_unit_disk(u: [f32; 2]) -> Vec3 {
+    let a = 2.0 * u[0] - 1.0;
+    let b = 2.0 * u[1] - 1.0;
+    if a == 0.0 && b == 0.0 {
+        return Vec3::ZERO;
     }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, std::f32::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (
+            b,
+            std::f32::consts::FRAC_PI_2 - std::f32::consts::FRAC_PI_4 * (a / b),
+        )
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+
+/// Unit vector for the uniform sample `u`, uniformly distributed over the sphere.
+pub fn sample_unit_sphere(u: [f32; 2]) -> Vec3 {
+    let z = 1.0 - 2.0 * u[0];
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * std::f32::consts::PI * u[1];
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
 }